@@ -35,6 +35,12 @@ where
     pub fn into_inner(self) -> BS {
         self.base
     }
+
+    /// Drops all the buffered writes, e.g., after reverting the execution which produced them.
+    /// Blocks already copied to the base store by [`Buffered::flush`] are kept there.
+    pub fn discard(&self) {
+        self.write.borrow_mut().clear();
+    }
 }
 
 impl<BS> Buffered for BufferedBlockstore<BS>
@@ -112,7 +118,7 @@ fn cbor_read_header_buf<B: Read>(br: &mut B, scratch: &mut [u8]) -> anyhow::Resu
 /// Given a CBOR serialized IPLD buffer, read through all of it and return all the Links.
 /// This function is useful because it is quite a bit more fast than doing this recursively on a
 /// deserialized IPLD object.
-fn scan_for_links<B: Read + Seek, F>(buf: &mut B, mut callback: F) -> Result<()>
+pub(crate) fn scan_for_links<B: Read + Seek, F>(buf: &mut B, mut callback: F) -> Result<()>
 where
     F: FnMut(Cid) -> anyhow::Result<()>,
 {
@@ -125,7 +131,9 @@ where
             0 | 1 | 7 => {}
             // MajByteString, MajTextString
             2 | 3 => {
-                buf.seek(std::io::SeekFrom::Current(extra as i64))?;
+                let len =
+                    i64::try_from(extra).map_err(|_| anyhow!("string in cbor input too long"))?;
+                buf.seek(std::io::SeekFrom::Current(len))?;
             }
             // MajTag
             6 => {
@@ -136,6 +144,9 @@ where
                     if maj != 2 {
                         return Err(anyhow!("expected cbor type byte string in input"));
                     }
+                    if extra == 0 {
+                        return Err(anyhow!("empty cid in cbor input"));
+                    }
                     if extra > 100 {
                         return Err(anyhow!("string in cbor input too long"));
                     }
@@ -143,16 +154,19 @@ where
                     let c = Cid::try_from(&scratch[1..extra])?;
                     callback(c)?;
                 } else {
-                    remaining += 1;
+                    remaining = checked_add_items(remaining, 1)?;
                 }
             }
             // MajArray
             4 => {
-                remaining += extra;
+                remaining = checked_add_items(remaining, extra)?;
             }
             // MajMap
             5 => {
-                remaining += extra * 2;
+                let items = extra
+                    .checked_mul(2)
+                    .ok_or_else(|| anyhow!("cbor map too large"))?;
+                remaining = checked_add_items(remaining, items)?;
             }
             _ => {
                 return Err(anyhow!("unhandled cbor type: {}", maj));
@@ -163,6 +177,14 @@ where
     Ok(())
 }
 
+/// Adds the number of items of a cbor array or map to the number of items left to scan, failing on
+/// overflow rather than panicking, as the input may be untrusted.
+fn checked_add_items(remaining: usize, items: usize) -> Result<usize> {
+    remaining
+        .checked_add(items)
+        .ok_or_else(|| anyhow!("too many items in cbor input"))
+}
+
 /// Copies the IPLD DAG under `root` from the cache to the base store.
 fn copy_rec<'a>(
     cache: &'a HashMap<Cid, Vec<u8>>,
@@ -292,6 +314,34 @@ mod tests {
         assert_eq!(mem.get_cbor::<u8>(&cid).unwrap(), Some(8));
     }
 
+    #[test]
+    fn discard() {
+        let mem = MemoryBlockstore::default();
+        let buf_store = BufferedBlockstore::new(&mem);
+
+        let kept = buf_store.put_cbor(&8u8, Code::Blake2b256).unwrap();
+        buf_store.flush(&kept).unwrap();
+        let discarded = buf_store.put_cbor(&9u8, Code::Blake2b256).unwrap();
+
+        buf_store.discard();
+        assert_eq!(buf_store.get_cbor::<u8>(&kept).unwrap(), Some(8));
+        assert_eq!(buf_store.get_cbor::<u8>(&discarded).unwrap(), None);
+        assert_eq!(mem.get_cbor::<u8>(&discarded).unwrap(), None);
+    }
+
+    #[test]
+    fn scan_for_links_rejects_malformed_input() {
+        let scan = |data: &[u8]| scan_for_links(&mut Cursor::new(data), |_| Ok(()));
+
+        // A CID tag wrapping an empty byte string.
+        assert!(scan(&[0xd8, 0x2a, 0x40]).is_err());
+        // An array, then a map, whose lengths overflow the number of items left to scan.
+        assert!(scan(&[0x82, 0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(scan(&[0xbb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        // A byte string longer than the input.
+        assert!(scan(&[0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
     #[test]
     fn buffered_store_with_links() {
         let mem = MemoryBlockstore::default();
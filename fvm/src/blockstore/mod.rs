@@ -3,4 +3,5 @@
 //! Private blockstores for use in the FVM.
 
 mod buffered;
+pub(crate) use buffered::scan_for_links;
 pub use buffered::BufferedBlockstore;
@@ -356,6 +356,12 @@ where
         self.events.append_event(evt)
     }
 
+    fn record_block_write(&mut self, actor: ActorID, size: u64) {
+        if self.machine.context().tracing {
+            self.trace(ExecutionEvent::BlockWrite { actor, size });
+        }
+    }
+
     // Helper for creating actors. This really doesn't belong on this trait.
     fn invocation_count(&self) -> u64 {
         self.invocation_count
@@ -505,7 +511,15 @@ where
         // Store the parametrs, and initialize the block registry for the target actor.
         let mut block_registry = BlockRegistry::new();
         let params_id = if let Some(blk) = params {
-            block_registry.put(blk)?
+            // Reachability is only enforced for user actors, so only their parameters are scanned
+            // for links.
+            if self.builtin_actors().id_by_code(&state.code) == 0 {
+                let _ =
+                    self.charge_gas(self.price_list().on_block_scan_links(blk.size() as usize))?;
+                block_registry.put_reachable(blk)?
+            } else {
+                block_registry.put(blk)?
+            }
         } else {
             NO_DATA_BLOCK_ID
         };
@@ -156,6 +156,10 @@ pub trait CallManager: 'static {
 
     /// Appends an event to the event accumulator.
     fn append_event(&mut self, evt: StampedEvent);
+
+    /// Records that `actor` persisted a block of `size` bytes to the store, in the execution trace
+    /// if tracing is enabled.
+    fn record_block_write(&mut self, actor: ActorID, size: u64);
 }
 
 /// The result of a method invocation.
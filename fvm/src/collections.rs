@@ -0,0 +1,311 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Collections used by the FVM, which may also be useful to embedders.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A map with an "undo" history. All changes to this map are recorded in the history and can be
+/// "reverted" by calling `rollback`. Specifically:
+///
+/// 1. The user can call `history_len` to record the current history length.
+/// 2. The user can _later_ call `rollback(previous_length)` to rollback to the state in step 1.
+#[derive(Clone)]
+pub struct HistoryMap<K, V> {
+    map: HashMap<K, V>,
+    history: Vec<(K, Option<V>)>,
+}
+
+impl<K, V> Default for HistoryMap<K, V> {
+    fn default() -> Self {
+        Self {
+            map: Default::default(),
+            history: Default::default(),
+        }
+    }
+}
+
+impl<K, V> HistoryMap<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Insert a k/v pair into the map, recording the previous value in the history.
+    pub fn insert(&mut self, k: K, v: V) {
+        self.history.push((k.clone(), self.map.insert(k, v)))
+    }
+
+    /// Lookup a value in the map given a key.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.get(k)
+    }
+
+    /// Lookup a value in the map given a key, for modification in place. Changes made through the
+    /// returned reference are _not_ recorded in the history.
+    pub(crate) fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.get_mut(k)
+    }
+
+    /// Looks up a value in the map given a key, or initializes the entry with the provided
+    /// function. Any modifications to the map are recorded in the history.
+    pub fn get_or_try_insert_with<F, E>(&mut self, k: K, f: F) -> std::result::Result<&V, E>
+    where
+        F: FnOnce() -> std::result::Result<V, E>,
+    {
+        match self.map.entry(k) {
+            Entry::Occupied(e) => Ok(e.into_mut()),
+            Entry::Vacant(e) => {
+                let v = f()?;
+                self.history.push((e.key().clone(), None));
+                Ok(e.insert(v))
+            }
+        }
+    }
+
+    /// Rollback to the specified point in history, undoing all changes made since. Rolling back to
+    /// a point at or past the current history length is a no-op.
+    pub fn rollback(&mut self, height: usize) {
+        if self.history.len() <= height {
+            return;
+        }
+        for (k, v) in self.history.drain(height..).rev() {
+            match v {
+                Some(v) => self.map.insert(k, v),
+                None => self.map.remove(&k),
+            };
+        }
+    }
+
+    /// Like `rollback`, but also returns the undone changes, most recent first. Each entry holds a
+    /// key along with the value restored for it, or `None` if the key was removed.
+    pub fn rollback_collecting(&mut self, height: usize) -> Vec<(K, Option<V>)>
+    where
+        V: Clone,
+    {
+        if self.history.len() <= height {
+            return Vec::new();
+        }
+        let mut reverted = Vec::with_capacity(self.history.len() - height);
+        for (k, v) in self.history.drain(height..).rev() {
+            match &v {
+                Some(v) => self.map.insert(k.clone(), v.clone()),
+                None => self.map.remove(&k),
+            };
+            reverted.push((k, v));
+        }
+        reverted
+    }
+
+    /// Returns the current history length, i.e., the point in history to pass to `rollback` to
+    /// revert all later changes.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Discards all undo history, making the current state of the map permanent.
+    pub fn discard_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Removes a key from the map _without_ recording it in the history. This must only be used
+    /// when the history is empty, as a later rollback could otherwise restore a stale value.
+    pub(crate) fn remove_unrecorded<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        debug_assert!(self.history.is_empty());
+        self.map.remove(k)
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterate over the current map.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter()
+    }
+
+    /// Iterate mutably over the current map.
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.map.iter_mut()
+    }
+}
+
+/// Tracks when the keys of a [`HistoryMap`] were last used, to evict the least recently used
+/// entries once the map grows past a given capacity.
+#[derive(Clone)]
+pub(crate) struct LruTracker<K> {
+    capacity: usize,
+    clock: u64,
+    last_used: HashMap<K, u64>,
+}
+
+impl<K> LruTracker<K>
+where
+    K: Hash + Eq + Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            last_used: HashMap::new(),
+        }
+    }
+
+    /// Records a use of `k`.
+    pub fn touch(&mut self, k: &K) {
+        self.clock += 1;
+        match self.last_used.get_mut(k) {
+            Some(last_used) => *last_used = self.clock,
+            None => {
+                self.last_used.insert(k.clone(), self.clock);
+            }
+        }
+    }
+
+    /// Evicts the least recently used entries of `map` until it's back to capacity, skipping the
+    /// entries for which `pinned` returns true. Keys which were never used are evicted first.
+    ///
+    /// Evictions aren't recorded in the history, so the history of `map` must be empty.
+    pub fn evict<V>(&mut self, map: &mut HistoryMap<K, V>, pinned: impl Fn(&V) -> bool) {
+        if map.len() > self.capacity {
+            let mut candidates: Vec<_> = map
+                .iter()
+                .filter(|(_, v)| !pinned(v))
+                .map(|(k, _)| (self.last_used.get(k).copied().unwrap_or(0), k.clone()))
+                .collect();
+            candidates.sort_unstable_by_key(|(last_used, _)| *last_used);
+            let excess = map.len() - self.capacity;
+            for (_, k) in candidates.into_iter().take(excess) {
+                map.remove_unrecorded(&k);
+                self.last_used.remove(&k);
+            }
+        }
+        // Forget the keys removed from the map by rollbacks.
+        if self.last_used.len() > map.len() {
+            self.last_used.retain(|k, _| map.get(k).is_some());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HistoryMap, LruTracker};
+
+    #[test]
+    fn history_map() {
+        let mut map = HistoryMap::<i32, &'static str>::default();
+
+        // Basic history tests.
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.history_len(), 0);
+        map.insert(1, "foo");
+        assert_eq!(map.history_len(), 1);
+        assert_eq!(map.get(&1), Some(&"foo"));
+        map.insert(2, "bar");
+        assert_eq!(map.history_len(), 2);
+        assert_eq!(map.get(&1), Some(&"foo"));
+        assert_eq!(map.get(&2), Some(&"bar"));
+        map.insert(1, "baz");
+        assert_eq!(map.history_len(), 3);
+        assert_eq!(map.get(&1), Some(&"baz"));
+        map.rollback(4); // doesn't panic.
+        assert_eq!(map.history_len(), 3);
+        map.rollback(3); // no-op.
+        assert_eq!(map.history_len(), 3);
+        assert_eq!(map.get(&1), Some(&"baz"));
+        map.rollback(2); // undoes the insert of 1 -> baz
+        assert_eq!(map.history_len(), 2);
+        assert_eq!(map.get(&1), Some(&"foo"));
+        assert_eq!(map.get(&2), Some(&"bar"));
+        map.rollback(1); // undoes the insert of 2 -> bar
+        assert_eq!(map.history_len(), 1);
+        assert_eq!(map.get(&1), Some(&"foo"));
+        assert_eq!(map.get(&2), None);
+        map.rollback(0); // empties the map
+        assert_eq!(map.history_len(), 0);
+        assert_eq!(map.get(&1), None);
+
+        // Inserts
+        assert_eq!(
+            map.get_or_try_insert_with(1, || -> Result<_, ()> { Ok("foo") })
+                .unwrap(),
+            &"foo",
+        );
+        assert_eq!(map.get(&1), Some(&"foo"));
+        assert_eq!(map.history_len(), 1);
+
+        // Doing it again changes nothing.
+        assert_eq!(
+            map.get_or_try_insert_with(1, || -> Result<_, ()> { panic!() })
+                .unwrap(),
+            &"foo",
+        );
+        assert_eq!(map.history_len(), 1);
+
+        // Bubbles the error and changes nothing.
+        assert_eq!(
+            map.get_or_try_insert_with(2, || { Err("err") })
+                .unwrap_err(),
+            "err",
+        );
+        assert_eq!(map.get(&2), None);
+        assert_eq!(map.history_len(), 1);
+
+        // Collect the undone changes.
+        map.insert(1, "bar");
+        map.insert(2, "baz");
+        assert_eq!(map.rollback_collecting(5), vec![]);
+        assert_eq!(
+            map.rollback_collecting(1),
+            vec![(2, None), (1, Some("foo"))]
+        );
+        assert_eq!(map.get(&1), Some(&"foo"));
+        assert_eq!(map.get(&2), None);
+        assert_eq!(map.history_len(), 1);
+
+        // Undo the first insertion.
+        map.rollback(0); // empties the map
+        assert_eq!(map.history_len(), 0);
+        assert_eq!(map.get(&1), None);
+    }
+
+    #[test]
+    fn lru_tracker() {
+        let mut map = HistoryMap::<i32, bool>::default();
+        let mut lru = LruTracker::new(2);
+        for k in 1..=4 {
+            map.insert(k, k == 1);
+            lru.touch(&k);
+        }
+        lru.touch(&2);
+        map.discard_history();
+
+        // 1 is pinned, and 2 was used more recently than 3 and 4.
+        lru.evict(&mut map, |&pinned| pinned);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&1), Some(&true));
+        assert_eq!(map.get(&2), Some(&false));
+
+        // Nothing to evict at capacity.
+        lru.evict(&mut map, |_| false);
+        assert_eq!(map.len(), 2);
+    }
+}
@@ -11,12 +11,14 @@ use num_traits::Zero;
 pub use self::charge::GasCharge;
 pub(crate) use self::outputs::GasOutputs;
 pub use self::price_list::{price_list_by_network_version, PriceList, WasmGasPrices};
+pub use self::schedule::GasSchedule;
 pub use self::timer::{GasInstant, GasTimer};
 use crate::kernel::{ExecutionError, Result};
 
 mod charge;
 mod outputs;
 mod price_list;
+mod schedule;
 mod timer;
 
 pub const MILLIGAS_PRECISION: i64 = 1000;
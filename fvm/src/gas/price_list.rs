@@ -241,6 +241,13 @@ lazy_static! {
 
         block_persist_compute: Gas::new(172000),
 
+        // TODO(#1279): benchmark. Scanning a block for links is assumed to cost about as much as
+        // allocating it.
+        block_scan_links: ScalingCost {
+            flat: Gas::zero(),
+            scale: Gas::new(2),
+        },
+
         syscall_cost: Gas::new(14000),
 
         // TODO(#1279)
@@ -440,6 +447,9 @@ pub struct PriceList {
     /// Gas cost to cover the cost of flushing a block.
     pub(crate) block_persist_compute: Gas,
 
+    /// Gas cost for scanning a block for the CIDs it links to, to enforce reachability.
+    pub(crate) block_scan_links: ScalingCost,
+
     /// General gas cost for performing a syscall, accounting for the overhead thereof.
     pub(crate) syscall_cost: Gas,
 
@@ -775,6 +785,16 @@ impl PriceList {
         GasCharge::new("OnBlockLink", initial_compute, deferred_compute + storage)
     }
 
+    /// Returns the gas required for scanning an object for the CIDs it links to.
+    #[inline]
+    pub fn on_block_scan_links(&self, data_size: usize) -> GasCharge {
+        GasCharge::new(
+            "OnBlockScanLinks",
+            self.block_scan_links.apply(data_size),
+            Zero::zero(),
+        )
+    }
+
     /// Returns the gas required for storing an object.
     #[inline]
     pub fn on_block_stat(&self) -> GasCharge {
@@ -0,0 +1,65 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::HashMap;
+
+use super::Gas;
+
+/// Per-operation scaling applied to named gas charges (e.g., `wasm_exec`, or the charges made by
+/// actors through the `gas::charge` syscall).
+///
+/// Each entry maps a charge name to a multiplier, expressed in percent, applied to the compute gas
+/// of that charge. Charges without an entry are left untouched.
+///
+/// This is intended for experimenting with alternate gas models. Any schedule other than
+/// [`GasSchedule::mainnet`] is consensus-breaking.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GasSchedule {
+    scales: HashMap<String, u32>,
+}
+
+impl GasSchedule {
+    /// The schedule used on mainnet, which doesn't scale any charge.
+    pub fn mainnet() -> Self {
+        Self::default()
+    }
+
+    /// Scale the compute gas of charges named `name` by `percent` percent.
+    pub fn with_scale(mut self, name: impl Into<String>, percent: u32) -> Self {
+        self.scales.insert(name.into(), percent);
+        self
+    }
+
+    /// Returns the compute gas to charge for the operation `name`, given its unscaled cost.
+    pub fn scale(&self, name: &str, compute: Gas) -> Gas {
+        match self.scales.get(name) {
+            Some(&percent) => Gas::from_milligas(
+                (compute.as_milligas() as i128 * percent as i128 / 100)
+                    .clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            ),
+            None => compute,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_traits::Zero;
+
+    use super::*;
+
+    #[test]
+    fn scale() {
+        let schedule = GasSchedule::mainnet()
+            .with_scale("wasm_exec", 250)
+            .with_scale("free", 0);
+
+        assert_eq!(schedule.scale("wasm_exec", Gas::new(10)), Gas::new(25));
+        assert_eq!(schedule.scale("free", Gas::new(10)), Gas::zero());
+        assert_eq!(schedule.scale("other", Gas::new(10)), Gas::new(10));
+        assert_eq!(
+            GasSchedule::mainnet().scale("wasm_exec", Gas::new(10)),
+            Gas::new(10)
+        );
+    }
+}
@@ -91,15 +91,32 @@ impl State {
     where
         B: Blockstore,
     {
-        let id = self.next_id;
-        self.next_id += 1;
+        let ids = self.map_addresses_to_new_ids(store, std::slice::from_ref(addr))?;
+        Ok(ids[0])
+    }
 
+    /// Allocates a new ID address for each of the argument addresses, in order, and stores the
+    /// mappings, flushing the address map only once. Returns the newly-allocated addresses.
+    pub fn map_addresses_to_new_ids<B>(
+        &mut self,
+        store: B,
+        addrs: &[Address],
+    ) -> Result<Vec<ActorID>>
+    where
+        B: Blockstore,
+    {
         let mut map = Hamt::<B, _>::load_with_bit_width(&self.address_map, store, HAMT_BIT_WIDTH)
             .or_fatal()?;
-        map.set(addr.to_bytes().into(), id).or_fatal()?;
+        let mut ids = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let id = self.next_id;
+            self.next_id += 1;
+            map.set(addr.to_bytes().into(), id).or_fatal()?;
+            ids.push(id);
+        }
         self.address_map = map.flush().or_fatal()?;
 
-        Ok(id)
+        Ok(ids)
     }
 
     /// ResolveAddress resolves an address to an ID-address, if possible.
@@ -1,18 +1,25 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::HashSet;
 use std::convert::TryInto;
+use std::io::Cursor;
 use std::rc::Rc;
 
+use cid::Cid;
 use fvm_ipld_encoding::DAG_CBOR;
-use fvm_shared::IPLD_RAW;
+use fvm_shared::{IDENTITY_HASH, IPLD_RAW};
 use thiserror::Error;
 
 use super::{ExecutionError, SyscallError};
+use crate::blockstore::scan_for_links;
 use crate::syscall_error;
 
 #[derive(Default)]
 pub struct BlockRegistry {
     blocks: Vec<Block>,
+    /// CIDs the actor may legitimately link to: blocks it opened or linked, and the CIDs linked
+    /// from the blocks it opened or received.
+    reachable: HashSet<Cid>,
 }
 
 /// Blocks in the block registry are addressed by an ordinal, starting from 1 (`FIRST_ID`).
@@ -73,6 +80,18 @@ impl Block {
             size: self.size(),
         }
     }
+
+    /// Returns the CIDs linked from this block. Only DAG-CBOR blocks can contain links.
+    pub fn links(&self) -> anyhow::Result<Vec<Cid>> {
+        let mut links = Vec::new();
+        if self.codec == DAG_CBOR && !self.data.is_empty() {
+            scan_for_links(&mut Cursor::new(self.data()), |link| {
+                links.push(link);
+                Ok(())
+            })?;
+        }
+        Ok(links)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -116,7 +135,7 @@ impl From<InvalidHandleError> for ExecutionError {
 
 impl BlockRegistry {
     pub(crate) fn new() -> Self {
-        Self { blocks: Vec::new() }
+        Self::default()
     }
 }
 
@@ -136,6 +155,31 @@ impl BlockRegistry {
         Ok(id)
     }
 
+    /// Adds a block the actor received or loaded from the state to the registry, marking all
+    /// the CIDs it links to as reachable.
+    ///
+    /// Blocks which fail to parse don't make anything reachable.
+    pub fn put_reachable(&mut self, block: Block) -> Result<BlockId, BlockPutError> {
+        if let Ok(links) = block.links() {
+            self.reachable.extend(links);
+        }
+        self.put(block)
+    }
+
+    /// Marks a CID as reachable.
+    pub fn mark_reachable(&mut self, cid: &Cid) {
+        self.reachable.insert(*cid);
+    }
+
+    /// Returns true if the actor may link to the given CID. Inline (identity-hashed) CIDs are
+    /// always reachable, as are CIDs which don't refer to DAG-CBOR or raw blocks in the store
+    /// (e.g., sector commitments).
+    pub fn is_reachable(&self, cid: &Cid) -> bool {
+        !matches!(cid.codec(), DAG_CBOR | IPLD_RAW)
+            || cid.hash().code() == IDENTITY_HASH
+            || self.reachable.contains(cid)
+    }
+
     /// Gets the block associated with a block handle.
     pub fn get(&self, id: BlockId) -> Result<&Block, InvalidHandleError> {
         if id < FIRST_ID {
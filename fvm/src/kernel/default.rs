@@ -59,10 +59,12 @@ pub struct DefaultKernel<C> {
     /// temporarily "give" the call manager to the other kernel before re-attaching it.
     call_manager: C,
     /// Tracks block data and organizes it through index handles so it can be
-    /// referred to.
-    ///
-    /// This does not yet reason about reachability.
+    /// referred to, along with the set of CIDs reachable by the actor.
     blocks: BlockRegistry,
+    /// Number of bytes written to the store through `block_link`.
+    bytes_written: u64,
+    /// Whether reachability is enforced for this actor, looked up on first use.
+    enforces_reachability: Option<bool>,
 }
 
 // Even though all children traits are implemented, Rust needs to know that the
@@ -95,6 +97,8 @@ where
             actor_id,
             method,
             value_received,
+            bytes_written: 0,
+            enforces_reachability: None,
         }
     }
 
@@ -133,6 +137,91 @@ where
                 }
             })
     }
+
+    /// Returns the number of bytes written to the store by this actor invocation, i.e., the total
+    /// size of the blocks it linked. Blocks created but never linked aren't persisted, so they
+    /// aren't counted. Each write is also reported through [`CallManager::record_block_write`],
+    /// so it shows up in the execution trace.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns true if reachability is enforced for this actor, i.e., if it's a user actor.
+    /// Built-in actors are trusted, and enforcing it for them would change the outcome of messages
+    /// already on chain.
+    ///
+    /// An actor's code never changes, so this is only looked up once per invocation.
+    fn enforces_reachability(&mut self) -> Result<bool> {
+        if let Some(enforces) = self.enforces_reachability {
+            return Ok(enforces);
+        }
+        let builtin_actors = self.call_manager.machine().builtin_actors();
+        let enforces = self
+            .get_self()?
+            .map_or(false, |a| builtin_actors.id_by_code(&a.code) == 0);
+        self.enforces_reachability = Some(enforces);
+        Ok(enforces)
+    }
+
+    /// Returns true if the actor may link to the given CID because it's in the reachable set of
+    /// the block registry, or it's the code CID of a built-in actor. The actor's current state
+    /// root is reachable too, but callers check it separately so they look it up at most once.
+    fn is_known_reachable(&self, cid: &Cid) -> bool {
+        self.blocks.is_reachable(cid)
+            || self.call_manager.machine().builtin_actors().id_by_code(cid) != 0
+    }
+
+    /// Returns the actor's current state root, or `None` if it has been deleted.
+    fn current_root(&self) -> Result<Option<Cid>> {
+        Ok(self.get_self()?.map(|a| a.state))
+    }
+
+    /// Charges for scanning a block the actor opened or received for the CIDs it links to, if
+    /// reachability is enforced for this actor. Returns whether it is, i.e., whether the block
+    /// should be added with [`BlockRegistry::put_reachable`].
+    fn charge_scan_links(&mut self, block: &Block) -> Result<bool> {
+        if !self.enforces_reachability()? {
+            return Ok(false);
+        }
+        let _ = self.call_manager.charge_gas(
+            self.call_manager
+                .price_list()
+                .on_block_scan_links(block.size() as usize),
+        )?;
+        Ok(true)
+    }
+
+    /// Rejects randomness requests for epochs after the current one, which the chain can't
+    /// supply yet.
+    fn check_randomness_epoch(&self, rand_epoch: ChainEpoch) -> Result<()> {
+        if rand_epoch > self.call_manager.context().epoch {
+            Err(syscall_error!(IllegalArgument; "epoch {} is in the future", rand_epoch).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sends a message in read-only mode. Any attempt by the callee (or anything it calls) to
+    /// mutate state fails with a `ReadOnly` syscall error.
+    ///
+    /// This is equivalent to calling [`SendOps::send`] with [`SendFlags::READ_ONLY`].
+    pub fn send_readonly(
+        &mut self,
+        recipient: &Address,
+        method: MethodNum,
+        params_id: BlockId,
+        value: &TokenAmount,
+        gas_limit: Option<Gas>,
+    ) -> Result<SendResult> {
+        self.send(
+            recipient,
+            method,
+            params_id,
+            value,
+            gas_limit,
+            SendFlags::READ_ONLY,
+        )
+    }
 }
 
 impl<C> SelfOps for DefaultKernel<C>
@@ -159,6 +248,13 @@ where
             .call_manager
             .charge_gas(self.call_manager.price_list().on_set_root())?;
 
+        if self.enforces_reachability()?
+            && !self.is_known_reachable(&new)
+            && self.current_root()? != Some(new)
+        {
+            return Err(syscall_error!(NotFound; "new state root {} is not reachable", new).into());
+        }
+
         t.record(self.mutate_self(|actor_state| {
             actor_state.state = new;
             Ok(())
@@ -243,7 +339,12 @@ where
         )?;
 
         let stat = block.stat();
-        let id = self.blocks.put(block)?;
+        let id = if self.charge_scan_links(&block)? {
+            self.blocks.mark_reachable(cid);
+            self.blocks.put_reachable(block)?
+        } else {
+            self.blocks.put(block)?
+        };
         t.stop_with(start);
         Ok((id, stat))
     }
@@ -264,6 +365,7 @@ where
         if hash_fun != BLAKE2B_256 || hash_len != 32 {
             return Err(syscall_error!(IllegalCid; "cids must be 32-byte blake2b").into());
         }
+        let enforces_reachability = self.enforces_reachability()?;
         let start = GasTimer::start();
         let block = self.blocks.get(id)?;
         let code = SupportedHashes::try_from(hash_fun)
@@ -280,13 +382,42 @@ where
             return Err(syscall_error!(IllegalCid; "invalid hash length: {}", hash_len).into());
         }
         let k = Cid::new_v1(block.codec(), hash.truncate(hash_len as u8));
-        // TODO(M2): Add the block to the reachable set.
+
+        if enforces_reachability {
+            let _ = self.call_manager.charge_gas(
+                self.call_manager
+                    .price_list()
+                    .on_block_scan_links(block.size() as usize),
+            )?;
+            let links = block
+                .links()
+                .map_err(|e| syscall_error!(Serialization; "failed to parse block links: {}", e))?;
+            let mut unknown = links
+                .iter()
+                .filter(|link| !self.is_known_reachable(link))
+                .peekable();
+            if unknown.peek().is_some() {
+                let root = self.current_root()?;
+                if let Some(link) = unknown.find(|link| Some(**link) != root) {
+                    return Err(
+                        syscall_error!(NotFound; "block links to unreachable CID {}", link).into(),
+                    );
+                }
+            }
+        }
+
         self.call_manager
             .blockstore()
             .put_keyed(&k, block.data())
             // TODO: This is really "super fatal". It means we failed to store state, and should
             // probably abort the entire block.
             .or_fatal()?;
+        self.bytes_written += block.size() as u64;
+        self.call_manager
+            .record_block_write(self.actor_id, block.size() as u64);
+        if enforces_reachability {
+            self.blocks.mark_reachable(&k);
+        }
         t.stop_with(start);
         Ok(k)
     }
@@ -333,6 +464,10 @@ where
 
         t.record(Ok(self.blocks.stat(id)?))
     }
+
+    fn block_exists(&self, id: BlockId) -> bool {
+        self.blocks.get(id).is_ok()
+    }
 }
 
 impl<C> MessageOps for DefaultKernel<C>
@@ -412,11 +547,13 @@ where
                 value: Some(blk),
             } => {
                 let block_stat = blk.stat();
-                let block_id = self
-                    .blocks
-                    .put(blk)
-                    .or_fatal()
-                    .context("failed to store a valid return value")?;
+                let block_id = if self.charge_scan_links(&blk)? {
+                    self.blocks.put_reachable(blk)
+                } else {
+                    self.blocks.put(blk)
+                }
+                .or_fatal()
+                .context("failed to store a valid return value")?;
                 SendResult {
                     block_id,
                     block_stat,
@@ -443,7 +580,11 @@ where
         // From v15 and onwards, Filecoin mainnet was fixed to use a static circ supply per epoch.
         // The value reported to the FVM from clients is now the static value,
         // the FVM simply reports that value to actors.
-        Ok(self.call_manager.context().circ_supply.clone())
+        let context = self.call_manager.context();
+        Ok(match &context.circ_supply_fn {
+            Some(f) => f.at(context.epoch),
+            None => context.circ_supply.clone(),
+        })
     }
 }
 
@@ -668,6 +809,11 @@ where
     }
 
     fn charge_gas(&self, name: &str, compute: Gas) -> Result<GasTimer> {
+        let compute = self
+            .call_manager
+            .context()
+            .gas_schedule
+            .scale(name, compute);
         self.call_manager.gas_tracker().charge_gas(name, compute)
     }
 
@@ -744,6 +890,7 @@ where
                 .price_list()
                 .on_get_randomness(entropy.len()),
         )?;
+        self.check_randomness_epoch(rand_epoch)?;
 
         // TODO(M2): Check error code
         // Specifically, lookback length?
@@ -766,6 +913,7 @@ where
                 .price_list()
                 .on_get_randomness(entropy.len()),
         )?;
+        self.check_randomness_epoch(rand_epoch)?;
 
         // TODO(M2): Check error code
         // Specifically, lookback length?
@@ -131,7 +131,8 @@ pub trait IpldBlockOps {
     ///
     /// This is the only way to add a new block to the "reachable" set.
     ///
-    /// This method will fail if the block handle is invalid.
+    /// This method will fail if the block handle is invalid, or if a user actor's block links to
+    /// unreachable blocks.
     fn block_link(&mut self, id: BlockId, hash_fun: u64, hash_len: u32) -> Result<Cid>;
 
     /// Read data from a block.
@@ -143,6 +144,11 @@ pub trait IpldBlockOps {
     ///
     /// This method will fail if the block handle is invalid.
     fn block_stat(&self, id: BlockId) -> Result<BlockStat>;
+
+    /// Returns true if the block handle is valid.
+    ///
+    /// Unlike the other block operations, this doesn't charge any gas.
+    fn block_exists(&self, id: BlockId) -> bool;
 }
 
 /// Actor state access and manipulation.
@@ -153,7 +159,7 @@ pub trait SelfOps: IpldBlockOps {
 
     /// Update the state-root.
     ///
-    /// This method will fail if the new state-root isn't reachable.
+    /// This method will fail if the new state-root of a user actor isn't reachable.
     fn set_root(&mut self, root: Cid) -> Result<()>;
 
     /// The balance of the receiver.
@@ -12,6 +12,7 @@ pub use kernel::default::DefaultKernel;
 pub use kernel::Kernel;
 
 pub mod call_manager;
+pub mod collections;
 pub mod engine;
 pub mod executor;
 pub mod externs;
@@ -166,9 +166,7 @@ where
     /// buffer into the underlying blockstore (the blockstore with which the machine was
     /// constructed).
     fn flush(&mut self) -> Result<Cid> {
-        let root = self.state_tree_mut().flush()?;
-        self.blockstore().flush(&root).or_fatal()?;
-        Ok(root)
+        self.state_tree_mut().flush_and_commit()
     }
 
     /// Creates an uninitialized actor.
@@ -1,30 +1,151 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use std::collections::HashMap;
+use std::fmt;
 
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
 use cid::Cid;
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::CborStore;
 
-const ACCOUNT_ACTOR_NAME: &str = "account";
-const INIT_ACTOR_NAME: &str = "init";
 const SYSTEM_ACTOR_NAME: &str = "system";
+const INIT_ACTOR_NAME: &str = "init";
+const CRON_ACTOR_NAME: &str = "cron";
+const ACCOUNT_ACTOR_NAME: &str = "account";
+const STORAGEPOWER_ACTOR_NAME: &str = "storagepower";
+const STORAGEMINER_ACTOR_NAME: &str = "storageminer";
+const STORAGEMARKET_ACTOR_NAME: &str = "storagemarket";
+const PAYMENTCHANNEL_ACTOR_NAME: &str = "paymentchannel";
+const MULTISIG_ACTOR_NAME: &str = "multisig";
+const REWARD_ACTOR_NAME: &str = "reward";
+const VERIFIEDREGISTRY_ACTOR_NAME: &str = "verifiedregistry";
+const DATACAP_ACTOR_NAME: &str = "datacap";
 const PLACEHOLDER_ACTOR_NAME: &str = "placeholder";
+const EVM_ACTOR_NAME: &str = "evm";
 const EAM_ACTOR_NAME: &str = "eam";
 const ETHACCOUNT_ACTOR_NAME: &str = "ethaccount";
 
+/// The builtin actors known to the FVM, identified in the manifest by their names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinActor {
+    System,
+    Init,
+    Cron,
+    Account,
+    StoragePower,
+    StorageMiner,
+    StorageMarket,
+    PaymentChannel,
+    Multisig,
+    Reward,
+    VerifiedRegistry,
+    DataCap,
+    Placeholder,
+    Evm,
+    Eam,
+    EthAccount,
+}
+
+impl BuiltinActor {
+    /// All the builtin actors known to the FVM.
+    pub const ALL: &'static [BuiltinActor] = &[
+        BuiltinActor::System,
+        BuiltinActor::Init,
+        BuiltinActor::Cron,
+        BuiltinActor::Account,
+        BuiltinActor::StoragePower,
+        BuiltinActor::StorageMiner,
+        BuiltinActor::StorageMarket,
+        BuiltinActor::PaymentChannel,
+        BuiltinActor::Multisig,
+        BuiltinActor::Reward,
+        BuiltinActor::VerifiedRegistry,
+        BuiltinActor::DataCap,
+        BuiltinActor::Placeholder,
+        BuiltinActor::Evm,
+        BuiltinActor::Eam,
+        BuiltinActor::EthAccount,
+    ];
+
+    /// Returns the name of the actor in the manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            BuiltinActor::System => SYSTEM_ACTOR_NAME,
+            BuiltinActor::Init => INIT_ACTOR_NAME,
+            BuiltinActor::Cron => CRON_ACTOR_NAME,
+            BuiltinActor::Account => ACCOUNT_ACTOR_NAME,
+            BuiltinActor::StoragePower => STORAGEPOWER_ACTOR_NAME,
+            BuiltinActor::StorageMiner => STORAGEMINER_ACTOR_NAME,
+            BuiltinActor::StorageMarket => STORAGEMARKET_ACTOR_NAME,
+            BuiltinActor::PaymentChannel => PAYMENTCHANNEL_ACTOR_NAME,
+            BuiltinActor::Multisig => MULTISIG_ACTOR_NAME,
+            BuiltinActor::Reward => REWARD_ACTOR_NAME,
+            BuiltinActor::VerifiedRegistry => VERIFIEDREGISTRY_ACTOR_NAME,
+            BuiltinActor::DataCap => DATACAP_ACTOR_NAME,
+            BuiltinActor::Placeholder => PLACEHOLDER_ACTOR_NAME,
+            BuiltinActor::Evm => EVM_ACTOR_NAME,
+            BuiltinActor::Eam => EAM_ACTOR_NAME,
+            BuiltinActor::EthAccount => ETHACCOUNT_ACTOR_NAME,
+        }
+    }
+
+    /// Returns the actor with the given manifest name, if known.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|actor| actor.name() == name)
+    }
+
+    /// Returns true if the FVM itself relies on this actor, i.e., if it must be present in every
+    /// manifest.
+    fn is_required(self) -> bool {
+        matches!(
+            self,
+            BuiltinActor::System
+                | BuiltinActor::Init
+                | BuiltinActor::Account
+                | BuiltinActor::Placeholder
+                | BuiltinActor::Eam
+                | BuiltinActor::EthAccount
+        )
+    }
+}
+
+/// Manifest versions which can be loaded.
+///
+/// Both versions are encoded the same way, as a CBOR list of `(name, code CID)` pairs. Version 2
+/// manifests may also list builtin actors unknown to this version of the FVM: those are indexed
+/// like any other actor (see [`Manifest::id_by_code`]), but otherwise ignored.
+const SUPPORTED_VERSIONS: &[u32] = &[1, 2];
+
 /// A mapping of builtin actor CIDs to their respective types.
 pub struct Manifest {
-    account_code: Cid,
-    placeholder_code: Cid,
-    system_code: Cid,
-    init_code: Cid,
-    eam_code: Cid,
-    ethaccount_code: Cid,
+    /// Code CIDs of the known builtin actors present in the manifest.
+    by_actor: HashMap<BuiltinActor, Cid>,
 
     by_id: HashMap<u32, Cid>,
     by_code: HashMap<Cid, u32>,
+    by_name: HashMap<String, Cid>,
+    /// Actor names, in the order of their IDs (i.e., the name of actor `id` is at `id - 1`).
+    names: Vec<String>,
+}
+
+/// A builtin actor whose code CID differs between two manifests, see [`Manifest::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestChange {
+    /// Name of the actor in the manifests.
+    pub name: String,
+    /// Code CID of the actor in the old manifest, or `None` if the actor was added.
+    pub old: Option<Cid>,
+    /// Code CID of the actor in the new manifest, or `None` if the actor was removed.
+    pub new: Option<Cid>,
+}
+
+/// Formats the change as `name: old -> new`, with `none` standing for a missing actor.
+impl fmt::Display for ManifestChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = |cid: &Option<Cid>| cid.map_or_else(|| "none".to_owned(), |c| c.to_string());
+        let (old, new) = (code(&self.old), code(&self.new));
+        write!(f, "{}: {old} -> {new}", self.name)
+    }
 }
 
 /// Create an "id CID" (for testing).
@@ -70,8 +191,11 @@ impl Manifest {
     }
 
     /// Load a manifest from the blockstore.
+    ///
+    /// Versions 1 and 2 are supported. They share the same format, but version 2 manifests may
+    /// contain actors this FVM doesn't know about.
     pub fn load<B: Blockstore>(bs: &B, root_cid: &Cid, ver: u32) -> anyhow::Result<Manifest> {
-        if ver != 1 {
+        if !SUPPORTED_VERSIONS.contains(&ver) {
             return Err(anyhow!("unsupported manifest version {}", ver));
         }
 
@@ -86,53 +210,56 @@ impl Manifest {
     }
 
     /// Construct a new manifest from actor name/cid tuples.
+    ///
+    /// Fails if any of the actors the FVM relies on is missing, or if two actors share a name or a
+    /// code CID. Other actors, including unknown ones, are indexed in the order in which they
+    /// appear.
     pub fn new(iter: impl IntoIterator<Item = (impl Into<String>, Cid)>) -> anyhow::Result<Self> {
         let mut by_name = HashMap::new();
         let mut by_id = HashMap::new();
         let mut by_code = HashMap::new();
+        let mut names = Vec::new();
 
         // Actors are indexed sequentially, starting at 1, in the order in which they appear in the
         // manifest. 0 is reserved for "everything else" (i.e., not a builtin actor).
         for ((name, code_cid), id) in iter.into_iter().zip(1u32..) {
-            let name = name.into();
+            let name: String = name.into();
+            if let Some(&other) = by_code.get(&code_cid) {
+                return Err(anyhow!(
+                    "manifest actors {} and {} share the code CID {}",
+                    names[other as usize - 1],
+                    name,
+                    code_cid
+                ));
+            }
+            if by_name.contains_key(&name) {
+                return Err(anyhow!("manifest lists the {} actor more than once", name));
+            }
             by_id.insert(id, code_cid);
             by_code.insert(code_cid, id);
-            by_name.insert(name, code_cid);
+            by_name.insert(name.clone(), code_cid);
+            names.push(name);
         }
 
-        let account_code = *by_name
-            .get(ACCOUNT_ACTOR_NAME)
-            .context("manifest missing account actor")?;
-
-        let system_code = *by_name
-            .get(SYSTEM_ACTOR_NAME)
-            .context("manifest missing system actor")?;
-
-        let init_code = *by_name
-            .get(INIT_ACTOR_NAME)
-            .context("manifest missing init actor")?;
-
-        let placeholder_code = *by_name
-            .get(PLACEHOLDER_ACTOR_NAME)
-            .context("manifest missing placeholder actor")?;
-
-        let eam_code = *by_name
-            .get(EAM_ACTOR_NAME)
-            .context("manifest missing eam actor")?;
-
-        let ethaccount_code = *by_name
-            .get(ETHACCOUNT_ACTOR_NAME)
-            .context("manifest missing ethaccount actor")?;
+        let mut by_actor = HashMap::new();
+        for &actor in BuiltinActor::ALL {
+            match by_name.get(actor.name()) {
+                Some(code_cid) => {
+                    by_actor.insert(actor, *code_cid);
+                }
+                None if actor.is_required() => {
+                    return Err(anyhow!("manifest missing {} actor", actor.name()));
+                }
+                None => {}
+            }
+        }
 
         Ok(Self {
-            account_code,
-            system_code,
-            init_code,
-            placeholder_code,
-            eam_code,
-            ethaccount_code,
+            by_actor,
             by_id,
             by_code,
+            by_name,
+            names,
         })
     }
 
@@ -146,52 +273,244 @@ impl Manifest {
         self.by_code.get(code).copied().unwrap_or(0)
     }
 
+    /// Returns the name of a builtin actor, given its code CID.
+    pub fn name_by_code(&self, code: &Cid) -> Option<&str> {
+        let id = *self.by_code.get(code)?;
+        self.names.get(id as usize - 1).map(String::as_str)
+    }
+
+    /// Returns the code CID of a builtin actor, given its name.
+    pub fn code_by_name(&self, name: &str) -> Option<&Cid> {
+        self.by_name.get(name)
+    }
+
+    /// Returns the code CID of a builtin actor, if present in the manifest.
+    pub fn code_of(&self, actor: BuiltinActor) -> Option<&Cid> {
+        self.by_actor.get(&actor)
+    }
+
+    /// Returns the type of a builtin actor, given its code CID. Returns `None` if the code CID
+    /// isn't a builtin actor, or is a builtin actor unknown to the FVM.
+    pub fn actor_type(&self, code: &Cid) -> Option<BuiltinActor> {
+        self.name_by_code(code).and_then(BuiltinActor::from_name)
+    }
+
+    /// Returns the code CID of an actor which must be present in every manifest.
+    fn required_code(&self, actor: BuiltinActor) -> &Cid {
+        debug_assert!(actor.is_required());
+        self.code_of(actor)
+            .expect("required actors are checked when the manifest is constructed")
+    }
+
     /// Returns true id the passed code CID is the account actor.
     pub fn is_account_actor(&self, cid: &Cid) -> bool {
-        &self.account_code == cid
+        self.required_code(BuiltinActor::Account) == cid
     }
 
     /// Returns true id the passed code CID is the placeholder actor.
     pub fn is_placeholder_actor(&self, cid: &Cid) -> bool {
-        &self.placeholder_code == cid
+        self.required_code(BuiltinActor::Placeholder) == cid
     }
 
     /// Returns true id the passed code CID is the EthAccount actor.
     pub fn is_ethaccount_actor(&self, cid: &Cid) -> bool {
-        &self.ethaccount_code == cid
+        self.required_code(BuiltinActor::EthAccount) == cid
     }
 
     pub fn builtin_actor_codes(&self) -> impl Iterator<Item = &Cid> {
         self.by_id.values()
     }
 
+    /// Returns the `(name, id, code CID)` of every actor in the manifest, in the order in which
+    /// they appear in the manifest (i.e., by ascending ID).
+    pub fn entries(&self) -> impl Iterator<Item = (&str, u32, &Cid)> {
+        self.names
+            .iter()
+            .zip(1u32..)
+            .map(|(name, id)| (name.as_str(), id, &self.by_id[&id]))
+    }
+
+    /// Returns the actors whose code CIDs differ between this manifest and `other`, including
+    /// actors present in only one of them. Changed and removed actors come first, in the order of
+    /// this manifest, followed by the added actors, in the order of `other`.
+    pub fn diff(&self, other: &Manifest) -> Vec<ManifestChange> {
+        let mut changes = Vec::new();
+        for (name, _, code) in self.entries() {
+            let new = other.code_by_name(name);
+            if new != Some(code) {
+                changes.push(ManifestChange {
+                    name: name.to_owned(),
+                    old: Some(*code),
+                    new: new.copied(),
+                });
+            }
+        }
+        for (name, _, code) in other.entries() {
+            if self.code_by_name(name).is_none() {
+                changes.push(ManifestChange {
+                    name: name.to_owned(),
+                    old: None,
+                    new: Some(*code),
+                });
+            }
+        }
+        changes
+    }
+
     /// Returns the code CID for the account actor.
     pub fn get_account_code(&self) -> &Cid {
-        &self.account_code
+        self.required_code(BuiltinActor::Account)
     }
 
     /// Returns the code CID for the init actor.
     pub fn get_init_code(&self) -> &Cid {
-        &self.init_code
+        self.required_code(BuiltinActor::Init)
     }
 
     /// Returns the code CID for the system actor.
     pub fn get_system_code(&self) -> &Cid {
-        &self.system_code
+        self.required_code(BuiltinActor::System)
     }
 
     /// Returns the code CID for the eam actor.
     pub fn get_eam_code(&self) -> &Cid {
-        &self.eam_code
+        self.required_code(BuiltinActor::Eam)
     }
 
     /// Returns the code CID for the system actor.
     pub fn get_placeholder_code(&self) -> &Cid {
-        &self.placeholder_code
+        self.required_code(BuiltinActor::Placeholder)
     }
 
     /// Returns the code CID for the Ethereum Account actor.
     pub fn get_ethaccount_code(&self) -> &Cid {
-        &self.ethaccount_code
+        self.required_code(BuiltinActor::EthAccount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_blockstore::MemoryBlockstore;
+    use multihash::Code;
+
+    use super::*;
+
+    #[test]
+    fn load_versions() {
+        let bs = MemoryBlockstore::default();
+        let future_code = id_cid(b"fil/test/future");
+        let mut codes = Manifest::DUMMY_CODES.to_vec();
+        codes.push(("future", future_code));
+        let root = bs.put_cbor(&codes, Code::Blake2b256).unwrap();
+
+        for ver in [1, 2] {
+            let manifest = Manifest::load(&bs, &root, ver).unwrap();
+            assert_eq!(manifest.id_by_code(&future_code), codes.len() as u32);
+            assert_eq!(manifest.code_by_id(codes.len() as u32), Some(&future_code));
+            assert!(manifest.is_account_actor(&id_cid(b"fil/test/account")));
+        }
+        assert!(Manifest::load(&bs, &root, 3).is_err());
+    }
+
+    #[test]
+    fn names() {
+        let manifest = Manifest::dummy();
+        for (name, code) in Manifest::DUMMY_CODES {
+            assert_eq!(manifest.name_by_code(code), Some(*name));
+            assert_eq!(manifest.code_by_name(name), Some(code));
+        }
+        assert_eq!(manifest.name_by_code(&id_cid(b"fil/test/unknown")), None);
+        assert_eq!(manifest.code_by_name("unknown"), None);
+    }
+
+    #[test]
+    fn entries() {
+        let manifest = Manifest::dummy();
+        let entries: Vec<_> = manifest.entries().collect();
+        assert_eq!(entries.len(), Manifest::DUMMY_CODES.len());
+        for ((name, id, code), (expected_name, expected_code)) in
+            entries.into_iter().zip(Manifest::DUMMY_CODES)
+        {
+            assert_eq!(name, *expected_name);
+            assert_eq!(code, expected_code);
+            assert_eq!(manifest.id_by_code(code), id);
+        }
+    }
+
+    #[test]
+    fn actor_types() {
+        let manifest = Manifest::dummy();
+        for (name, code) in Manifest::DUMMY_CODES {
+            let actor = BuiltinActor::from_name(name).unwrap();
+            assert_eq!(manifest.code_of(actor), Some(code));
+            assert_eq!(manifest.actor_type(code), Some(actor));
+        }
+        assert_eq!(manifest.code_of(BuiltinActor::StorageMarket), None);
+        assert_eq!(manifest.actor_type(&id_cid(b"fil/test/unknown")), None);
+        assert_eq!(
+            manifest.get_account_code(),
+            manifest.code_of(BuiltinActor::Account).unwrap()
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut codes = Manifest::DUMMY_CODES.to_vec();
+        codes.push(("future", id_cid(b"fil/test/cron")));
+        let err = Manifest::new(codes).err().unwrap();
+        assert!(err.to_string().contains("cron and future"), "{}", err);
+
+        let mut codes = Manifest::DUMMY_CODES.to_vec();
+        codes.push(("cron", id_cid(b"fil/test/future")));
+        let err = Manifest::new(codes).err().unwrap();
+        assert!(err.to_string().contains("cron"), "{}", err);
+    }
+
+    #[test]
+    fn diff() {
+        let old = Manifest::dummy();
+        assert!(old.diff(&old).is_empty());
+
+        let mut codes = Manifest::DUMMY_CODES.to_vec();
+        codes.retain(|(name, _)| *name != CRON_ACTOR_NAME);
+        codes.push(("future", id_cid(b"fil/test/future")));
+        codes[0].1 = id_cid(b"fil/test/system/v2");
+        let new = Manifest::new(codes).unwrap();
+
+        let changes = old.diff(&new);
+        assert_eq!(
+            changes,
+            vec![
+                ManifestChange {
+                    name: SYSTEM_ACTOR_NAME.to_owned(),
+                    old: Some(id_cid(b"fil/test/system")),
+                    new: Some(id_cid(b"fil/test/system/v2")),
+                },
+                ManifestChange {
+                    name: CRON_ACTOR_NAME.to_owned(),
+                    old: Some(id_cid(b"fil/test/cron")),
+                    new: None,
+                },
+                ManifestChange {
+                    name: "future".to_owned(),
+                    old: None,
+                    new: Some(id_cid(b"fil/test/future")),
+                },
+            ]
+        );
+        assert_eq!(
+            changes[1].to_string(),
+            format!("cron: {} -> none", id_cid(b"fil/test/cron"))
+        );
+    }
+
+    #[test]
+    fn missing_actor() {
+        let codes = Manifest::DUMMY_CODES
+            .iter()
+            .copied()
+            .filter(|(name, _)| *name != INIT_ACTOR_NAME);
+        let err = Manifest::new(codes).err().unwrap();
+        assert!(err.to_string().contains("init"), "{}", err);
     }
 }
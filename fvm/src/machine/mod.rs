@@ -1,5 +1,8 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use std::fmt;
+use std::sync::Arc;
+
 use cid::Cid;
 use derive_more::{Deref, DerefMut};
 use fvm_ipld_blockstore::Blockstore;
@@ -11,7 +14,7 @@ use fvm_shared::ActorID;
 use num_traits::Zero;
 
 use crate::externs::Externs;
-use crate::gas::{price_list_by_network_version, PriceList};
+use crate::gas::{price_list_by_network_version, GasSchedule, PriceList};
 use crate::kernel::Result;
 use crate::state_tree::{ActorState, StateTree};
 
@@ -24,7 +27,7 @@ pub mod limiter;
 mod manifest;
 
 use fvm_shared::event::StampedEvent;
-pub use manifest::Manifest;
+pub use manifest::{BuiltinActor, Manifest, ManifestChange};
 
 use self::limiter::MemoryLimiter;
 
@@ -152,8 +155,37 @@ pub struct NetworkConfig {
     /// DEFAULT: The price-list for the current network version.
     pub price_list: &'static PriceList,
 
+    /// Scaling applied to named gas charges, for experimenting with alternate gas models.
+    ///
+    /// DEFAULT: [`GasSchedule::mainnet`] (no scaling).
+    pub gas_schedule: GasSchedule,
+
     /// Actor redirects for debug execution
     pub actor_redirect: Vec<(Cid, Cid)>,
+
+    /// Computes the circulating supply reported to actors from the current epoch, overriding
+    /// [`MachineContext::circ_supply`]. This is meant for testing, e.g., to model vesting.
+    ///
+    /// DEFAULT: `None` (the constant [`MachineContext::circ_supply`] is reported).
+    pub circ_supply_fn: Option<CircSupplyFn>,
+}
+
+/// A function returning the circulating supply at a given epoch. See
+/// [`NetworkConfig::set_circ_supply_fn`].
+#[derive(Clone)]
+pub struct CircSupplyFn(Arc<dyn Fn(ChainEpoch) -> TokenAmount + Send + Sync>);
+
+impl CircSupplyFn {
+    /// Returns the circulating supply at `epoch`.
+    pub fn at(&self, epoch: ChainEpoch) -> TokenAmount {
+        (self.0)(epoch)
+    }
+}
+
+impl fmt::Debug for CircSupplyFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CircSupplyFn(..)")
+    }
 }
 
 impl NetworkConfig {
@@ -169,8 +201,10 @@ impl NetworkConfig {
             actor_debugging: false,
             builtin_actors_override: None,
             price_list: price_list_by_network_version(network_version),
+            gas_schedule: GasSchedule::mainnet(),
             actor_redirect: vec![],
             max_block_size: 1 << 20,
+            circ_supply_fn: None,
         }
     }
 
@@ -194,6 +228,17 @@ impl NetworkConfig {
         self
     }
 
+    /// Compute the circulating supply reported to actors with `f`, evaluated at the current epoch
+    /// whenever an actor asks for it. This is a consensus-critical option, it should only be used
+    /// for local testing.
+    pub fn set_circ_supply_fn<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(ChainEpoch) -> TokenAmount + Send + Sync + 'static,
+    {
+        self.circ_supply_fn = Some(CircSupplyFn(Arc::new(f)));
+        self
+    }
+
     /// Create a ['MachineContext'] for a given epoch, timestamp, and initial state.
     pub fn for_epoch(
         &self,
@@ -212,6 +257,13 @@ impl NetworkConfig {
         }
     }
 
+    /// Scale named gas charges according to the given schedule. This is a consensus-critical
+    /// option, it should only be used for local testing.
+    pub fn set_gas_schedule(&mut self, schedule: GasSchedule) -> &mut Self {
+        self.gas_schedule = schedule;
+        self
+    }
+
     /// Set Chain ID of the network.
     pub fn chain_id(&mut self, id: ChainID) -> &mut Self {
         self.chain_id = id;
@@ -2,26 +2,31 @@
 // Copyright 2019-2022 ChainSafe Systems
 // SPDX-License-Identifier: Apache-2.0, MIT
 
-use std::borrow::Borrow;
-use std::cell::RefCell;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+use std::ops::RangeInclusive;
 
 use anyhow::{anyhow, Context as _};
 use cid::{multihash, Cid};
-use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_blockstore::{Blockstore, Buffered};
 use fvm_ipld_encoding::tuple::*;
-use fvm_ipld_encoding::CborStore;
-use fvm_ipld_hamt::Hamt;
-use fvm_shared::address::{Address, Payload};
+use fvm_ipld_encoding::{CborStore, DAG_CBOR};
+use fvm_ipld_hamt::{BytesKey, Hamt};
+#[cfg(feature = "arb")]
+use fvm_shared::address::MAX_SUBADDRESS_LEN;
+use fvm_shared::address::{Address, Payload, Protocol};
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::commcid::{FIL_COMMITMENT_SEALED, FIL_COMMITMENT_UNSEALED};
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::state::{StateInfo0, StateRoot, StateTreeVersion};
-use fvm_shared::{ActorID, HAMT_BIT_WIDTH};
+use fvm_shared::{ActorID, HAMT_BIT_WIDTH, IDENTITY_HASH};
 use num_traits::Zero;
 #[cfg(feature = "arb")]
 use quickcheck::Arbitrary;
 
+use crate::blockstore::scan_for_links;
+use crate::collections::{HistoryMap, LruTracker};
 use crate::init_actor::State as InitActorState;
 use crate::kernel::{ClassifyResult, ExecutionError, Result};
 use crate::{syscall_error, EMPTY_ARR_CID};
@@ -29,10 +34,12 @@ use crate::{syscall_error, EMPTY_ARR_CID};
 /// State tree implementation using hamt. This structure is not threadsafe and should only be used
 /// in sync contexts.
 pub struct StateTree<S> {
-    hamt: Hamt<S, ActorState>,
+    hamt: ActorsHamt<S>,
 
     version: StateTreeVersion,
     info: Option<Cid>,
+    /// State info set through `set_state_info`, written on the next flush.
+    pending_info: Option<StateInfo0>,
 
     /// An actor-state cache that internally keeps an undo history.
     actor_cache: RefCell<HistoryMap<ActorID, ActorCacheEntry>>,
@@ -45,90 +52,134 @@ pub struct StateTree<S> {
     /// 1. Modifications are rejected.
     /// 2. Creating/discarding a layer simply adds/subtracts from this number
     read_only_layers: u32,
+    /// ID ranges reserved through `reserve_id_range`, for test fixtures.
+    #[cfg(feature = "testing")]
+    reserved_ids: Vec<RangeInclusive<ActorID>>,
+    /// Policy checked against the pending changes before each flush.
+    flush_policy: Option<FlushPolicy>,
+    /// Deleted actors, retained when archive mode is enabled.
+    archive: Option<Archive>,
+    /// The epoch recorded along with actors archived on deletion.
+    archive_epoch: ChainEpoch,
+    /// The ID to give to the next snapshot taken through `snapshot`.
+    next_snapshot: u64,
+    /// Hits and misses of the actor and address caches.
+    cache_stats: Cell<CacheStats>,
+    /// Recency of the actor cache entries, if the cache is limited.
+    actor_lru: RefCell<Option<LruTracker<ActorID>>>,
+    /// Recency of the resolve cache entries, if the cache is limited.
+    resolve_lru: RefCell<Option<LruTracker<Address>>>,
 }
 
-/// A map with an "undo" history. All changes to this map are recorded in the history and can be "reverted" by calling `rollback`. Specifically:
-///
-/// 1. The user can call `history_len` to record the current history length.
-/// 2. The user can _later_ call `rollback(previous_length)` to rollback to the state in step 1.
-struct HistoryMap<K, V> {
-    map: HashMap<K, V>,
-    history: Vec<(K, Option<V>)>,
+/// Deleted actors retained in archive mode. See [`StateTree::enable_archive`].
+#[derive(Clone)]
+struct Archive {
+    /// Deleted actors, keyed by ID and the epoch at which they were deleted. This keeps an undo
+    /// history so deletions are forgotten when their transaction is reverted.
+    actors: HistoryMap<(ActorID, ChainEpoch), ActorState>,
 }
 
-impl<K, V> Default for HistoryMap<K, V> {
-    fn default() -> Self {
-        Self {
-            map: Default::default(),
-            history: Default::default(),
-        }
-    }
-}
+/// A check run by [`StateTree::flush`] against the pending changes, vetoing the flush on error.
+pub type FlushPolicy = Box<dyn Fn(&PendingSummary) -> Result<()> + Send + Sync>;
 
-impl<K, V> HistoryMap<K, V>
-where
-    K: Hash + Eq + Clone,
-{
-    /// Insert a k/v pair into the map, recording the previous value in the history.
-    fn insert(&mut self, k: K, v: V) {
-        self.history.push((k.clone(), self.map.insert(k, v)))
-    }
+// The state tree is owned by the machine, which executors may move across threads.
+#[allow(dead_code)]
+fn assert_state_tree_is_send<S: Send>() {
+    fn is_send<T: Send>() {}
+    is_send::<StateTree<S>>();
+}
 
-    /// Lookup a value in the map given a key.
-    fn get<Q>(&self, k: &Q) -> Option<&V>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        self.map.get(k)
-    }
+/// Summary of the changes pending in a state tree, i.e., the changes the next flush will write.
+#[derive(Debug, Default)]
+pub struct PendingSummary<'a> {
+    /// Actors which don't exist in the last flushed state.
+    pub created: Vec<(ActorID, &'a ActorState)>,
+    /// Actors which exist in the last flushed state, and have been modified.
+    pub updated: Vec<(ActorID, &'a ActorState)>,
+    /// Actors which have been deleted.
+    pub deleted: Vec<ActorID>,
+}
 
-    /// Looks up a value in the map given a key, or initializes the entry with the provided
-    /// function. Any modifications to the map are recorded in the history.
-    fn get_or_try_insert_with<F, E>(&mut self, k: K, f: F) -> std::result::Result<&V, E>
-    where
-        F: FnOnce() -> std::result::Result<V, E>,
-    {
-        match self.map.entry(k) {
-            Entry::Occupied(e) => Ok(e.into_mut()),
-            Entry::Vacant(e) => {
-                let v = f()?;
-                self.history.push((e.key().clone(), None));
-                Ok(e.insert(v))
+/// A [`FlushPolicy`] rejecting flushes which would write several actors with the same delegated
+/// address, with a fatal error naming them. This catches corruption (e.g., from a buggy migration)
+/// when it's written rather than when it's next loaded, at the cost of a pass over the pending
+/// changes on every flush. Only the actors being written are checked, not the rest of the tree.
+///
+/// Enable it with `tree.set_flush_policy(Box::new(check_unique_delegated_addresses))`.
+pub fn check_unique_delegated_addresses(summary: &PendingSummary) -> Result<()> {
+    let mut seen = HashMap::new();
+    for (id, actor) in summary.created.iter().chain(&summary.updated) {
+        if let Some(addr) = actor.delegated_address {
+            if let Some(other) = seen.insert(addr, *id) {
+                return Err(ExecutionError::Fatal(anyhow!(
+                    "actors {} and {} share the delegated address {}",
+                    other.min(*id),
+                    other.max(*id),
+                    addr
+                )));
             }
         }
     }
+    Ok(())
+}
 
-    /// Rollback to the specified point in history.
-    fn rollback(&mut self, height: usize) {
-        if self.history.len() <= height {
-            return;
-        }
-        for (k, v) in self.history.drain(height..).rev() {
-            match v {
-                Some(v) => self.map.insert(k, v),
-                None => self.map.remove(&k),
-            };
-        }
-    }
+/// Statistics about the work done by [`StateTree::flush_with_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushStats {
+    /// Number of created or modified actors written to the tree.
+    pub actors_written: usize,
+    /// Number of actors deleted from the tree.
+    pub actors_deleted: usize,
+    /// Number of cached actors skipped because they weren't modified.
+    pub actors_skipped_clean: usize,
+}
 
-    /// Returns the current history length.
-    fn history_len(&self) -> usize {
-        self.history.len()
-    }
+/// Hits and misses of the state tree caches, see [`StateTree::cache_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of actor lookups served by the actor cache.
+    pub actor_hits: u64,
+    /// Number of actor lookups which had to go to the HAMT.
+    pub actor_misses: u64,
+    /// Number of address resolutions served by the resolve cache.
+    pub resolve_hits: u64,
+    /// Number of address resolutions which had to go to the init actor.
+    pub resolve_misses: u64,
+}
 
-    /// Discards all undo history.
-    fn discard_history(&mut self) {
-        self.history.clear();
-    }
+/// What [`StateTree::merge_from`] does with actors which already exist in the target tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Fail the merge, without merging any actor.
+    Error,
+    /// Keep the existing actor.
+    Skip,
+    /// Replace the existing actor with the merged one.
+    Overwrite,
+}
 
-    /// Iterate mutably over the current map.
-    fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
-        self.map.iter_mut()
-    }
+/// Capacities of the state tree caches, see [`StateTree::with_cache_limits`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// Maximum number of entries in the actor cache, or `None` for no limit.
+    pub actors: Option<usize>,
+    /// Maximum number of entries in the address resolution cache, or `None` for no limit.
+    pub addresses: Option<usize>,
+}
+
+/// A change to an actor between two state trees. See [`StateTree::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActorChange {
+    /// The actor only exists in the other tree.
+    Added(ActorState),
+    /// The actor only exists in this tree.
+    Removed(ActorState),
+    /// The actor exists in both trees, with different states.
+    Modified { from: ActorState, to: ActorState },
 }
 
 /// An entry in the actor cache.
+#[derive(Clone)]
 struct ActorCacheEntry {
     /// True if this is a change that should be flushed.
     dirty: bool,
@@ -137,13 +188,26 @@ struct ActorCacheEntry {
 }
 
 /// State snap shot layer.
+#[derive(Clone)]
 struct StateSnapLayer {
     /// The actor-cache height at which this snapshot was taken.
     actor_cache_height: usize,
     /// The resolve-cache height at which this snapshot was taken.
     resolve_cache_height: usize,
+    /// The archive height at which this snapshot was taken, if archive mode is enabled.
+    archive_height: usize,
+    /// The ID of this layer, if it was created through [`StateTree::snapshot`].
+    snapshot: Option<SnapshotId>,
+    /// Actors whose state as of this snapshot is already recorded in the actor-cache history, so
+    /// they can be mutated in place without breaking a rollback to this snapshot.
+    mutated_actors: HashSet<ActorID>,
 }
 
+/// Handle on a snapshot taken with [`StateTree::snapshot`], to be passed to
+/// [`StateTree::revert_to`] or [`StateTree::commit`]. It's invalidated once used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotId(u64);
+
 impl<S> StateTree<S>
 where
     S: Blockstore,
@@ -170,19 +234,33 @@ where
         };
 
         // Both V3 and V4 use bitwidt=5.
-        let hamt = Hamt::new_with_bit_width(store, HAMT_BIT_WIDTH);
+        let hamt = ActorsHamt::Current(Hamt::new_with_bit_width(store, HAMT_BIT_WIDTH));
         Ok(Self {
             hamt,
             version,
             info,
+            pending_info: None,
             actor_cache: Default::default(),
             resolve_cache: Default::default(),
             layers: Vec::new(),
             read_only_layers: 0,
+            #[cfg(feature = "testing")]
+            reserved_ids: Vec::new(),
+            flush_policy: None,
+            archive: None,
+            archive_epoch: 0,
+            next_snapshot: 0,
+            cache_stats: Default::default(),
+            actor_lru: Default::default(),
+            resolve_lru: Default::default(),
         })
     }
 
     /// Constructor for a hamt state tree given an IPLD store
+    ///
+    /// Besides V5, this can load V3 and V4 state trees for historical replay. These are read-only:
+    /// actors can be looked up and iterated over, and flushing an unmodified tree returns the
+    /// original root, but all mutations fail with a fatal error. V0-V2 trees aren't supported.
     pub fn new_from_root(store: S, c: &Cid) -> Result<Self> {
         // Try to load state root, if versioned
         let (version, info, actors) = match store.get_cbor(c) {
@@ -207,33 +285,58 @@ where
         };
 
         match version {
-            StateTreeVersion::V0
-            | StateTreeVersion::V1
-            | StateTreeVersion::V2
-            | StateTreeVersion::V3
-            | StateTreeVersion::V4 => Err(ExecutionError::Fatal(anyhow!(
-                "unsupported state tree version: {:?}",
-                version
-            ))),
+            StateTreeVersion::V0 | StateTreeVersion::V1 | StateTreeVersion::V2 => Err(
+                ExecutionError::Fatal(anyhow!("unsupported state tree version: {:?}", version)),
+            ),
 
-            StateTreeVersion::V5 => {
-                let hamt = Hamt::load_with_bit_width(&actors, store, HAMT_BIT_WIDTH)
-                    .context("failed to load state tree")
-                    .or_fatal()?;
+            StateTreeVersion::V3 | StateTreeVersion::V4 | StateTreeVersion::V5 => {
+                let hamt = if version == StateTreeVersion::V5 {
+                    Hamt::load_with_bit_width(&actors, store, HAMT_BIT_WIDTH)
+                        .map(ActorsHamt::Current)
+                } else {
+                    Hamt::load_with_bit_width(&actors, store, HAMT_BIT_WIDTH)
+                        .map(ActorsHamt::Legacy)
+                }
+                .context("failed to load state tree")
+                .or_fatal()?;
 
                 Ok(Self {
                     hamt,
                     version,
                     info,
+                    pending_info: None,
                     actor_cache: Default::default(),
                     resolve_cache: Default::default(),
                     layers: Vec::new(),
                     read_only_layers: 0,
+                    #[cfg(feature = "testing")]
+                    reserved_ids: Vec::new(),
+                    flush_policy: None,
+                    archive: None,
+                    archive_epoch: 0,
+                    next_snapshot: 0,
+                    cache_stats: Default::default(),
+                    actor_lru: Default::default(),
+                    resolve_lru: Default::default(),
                 })
             }
         }
     }
 
+    /// Limits the number of entries kept in the actor and address resolution caches, which
+    /// otherwise grow for the lifetime of the tree. This matters for long-lived trees, e.g., when
+    /// replaying many tipsets on top of the same tree.
+    ///
+    /// The least recently used entries are evicted when the outermost transaction ends and after
+    /// each flush, never from inside a transaction, as reverting it would need them. Actors with
+    /// unflushed changes are never evicted, so the actor cache may exceed its limit until the next
+    /// flush.
+    pub fn with_cache_limits(mut self, limits: CacheLimits) -> Self {
+        *self.actor_lru.get_mut() = limits.actors.map(LruTracker::new);
+        *self.resolve_lru.get_mut() = limits.addresses.map(LruTracker::new);
+        self
+    }
+
     /// Retrieve store reference to modify db.
     pub fn store(&self) -> &S {
         self.hamt.store()
@@ -251,10 +354,85 @@ where
 
     /// Get actor state from an actor ID.
     pub fn get_actor(&self, id: ActorID) -> Result<Option<ActorState>> {
-        self.actor_cache
+        self.with_actor(id, |actor| actor.cloned())
+    }
+
+    /// Returns true if the actor exists. Unlike [`StateTree::get_actor`], this doesn't clone the
+    /// actor state, but still caches it.
+    pub fn actor_exists(&self, id: ActorID) -> Result<bool> {
+        self.with_actor(id, |actor| actor.is_some())
+    }
+
+    /// Get the delegated address of an actor, if it has one. Returns `None` if the actor doesn't
+    /// exist.
+    pub fn delegated_address(&self, id: ActorID) -> Result<Option<Address>> {
+        self.with_actor(id, |actor| actor.and_then(|a| a.delegated_address))
+    }
+
+    /// Returns the total size, in bytes, of the blocks making up an actor's state: the block
+    /// referenced by its `state` CID and every block transitively linked from it. Blocks linked
+    /// more than once are only counted once.
+    ///
+    /// This loads every block of the state graph, so it can be very expensive for actors with
+    /// large state (e.g., miners and markets).
+    pub fn actor_state_size(&self, id: ActorID) -> Result<u64> {
+        let root = match self.with_actor(id, |actor| actor.map(|a| a.state))? {
+            Some(root) => root,
+            None => return Err(syscall_error!(NotFound; "actor {} not found", id).into()),
+        };
+
+        let mut size = 0u64;
+        let mut seen = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(cid) = stack.pop() {
+            if !seen.insert(cid) {
+                continue;
+            }
+            let mut push_link = |link| -> anyhow::Result<()> {
+                stack.push(link);
+                Ok(())
+            };
+            // Inlined blocks aren't in the store, but may still link to other blocks.
+            if cid.hash().code() == IDENTITY_HASH {
+                if cid.codec() == DAG_CBOR {
+                    scan_for_links(&mut Cursor::new(cid.hash().digest()), &mut push_link)
+                        .or_fatal()?;
+                }
+                continue;
+            }
+            // Piece and sector commitments aren't stored either.
+            if matches!(cid.codec(), FIL_COMMITMENT_UNSEALED | FIL_COMMITMENT_SEALED) {
+                continue;
+            }
+            let block = self
+                .store()
+                .get(&cid)
+                .or_fatal()?
+                .ok_or_else(|| anyhow!("missing block {} in state of actor {}", cid, id))
+                .or_fatal()?;
+            size += block.len() as u64;
+            if cid.codec() == DAG_CBOR {
+                scan_for_links(&mut Cursor::new(&block), &mut push_link)
+                    .with_context(|| format!("failed to scan block {} for links", cid))
+                    .or_fatal()?;
+            }
+        }
+        Ok(size)
+    }
+
+    /// Looks up an actor (through the cache) and applies `f` to it, without cloning it.
+    fn with_actor<F, R>(&self, id: ActorID, f: F) -> Result<R>
+    where
+        F: FnOnce(Option<&ActorState>) -> R,
+    {
+        self.touch_actor(id);
+        let mut missed = false;
+        let res = self
+            .actor_cache
             .borrow_mut()
             .get_or_try_insert_with(id, || {
                 // It's not cached/dirty, so we look it up and cache it.
+                missed = true;
                 let key = Address::new_id(id).to_bytes();
                 Ok(ActorCacheEntry {
                     dirty: false,
@@ -262,16 +440,63 @@ where
                         .hamt
                         .get(&key)
                         .with_context(|| format!("failed to lookup actor {}", id))
-                        .or_fatal()?
-                        .cloned(),
+                        .or_fatal()?,
                 })
             })
-            .map(|ActorCacheEntry { actor, .. }| actor.clone())
+            .map(|ActorCacheEntry { actor, .. }| f(actor.as_ref()));
+        self.update_cache_stats(|stats| match missed {
+            true => stats.actor_misses += 1,
+            false => stats.actor_hits += 1,
+        });
+        res
+    }
+
+    /// Returns the number of hits and misses of the actor and address caches since this state
+    /// tree was created. Lookups of ID addresses, which don't go through the resolve cache, aren't
+    /// counted.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats.get()
+    }
+
+    fn update_cache_stats(&self, f: impl FnOnce(&mut CacheStats)) {
+        let mut stats = self.cache_stats.get();
+        f(&mut stats);
+        self.cache_stats.set(stats);
+    }
+
+    /// Records a use of the actor cache entry of `id`, if the cache is limited.
+    fn touch_actor(&self, id: ActorID) {
+        if let Some(lru) = self.actor_lru.borrow_mut().as_mut() {
+            lru.touch(&id);
+        }
+    }
+
+    /// Records a use of the resolve cache entry of `addr`, if the cache is limited.
+    fn touch_address(&self, addr: &Address) {
+        if let Some(lru) = self.resolve_lru.borrow_mut().as_mut() {
+            lru.touch(addr);
+        }
+    }
+
+    /// Evicts the least recently used cache entries past the cache limits. This must only be
+    /// called outside of transactions, and discards the undo history of the caches.
+    fn trim_caches(&mut self) {
+        if let Some(lru) = self.actor_lru.get_mut() {
+            let cache = self.actor_cache.get_mut();
+            cache.discard_history();
+            lru.evict(cache, |entry| entry.dirty);
+        }
+        if let Some(lru) = self.resolve_lru.get_mut() {
+            let cache = self.resolve_cache.get_mut();
+            cache.discard_history();
+            lru.evict(cache, |_| false);
+        }
     }
 
     /// Set actor state with an actor ID.
     pub fn set_actor(&mut self, id: ActorID, actor: ActorState) -> Result<()> {
         self.assert_writable()?;
+        self.touch_actor(id);
 
         self.actor_cache.borrow_mut().insert(
             id,
@@ -283,15 +508,74 @@ where
         Ok(())
     }
 
+    /// Like [`StateTree::set_actor`], but checks that the actor's delegated address is consistent
+    /// with the rest of the state first:
+    ///
+    /// 1. The delegated address of an existing actor can't be changed, nor removed, once set.
+    /// 2. The delegated address can't already be claimed by another actor, according to the init
+    ///    actor's address map.
+    ///
+    /// Fails with `IllegalArgument` if either check fails. Setting a delegated address requires
+    /// the init actor to exist.
+    pub fn set_actor_checked(&mut self, id: ActorID, actor: ActorState) -> Result<()> {
+        if let Some(existing) = self.delegated_address(id)? {
+            if actor.delegated_address != Some(existing) {
+                return Err(syscall_error!(IllegalArgument;
+                    "cannot change the delegated address of actor {} from {}", id, existing)
+                .into());
+            }
+        }
+        if let Some(addr) = &actor.delegated_address {
+            match self.lookup_id(addr)? {
+                Some(owner) if owner != id => {
+                    return Err(syscall_error!(IllegalArgument;
+                        "delegated address {} of actor {} is already claimed by actor {}",
+                        addr, id, owner)
+                    .into())
+                }
+                _ => {}
+            }
+        }
+        self.set_actor(id, actor)
+    }
+
+    /// Set the states of multiple actors at once. This is equivalent to calling `set_actor` for
+    /// each of them (including with respect to transaction reverts), but cheaper when setting up
+    /// large states (e.g., at genesis).
+    pub fn set_actors(
+        &mut self,
+        actors: impl IntoIterator<Item = (ActorID, ActorState)>,
+    ) -> Result<()> {
+        self.assert_writable()?;
+
+        let cache = self.actor_cache.get_mut();
+        for (id, actor) in actors {
+            if let Some(lru) = self.actor_lru.get_mut() {
+                lru.touch(&id);
+            }
+            cache.insert(
+                id,
+                ActorCacheEntry {
+                    actor: Some(actor),
+                    dirty: true,
+                },
+            );
+        }
+        Ok(())
+    }
+
     /// Get an ID address from any Address
     pub fn lookup_id(&self, addr: &Address) -> Result<Option<ActorID>> {
         if let &Payload::ID(id) = addr.payload() {
             return Ok(Some(id));
         }
 
+        self.touch_address(addr);
         if let Some(&res_address) = self.resolve_cache.borrow().get(addr) {
+            self.update_cache_stats(|stats| stats.resolve_hits += 1);
             return Ok(Some(res_address));
         }
+        self.update_cache_stats(|stats| stats.resolve_misses += 1);
 
         let (state, _) = InitActorState::load(self)?;
 
@@ -305,9 +589,24 @@ where
         Ok(Some(a))
     }
 
+    /// Resolve any Address to both its actor ID and the corresponding ID address. Like
+    /// [`StateTree::lookup_id`], this goes through the resolve cache.
+    pub fn resolve(&self, addr: &Address) -> Result<Option<(ActorID, Address)>> {
+        Ok(self.lookup_id(addr)?.map(|id| (id, Address::new_id(id))))
+    }
+
     /// Delete actor identified by the supplied ID. Returns no error if the actor doesn't exist.
     pub fn delete_actor(&mut self, id: ActorID) -> Result<()> {
         self.assert_writable()?;
+        self.touch_actor(id);
+
+        if self.archive.is_some() {
+            if let Some(actor) = self.get_actor(id)? {
+                let epoch = self.archive_epoch;
+                let archive = self.archive.as_mut().unwrap();
+                archive.actors.insert((id, epoch), actor);
+            }
+        }
 
         // Record that we've deleted the actor.
         self.actor_cache.borrow_mut().insert(
@@ -337,10 +636,34 @@ where
 
     /// Try to mutate the actor state identified by the supplied ID, returning false if the actor
     /// doesn't exist.
+    ///
+    /// If the actor's previous state is already recorded in the current transaction (or there is
+    /// no transaction), the cached actor is mutated in place. In that case, changes made by a
+    /// failing `mutate` remain until the enclosing transaction is reverted, as the call manager
+    /// does for every failed call.
     pub fn maybe_mutate_actor_id<F>(&mut self, id: ActorID, mutate: F) -> Result<bool>
     where
         F: FnOnce(&mut ActorState) -> Result<()>,
     {
+        self.assert_writable()?;
+
+        // Fast path: the actor is dirty and its previous state is already in the undo history of
+        // the current transaction, so we can update it in the cache without recording it again.
+        let recorded = self
+            .layers
+            .last()
+            .map_or(true, |layer| layer.mutated_actors.contains(&id));
+        if recorded {
+            if let Some(ActorCacheEntry {
+                dirty: true,
+                actor: Some(actor),
+            }) = self.actor_cache.get_mut().get_mut(&id)
+            {
+                mutate(actor)?;
+                return Ok(true);
+            }
+        }
+
         // Retrieve actor state from address
         let mut act = match self.get_actor(id)? {
             Some(act) => act,
@@ -351,6 +674,9 @@ where
         mutate(&mut act)?;
         // Set the actor
         self.set_actor(id, act)?;
+        if let Some(layer) = self.layers.last_mut() {
+            layer.mutated_actors.insert(id);
+        }
         Ok(true)
     }
 
@@ -371,6 +697,154 @@ where
         Ok(new_addr)
     }
 
+    /// Register a batch of new addresses through the init actor, returning their IDs in the same
+    /// order. Unlike calling [`StateTree::register_new_address`] for each of them, this loads and
+    /// writes the init actor state only once, which matters when registering many addresses
+    /// (e.g., at genesis or in migrations).
+    pub fn register_new_addresses(&mut self, addrs: &[Address]) -> Result<Vec<ActorID>> {
+        let (mut state, mut actor) = InitActorState::load(self)?;
+
+        let new_addrs = state.map_addresses_to_new_ids(self.store(), addrs)?;
+
+        actor.state = self
+            .store()
+            .put_cbor(&state, multihash::Code::Blake2b256)
+            .or_fatal()?;
+
+        self.set_actor(crate::init_actor::INIT_ACTOR_ID, actor)?;
+
+        Ok(new_addrs)
+    }
+
+    /// Resolve an address to its actor ID, registering a new ID through the init actor if the
+    /// address isn't known yet. ID addresses resolve to their own ID, without looking at the init
+    /// actor.
+    pub fn resolve_or_assign(&mut self, addr: &Address) -> Result<ActorID> {
+        if let Some(id) = self.lookup_id(addr)? {
+            return Ok(id);
+        }
+        let id = self.register_new_address(addr)?;
+        self.touch_address(addr);
+        self.resolve_cache.get_mut().insert(*addr, id);
+        Ok(id)
+    }
+
+    /// Reserve the IDs `lo..=hi` so the init actor never allocates them, by advancing its next-id
+    /// counter past the range. This lets test fixtures place actors at well-known IDs without
+    /// colliding with the ones allocated through [`StateTree::register_new_address`].
+    ///
+    /// Fails if the init actor has already allocated any ID at or above `lo`.
+    #[cfg(feature = "testing")]
+    pub fn reserve_id_range(&mut self, lo: ActorID, hi: ActorID) -> Result<()> {
+        if lo > hi {
+            return Err(syscall_error!(IllegalArgument; "invalid ID range {}..={}", lo, hi).into());
+        }
+
+        let (mut state, mut actor) = InitActorState::load(self)?;
+        if state.next_id > lo {
+            return Err(syscall_error!(
+                IllegalArgument;
+                "cannot reserve IDs {}..={}, the init actor already allocated up to {}",
+                lo,
+                hi,
+                state.next_id - 1
+            )
+            .into());
+        }
+        state.next_id = hi + 1;
+
+        actor.state = self
+            .store()
+            .put_cbor(&state, multihash::Code::Blake2b256)
+            .or_fatal()?;
+        self.set_actor(crate::init_actor::INIT_ACTOR_ID, actor)?;
+
+        self.reserved_ids.push(lo..=hi);
+        Ok(())
+    }
+
+    /// Returns the ID ranges reserved through [`StateTree::reserve_id_range`].
+    #[cfg(feature = "testing")]
+    pub fn reserved_id_ranges(&self) -> &[RangeInclusive<ActorID>] {
+        &self.reserved_ids
+    }
+
+    /// Enable archive mode: from now on, deleted actors are retained in memory, keyed by their ID
+    /// and the epoch set with [`StateTree::set_archive_epoch`], and can be retrieved with
+    /// [`StateTree::archived_actor`]. Does nothing if archive mode is already enabled.
+    ///
+    /// This is a debugging/analysis aid. The archive isn't part of the state root, nor of the
+    /// consensus state: flushing doesn't persist it.
+    pub fn enable_archive(&mut self) {
+        if self.archive.is_none() {
+            self.archive = Some(Archive {
+                actors: Default::default(),
+            });
+        }
+    }
+
+    /// Sets the epoch recorded with actors archived on deletion from now on. Defaults to 0.
+    pub fn set_archive_epoch(&mut self, epoch: ChainEpoch) {
+        self.archive_epoch = epoch;
+    }
+
+    /// Returns the state of an actor deleted at the given epoch while archive mode was enabled.
+    pub fn archived_actor(&self, id: ActorID, epoch: ChainEpoch) -> Option<&ActorState> {
+        self.archive
+            .as_ref()
+            .and_then(|archive| archive.actors.get(&(id, epoch)))
+    }
+
+    /// Returns the state info object the state root links to, including changes made through
+    /// [`StateTree::set_state_info`] which haven't been flushed yet. Returns `None` for state tree
+    /// versions without state info.
+    pub fn state_info(&self) -> Result<Option<StateInfo0>> {
+        if let Some(info) = &self.pending_info {
+            return Ok(Some(info.clone()));
+        }
+        let cid = match self.info {
+            Some(cid) => cid,
+            None => return Ok(None),
+        };
+        let info = self
+            .store()
+            .get_cbor(&cid)
+            .context("failed to load state info")
+            .or_fatal()?
+            .with_context(|| format!("state info {} not found", cid))
+            .or_fatal()?;
+        Ok(Some(info))
+    }
+
+    /// Replaces the state info object the state root links to. The new object is written to the
+    /// store on the next flush.
+    ///
+    /// State info isn't tracked by transactions, so this fails with a fatal error inside of one,
+    /// as well as on read-only trees.
+    pub fn set_state_info(&mut self, info: StateInfo0) -> Result<()> {
+        self.assert_writable()?;
+        if self.in_transaction() {
+            return Err(ExecutionError::Fatal(anyhow!(
+                "cannot set the state info inside of a transaction",
+            )));
+        }
+        if self.info.is_none() {
+            return Err(ExecutionError::Fatal(anyhow!(
+                "{:?} state trees have no state info",
+                self.version
+            )));
+        }
+        self.pending_info = Some(info);
+        Ok(())
+    }
+
+    /// Set a policy checked against the pending changes at the start of every flush. If the
+    /// policy returns an error, the flush is aborted with that error before anything is written,
+    /// and the pending changes are left in place.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = Some(policy);
+    }
+
     /// Begin a new state transaction. Transactions stack.
     pub fn begin_transaction(&mut self, read_only: bool) {
         if read_only || self.is_read_only() {
@@ -379,6 +853,12 @@ where
             self.layers.push(StateSnapLayer {
                 actor_cache_height: self.actor_cache.get_mut().history_len(),
                 resolve_cache_height: self.resolve_cache.get_mut().history_len(),
+                archive_height: self
+                    .archive
+                    .as_ref()
+                    .map_or(0, |archive| archive.actors.history_len()),
+                snapshot: None,
+                mutated_actors: HashSet::new(),
             })
         }
     }
@@ -400,12 +880,74 @@ where
                 self.resolve_cache
                     .get_mut()
                     .rollback(layer.resolve_cache_height);
+                if let Some(archive) = &mut self.archive {
+                    archive.actors.rollback(layer.archive_height);
+                }
+            } else if let Some(parent) = self.layers.last_mut() {
+                // The committed changes are now part of the parent layer.
+                parent.mutated_actors.extend(layer.mutated_actors);
             }
         }
         // When we end the last transaction, discard the undo history.
         if !self.in_transaction() {
             self.actor_cache.get_mut().discard_history();
             self.resolve_cache.get_mut().discard_history();
+            if let Some(archive) = &mut self.archive {
+                archive.actors.discard_history();
+            }
+            self.trim_caches();
+        }
+        Ok(())
+    }
+
+    /// Takes a snapshot of the current state, returning a handle to later revert to or commit it.
+    ///
+    /// This is an explicit alternative to `begin_transaction`/`end_transaction`, sharing the same
+    /// layer stack: a snapshot must not be ended by `end_transaction`, and transactions begun
+    /// after a snapshot are ended along with it. A snapshot taken in read-only mode can only be
+    /// ended once read-only mode is over.
+    pub fn snapshot(&mut self) -> SnapshotId {
+        let id = SnapshotId(self.next_snapshot);
+        self.next_snapshot += 1;
+        self.layers.push(StateSnapLayer {
+            actor_cache_height: self.actor_cache.get_mut().history_len(),
+            resolve_cache_height: self.resolve_cache.get_mut().history_len(),
+            archive_height: self
+                .archive
+                .as_ref()
+                .map_or(0, |archive| archive.actors.history_len()),
+            snapshot: Some(id),
+            mutated_actors: HashSet::new(),
+        });
+        id
+    }
+
+    /// Reverts all changes made since the snapshot `id` was taken, including those of snapshots
+    /// and transactions begun after it, which are ended as well.
+    pub fn revert_to(&mut self, id: SnapshotId) -> Result<()> {
+        self.end_snapshot(id, true)
+    }
+
+    /// Commits the snapshot `id` into the enclosing snapshot or transaction, along with
+    /// snapshots and transactions begun after it.
+    pub fn commit(&mut self, id: SnapshotId) -> Result<()> {
+        self.end_snapshot(id, false)
+    }
+
+    fn end_snapshot(&mut self, id: SnapshotId, revert: bool) -> Result<()> {
+        if self.is_read_only() {
+            return Err(ExecutionError::Fatal(anyhow!(
+                "cannot end a snapshot while in read-only mode"
+            )));
+        }
+        let pos = self
+            .layers
+            .iter()
+            .rposition(|layer| layer.snapshot == Some(id))
+            .with_context(|| format!("snapshot {} already committed or reverted", id.0))
+            .or_fatal()?;
+        while self.layers.len() > pos {
+            self.end_transaction(revert)?;
         }
         Ok(())
     }
@@ -416,34 +958,84 @@ where
     }
 
     /// Flush state tree and return Cid root.
+    ///
+    /// The state root is encoded exactly like lotus encodes it: a CBOR tuple of
+    /// `[version, actors, info]`, where `info` links to an empty CBOR array (`StateInfo0`).
     pub fn flush(&mut self) -> Result<Cid> {
+        self.flush_with_stats().map(|(root, _)| root)
+    }
+
+    /// Like [`StateTree::flush`], but also returns statistics about the actors written.
+    pub fn flush_with_stats(&mut self) -> Result<(Cid, FlushStats)> {
         if self.in_transaction() {
             return Err(ExecutionError::Fatal(anyhow!(
                 "cannot flush while inside of a transaction",
             )));
         }
+        if let Some(policy) = &self.flush_policy {
+            let mut summary = PendingSummary::default();
+            for (&id, entry) in self.actor_cache.get_mut().iter() {
+                if !entry.dirty {
+                    continue;
+                }
+                match &entry.actor {
+                    None => summary.deleted.push(id),
+                    Some(state) => {
+                        let exists = self
+                            .hamt
+                            .contains_key(&Address::new_id(id).to_bytes())
+                            .or_fatal()?;
+                        if exists {
+                            summary.updated.push((id, state));
+                        } else {
+                            summary.created.push((id, state));
+                        }
+                    }
+                }
+            }
+            policy(&summary)?;
+        }
+        let mut stats = FlushStats::default();
         for (&id, entry) in self.actor_cache.get_mut().iter_mut() {
             if !entry.dirty {
+                stats.actors_skipped_clean += 1;
                 continue;
             }
             entry.dirty = false;
             let addr = Address::new_id(id);
             match entry.actor {
                 None => {
-                    self.hamt.delete(&addr.to_bytes()).or_fatal()?;
+                    self.hamt
+                        .current_mut()?
+                        .delete(&addr.to_bytes())
+                        .or_fatal()?;
+                    stats.actors_deleted += 1;
                 }
                 Some(ref state) => {
                     self.hamt
+                        .current_mut()?
                         .set(addr.to_bytes().into(), state.clone())
                         .or_fatal()?;
+                    stats.actors_written += 1;
                 }
             }
         }
 
         let root = self.hamt.flush().or_fatal()?;
+        self.trim_caches();
+
+        if let Some(info) = &self.pending_info {
+            let cid = self
+                .store()
+                .put_cbor(info, multihash::Code::Blake2b256)
+                .context("failed to put state info")
+                .or_fatal()?;
+            self.info = Some(cid);
+            self.pending_info = None;
+        }
 
         match self.version {
-            StateTreeVersion::V0 => Ok(root),
+            StateTreeVersion::V0 => Ok((root, stats)),
             _ => {
                 let cid = self
                     .info
@@ -457,16 +1049,104 @@ where
                     .store()
                     .put_cbor(obj, multihash::Code::Blake2b256)
                     .or_fatal()?;
-                Ok(root)
+                Ok((root, stats))
             }
         }
     }
 
+    /// Flushes the tree to a staged store (e.g., a [`BufferedBlockstore`]), then commits the
+    /// blocks reachable from the new root to the backing store: the actors HAMT, and the actor
+    /// states it links to. Blocks which were written to the staged store but aren't reachable,
+    /// e.g., intermediate states, are never committed.
+    ///
+    /// To revert instead, drop the tree without calling this: the backing store is left untouched.
+    ///
+    /// [`BufferedBlockstore`]: crate::blockstore::BufferedBlockstore
+    pub fn flush_and_commit(&mut self) -> Result<Cid>
+    where
+        S: Buffered,
+    {
+        let root = self.flush()?;
+        self.store()
+            .flush(&root)
+            .context("failed to commit the staged state tree")
+            .or_fatal()?;
+        Ok(root)
+    }
+
     /// Consumes this StateTree and returns the Blockstore it owns via the HAMT.
     pub fn into_store(self) -> S {
         self.hamt.into_store()
     }
 
+    /// Forks this state tree into an independent copy, including its pending (unflushed) changes,
+    /// its caches and its transaction layers. Changes made to either tree aren't visible to the
+    /// other.
+    ///
+    /// The blockstore isn't forked, both trees share it: blocks written by one of them, e.g., when
+    /// flushing, are visible to the other. The flush policy isn't carried over to the fork.
+    pub fn fork(&self) -> Self
+    where
+        S: Clone,
+    {
+        Self {
+            hamt: self.hamt.clone(),
+            version: self.version,
+            info: self.info,
+            pending_info: self.pending_info.clone(),
+            actor_cache: self.actor_cache.clone(),
+            resolve_cache: self.resolve_cache.clone(),
+            layers: self.layers.clone(),
+            read_only_layers: self.read_only_layers,
+            #[cfg(feature = "testing")]
+            reserved_ids: self.reserved_ids.clone(),
+            flush_policy: None,
+            archive: self.archive.clone(),
+            archive_epoch: self.archive_epoch,
+            next_snapshot: self.next_snapshot,
+            cache_stats: self.cache_stats.clone(),
+            actor_lru: self.actor_lru.clone(),
+            resolve_lru: self.resolve_lru.clone(),
+        }
+    }
+
+    /// Merges the actors of the state tree rooted at `other_root`, which must be in the same
+    /// store, into this tree, returning the number of actors merged. Actors with IDs already
+    /// present in this tree are handled according to `on_collision`.
+    ///
+    /// Actors are set as with [`StateTree::set_actor`], so the merge is reverted along with the
+    /// current transaction. Only actors are merged: the address mappings of the other tree's init
+    /// actor aren't, and neither are the entries of its resolve cache.
+    pub fn merge_from(&mut self, other_root: &Cid, on_collision: CollisionPolicy) -> Result<usize> {
+        self.assert_writable()?;
+
+        let mut actors = Vec::new();
+        StateTree::new_from_root(self.store(), other_root)?.for_each_id(|id, actor| {
+            actors.push((id, actor.clone()));
+            Ok(())
+        })?;
+
+        let mut merged = Vec::with_capacity(actors.len());
+        for (id, actor) in actors {
+            if self.actor_exists(id)? {
+                match on_collision {
+                    CollisionPolicy::Error => {
+                        return Err(syscall_error!(IllegalArgument;
+                            "actor {} already exists in the state tree", id)
+                        .into())
+                    }
+                    CollisionPolicy::Skip => continue,
+                    CollisionPolicy::Overwrite => {}
+                }
+            }
+            merged.push((id, actor));
+        }
+
+        let count = merged.len();
+        self.set_actors(merged)?;
+        Ok(count)
+    }
+
     pub fn for_each<F>(&self, mut f: F) -> anyhow::Result<()>
     where
         F: FnMut(Address, &ActorState) -> anyhow::Result<()>,
@@ -478,18 +1158,149 @@ where
         Ok(())
     }
 
-    pub fn is_read_only(&self) -> bool {
-        self.read_only_layers > 0
+    /// Like [`StateTree::for_each`], but passes the actor IDs to `f` instead of their addresses.
+    /// Keys are decoded straight into IDs, which is cheaper than parsing them as addresses.
+    ///
+    /// Fails with a fatal error if a key isn't an ID address, which means the tree is corrupt.
+    pub fn for_each_id<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(ActorID, &ActorState) -> anyhow::Result<()>,
+    {
+        self.hamt
+            .for_each(|k, v| {
+                let id = match k.0.split_first() {
+                    Some((&protocol, payload)) if protocol == Protocol::ID as u8 => {
+                        match unsigned_varint::decode::u64(payload) {
+                            Ok((id, [])) => id,
+                            _ => return Err(anyhow!("invalid ID address key {:?}", k.0)),
+                        }
+                    }
+                    _ => return Err(anyhow!("state tree key {:?} isn't an ID address", k.0)),
+                };
+                f(id, v)
+            })
+            .context("failed to iterate over the state tree")
+            .or_fatal()
     }
 
-    fn assert_writable(&self) -> Result<()> {
-        if self.is_read_only() {
-            Err(syscall_error!(ReadOnly; "cannot mutate state while in read-only mode").into())
-        } else {
+    /// Returns the actors whose code or state CIDs can't be found in the store, along with the
+    /// missing CIDs, ordered by actor ID. Pending (unflushed) changes are taken into account.
+    ///
+    /// This walks the entire state tree, so it's expensive. It's meant as a consistency check for
+    /// imported or partially synced state.
+    pub fn find_dangling_refs(&self) -> Result<Vec<(ActorID, Cid)>> {
+        let cache = self.actor_cache.borrow();
+        let mut dangling = Vec::new();
+        let mut check = |id: ActorID, actor: &ActorState| -> anyhow::Result<()> {
+            for cid in [actor.code, actor.state] {
+                // Identity-hashed CIDs carry their data inline.
+                if cid.hash().code() != IDENTITY_HASH && !self.store().has(&cid)? {
+                    dangling.push((id, cid));
+                }
+            }
             Ok(())
-        }
-    }
-}
+        };
+
+        self.hamt
+            .for_each(|k, actor| {
+                let id = Address::from_bytes(&k.0)?.id()?;
+                // Cached actors take precedence, we check them below.
+                if cache.get(&id).is_some() {
+                    return Ok(());
+                }
+                check(id, actor)
+            })
+            .context("failed to iterate over the state tree")
+            .or_fatal()?;
+        for (&id, entry) in cache.iter() {
+            if let Some(actor) = &entry.actor {
+                check(id, actor).or_fatal()?;
+            }
+        }
+
+        dangling.sort_by_key(|&(id, _)| id);
+        Ok(dangling)
+    }
+
+    /// Computes the actors changed between this tree and the tree at `other_root`, which is
+    /// loaded from the same store. Changes are relative to this tree (e.g., actors only present
+    /// at `other_root` are [`ActorChange::Added`]) and ordered by actor ID. Pending (unflushed)
+    /// changes to this tree are taken into account.
+    ///
+    /// Like [`StateTree::find_dangling_refs`], this walks both trees entirely.
+    pub fn diff(&self, other_root: &Cid) -> Result<Vec<(ActorID, ActorChange)>> {
+        let other = StateTree::new_from_root(self.store(), other_root)?;
+        let mut ours = self.actors()?.into_iter().peekable();
+        let mut theirs = other.actors()?.into_iter().peekable();
+
+        let mut changes = Vec::new();
+        loop {
+            let change = match (ours.peek(), theirs.peek()) {
+                (None, None) => break,
+                (Some(_), None) => {
+                    let (id, actor) = ours.next().unwrap();
+                    (id, ActorChange::Removed(actor))
+                }
+                (Some((a, _)), Some((b, _))) if a < b => {
+                    let (id, actor) = ours.next().unwrap();
+                    (id, ActorChange::Removed(actor))
+                }
+                (Some((a, _)), Some((b, _))) if a == b => {
+                    let (id, from) = ours.next().unwrap();
+                    let (_, to) = theirs.next().unwrap();
+                    if from == to {
+                        continue;
+                    }
+                    (id, ActorChange::Modified { from, to })
+                }
+                (_, Some(_)) => {
+                    let (id, actor) = theirs.next().unwrap();
+                    (id, ActorChange::Added(actor))
+                }
+            };
+            changes.push(change);
+        }
+        Ok(changes)
+    }
+
+    /// Collects all the actors in the tree, including pending changes, ordered by ID. Fails if
+    /// an address in the tree isn't an ID address.
+    fn actors(&self) -> Result<BTreeMap<ActorID, ActorState>> {
+        let mut actors = BTreeMap::new();
+        self.hamt
+            .for_each(|k, actor| {
+                let id = Address::from_bytes(&k.0)?.id()?;
+                actors.insert(id, actor.clone());
+                Ok(())
+            })
+            .context("failed to iterate over the state tree")
+            .or_fatal()?;
+        for (&id, entry) in self.actor_cache.borrow().iter() {
+            match &entry.actor {
+                Some(actor) => actors.insert(id, actor.clone()),
+                None => actors.remove(&id),
+            };
+        }
+        Ok(actors)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only_layers > 0
+    }
+
+    fn assert_writable(&self) -> Result<()> {
+        if matches!(self.version, StateTreeVersion::V3 | StateTreeVersion::V4) {
+            Err(ExecutionError::Fatal(anyhow!(
+                "cannot mutate a {:?} state tree",
+                self.version
+            )))
+        } else if self.is_read_only() {
+            Err(syscall_error!(ReadOnly; "cannot mutate state while in read-only mode").into())
+        } else {
+            Ok(())
+        }
+    }
+}
 
 /// State of all actor implementations.
 #[derive(PartialEq, Eq, Clone, Debug, Serialize_tuple, Deserialize_tuple)]
@@ -504,7 +1315,8 @@ pub struct ActorState {
     pub balance: TokenAmount,
     /// The actor's "delegated" address, if assigned.
     ///
-    /// This field is set on actor creation and never modified.
+    /// This field is set on actor creation and never modified. Actors loaded from V3 and V4 state
+    /// trees, which predate it, have none.
     pub delegated_address: Option<Address>,
 }
 
@@ -537,6 +1349,21 @@ impl ActorState {
         }
     }
 
+    /// Returns a builder for an actor with the specified code.
+    pub fn builder(code: Cid) -> ActorStateBuilder {
+        ActorStateBuilder::new(code)
+    }
+
+    /// Returns the actor's delegated address, if assigned.
+    pub fn delegated_address(&self) -> Option<&Address> {
+        self.delegated_address.as_ref()
+    }
+
+    /// Returns true if the actor has been assigned a delegated address.
+    pub fn has_delegated_address(&self) -> bool {
+        self.delegated_address.is_some()
+    }
+
     /// Safely deducts funds from an Actor
     pub fn deduct_funds(&mut self, amt: &TokenAmount) -> Result<()> {
         if &self.balance < amt {
@@ -554,6 +1381,136 @@ impl ActorState {
     }
 }
 
+/// Actor state as encoded in V3 and V4 state trees, which lack the delegated address.
+#[derive(PartialEq, Eq, Clone, Debug, Serialize_tuple, Deserialize_tuple)]
+struct ActorStateV4 {
+    code: Cid,
+    state: Cid,
+    sequence: u64,
+    balance: TokenAmount,
+}
+
+impl From<ActorStateV4> for ActorState {
+    fn from(actor: ActorStateV4) -> Self {
+        ActorState::new(actor.code, actor.state, actor.balance, actor.sequence, None)
+    }
+}
+
+/// The HAMT of actors, with the actor encoding of the state tree version.
+#[derive(Clone)]
+enum ActorsHamt<S> {
+    /// Actors of V5 state trees.
+    Current(Hamt<S, ActorState>),
+    /// Actors of V3 and V4 state trees. These trees are read-only.
+    Legacy(Hamt<S, ActorStateV4>),
+}
+
+impl<S> ActorsHamt<S>
+where
+    S: Blockstore,
+{
+    fn store(&self) -> &S {
+        match self {
+            Self::Current(hamt) => hamt.store(),
+            Self::Legacy(hamt) => hamt.store(),
+        }
+    }
+
+    fn into_store(self) -> S {
+        match self {
+            Self::Current(hamt) => hamt.into_store(),
+            Self::Legacy(hamt) => hamt.into_store(),
+        }
+    }
+
+    /// Returns the HAMT to write actors to, failing for V3 and V4 state trees.
+    fn current_mut(&mut self) -> Result<&mut Hamt<S, ActorState>> {
+        match self {
+            Self::Current(hamt) => Ok(hamt),
+            Self::Legacy(_) => Err(ExecutionError::Fatal(anyhow!(
+                "cannot write actors to a legacy state tree"
+            ))),
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> std::result::Result<Option<ActorState>, fvm_ipld_hamt::Error> {
+        Ok(match self {
+            Self::Current(hamt) => hamt.get(key)?.cloned(),
+            Self::Legacy(hamt) => hamt.get(key)?.cloned().map(ActorState::from),
+        })
+    }
+
+    fn contains_key(&self, key: &[u8]) -> std::result::Result<bool, fvm_ipld_hamt::Error> {
+        match self {
+            Self::Current(hamt) => hamt.contains_key(key),
+            Self::Legacy(hamt) => hamt.contains_key(key),
+        }
+    }
+
+    fn for_each<F>(&self, mut f: F) -> std::result::Result<(), fvm_ipld_hamt::Error>
+    where
+        F: FnMut(&BytesKey, &ActorState) -> anyhow::Result<()>,
+    {
+        match self {
+            Self::Current(hamt) => hamt.for_each(f),
+            Self::Legacy(hamt) => hamt.for_each(|k, v| f(k, &v.clone().into())),
+        }
+    }
+
+    fn flush(&mut self) -> std::result::Result<Cid, fvm_ipld_hamt::Error> {
+        match self {
+            Self::Current(hamt) => hamt.flush(),
+            Self::Legacy(hamt) => hamt.flush(),
+        }
+    }
+}
+
+/// Builder for [`ActorState`], naming each field to avoid mixing up the arguments of
+/// [`ActorState::new`]. The state defaults to an empty array, the balance and sequence to zero,
+/// and the actor has no delegated address unless specified.
+#[derive(Clone, Debug)]
+pub struct ActorStateBuilder {
+    actor: ActorState,
+}
+
+impl ActorStateBuilder {
+    /// Creates a builder for an actor with the specified code.
+    pub fn new(code: Cid) -> Self {
+        ActorStateBuilder {
+            actor: ActorState::new_empty(code, None),
+        }
+    }
+
+    pub fn code(mut self, code: Cid) -> Self {
+        self.actor.code = code;
+        self
+    }
+
+    pub fn state(mut self, state: Cid) -> Self {
+        self.actor.state = state;
+        self
+    }
+
+    pub fn balance(mut self, balance: TokenAmount) -> Self {
+        self.actor.balance = balance;
+        self
+    }
+
+    pub fn sequence(mut self, sequence: u64) -> Self {
+        self.actor.sequence = sequence;
+        self
+    }
+
+    pub fn delegated_address(mut self, address: Address) -> Self {
+        self.actor.delegated_address = Some(address);
+        self
+    }
+
+    pub fn build(self) -> ActorState {
+        self.actor
+    }
+}
+
 #[cfg(feature = "arb")]
 impl Arbitrary for ActorState {
     fn arbitrary(g: &mut quickcheck::Gen) -> Self {
@@ -566,7 +1523,11 @@ impl Arbitrary for ActorState {
             state: cid,
             sequence: u64::arbitrary(g),
             balance: TokenAmount::from_atto(u64::arbitrary(g)),
-            address: None,
+            delegated_address: bool::arbitrary(g).then(|| {
+                let mut subaddress = Vec::<u8>::arbitrary(g);
+                subaddress.truncate(MAX_SUBADDRESS_LEN);
+                Address::new_delegated(u64::arbitrary(g), &subaddress).unwrap()
+            }),
         }
     }
 }
@@ -653,20 +1614,26 @@ pub mod json {
 #[cfg(test)]
 mod tests {
     use cid::multihash::Code::Blake2b256;
-    use cid::multihash::Multihash;
+    use cid::multihash::{Multihash, MultihashDigest};
     use cid::Cid;
-    use fvm_ipld_blockstore::MemoryBlockstore;
+    use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
     use fvm_ipld_encoding::{CborStore, DAG_CBOR};
+    use fvm_ipld_hamt::Hamt;
     use fvm_shared::address::{Address, SECP_PUB_LEN};
     use fvm_shared::econ::TokenAmount;
-    use fvm_shared::state::StateTreeVersion;
-    use fvm_shared::{ActorID, IDENTITY_HASH, IPLD_RAW};
+    use fvm_shared::error::ErrorNumber;
+    use fvm_shared::state::{StateInfo0, StateRoot, StateTreeVersion};
+    use fvm_shared::{ActorID, HAMT_BIT_WIDTH, IDENTITY_HASH, IPLD_RAW};
     use lazy_static::lazy_static;
 
-    use super::HistoryMap;
-    use crate::init_actor;
+    use crate::blockstore::BufferedBlockstore;
     use crate::init_actor::INIT_ACTOR_ID;
-    use crate::state_tree::{ActorState, StateTree};
+    use crate::kernel::{ExecutionError, SyscallError};
+    use crate::state_tree::{
+        check_unique_delegated_addresses, ActorChange, ActorState, CacheLimits, CacheStats,
+        CollisionPolicy, FlushStats, PendingSummary, StateTree,
+    };
+    use crate::{init_actor, syscall_error, EMPTY_ARR_CID};
 
     lazy_static! {
         pub static ref DUMMY_ACCOUNT_ACTOR_CODE_ID: Cid = Cid::new_v1(
@@ -703,6 +1670,123 @@ mod tests {
         assert_eq!(tree.get_actor(actor_id).unwrap().unwrap(), act_a);
     }
 
+    #[test]
+    fn actor_exists() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let act_s = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        tree.set_actor(3, act_s.clone()).unwrap();
+        assert!(tree.actor_exists(3).unwrap());
+        assert!(!tree.actor_exists(4).unwrap());
+
+        // Flushed actors are found in the HAMT, and cached.
+        let root = tree.flush().unwrap();
+        let mut tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert!(tree.actor_exists(3).unwrap());
+        assert!(tree.actor_cache.borrow().get(&3).is_some());
+        assert_eq!(tree.get_actor(3).unwrap(), Some(act_s));
+        tree.delete_actor(3).unwrap();
+        assert!(!tree.actor_exists(3).unwrap());
+    }
+
+    #[test]
+    fn cache_stats() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let init_state = init_actor::State::new_test(&store);
+        let init_state_cid = store.put_cbor(&init_state, Blake2b256).unwrap();
+        tree.set_actor(
+            INIT_ACTOR_ID,
+            ActorState::new(
+                *DUMMY_INIT_ACTOR_CODE_ID,
+                init_state_cid,
+                Default::default(),
+                0,
+                None,
+            ),
+        )
+        .unwrap();
+        let addr = Address::new_secp256k1(&[1; SECP_PUB_LEN]).unwrap();
+        let id = tree.register_new_address(&addr).unwrap();
+        tree.set_actor(
+            id,
+            ActorState::new_empty(*DUMMY_ACCOUNT_ACTOR_CODE_ID, None),
+        )
+        .unwrap();
+        let root = tree.flush().unwrap();
+
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.cache_stats(), CacheStats::default());
+
+        tree.get_actor(id).unwrap();
+        tree.get_actor(id).unwrap();
+        tree.actor_exists(id + 1).unwrap();
+        assert_eq!(tree.lookup_id(&addr).unwrap(), Some(id));
+        assert_eq!(tree.lookup_id(&addr).unwrap(), Some(id));
+        tree.lookup_id(&Address::new_id(id)).unwrap();
+
+        let stats = tree.cache_stats();
+        assert_eq!(stats.resolve_hits, 1);
+        assert_eq!(stats.resolve_misses, 1);
+        // Resolving the address loaded the init actor, through the actor cache.
+        assert_eq!(stats.actor_hits, 1);
+        assert_eq!(stats.actor_misses, 3);
+    }
+
+    #[test]
+    fn set_actor_checked() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let init_state = init_actor::State::new_test(&store);
+        let init_state_cid = store.put_cbor(&init_state, Blake2b256).unwrap();
+        tree.set_actor(
+            INIT_ACTOR_ID,
+            ActorState::new(
+                *DUMMY_INIT_ACTOR_CODE_ID,
+                init_state_cid,
+                Default::default(),
+                0,
+                None,
+            ),
+        )
+        .unwrap();
+
+        let f4_addr = Address::new_delegated(10, &[1; 20]).unwrap();
+        let other_f4_addr = Address::new_delegated(10, &[2; 20]).unwrap();
+        let id = tree.register_new_address(&f4_addr).unwrap();
+        let actor = |addr| ActorState::new_empty(*DUMMY_ACCOUNT_ACTOR_CODE_ID, addr);
+        let is_illegal_argument = |err: ExecutionError| {
+            matches!(
+                err,
+                ExecutionError::Syscall(SyscallError(_, ErrorNumber::IllegalArgument))
+            )
+        };
+
+        // Setting the delegated address the actor was registered with, then updating the actor
+        // without changing it, is fine.
+        tree.set_actor_checked(id, actor(Some(f4_addr))).unwrap();
+        tree.set_actor_checked(id, actor(Some(f4_addr))).unwrap();
+
+        // It can't be changed, nor removed.
+        let err = tree
+            .set_actor_checked(id, actor(Some(other_f4_addr)))
+            .unwrap_err();
+        assert!(is_illegal_argument(err));
+        let err = tree.set_actor_checked(id, actor(None)).unwrap_err();
+        assert!(is_illegal_argument(err));
+
+        // Nor claimed by another actor.
+        let err = tree
+            .set_actor_checked(id + 1, actor(Some(f4_addr)))
+            .unwrap_err();
+        assert!(is_illegal_argument(err));
+        tree.set_actor_checked(id + 1, actor(None)).unwrap();
+
+        assert_eq!(tree.get_actor(id).unwrap(), Some(actor(Some(f4_addr))));
+        assert_eq!(tree.get_actor(id + 1).unwrap(), Some(actor(None)));
+    }
+
     #[test]
     fn delete_actor() {
         let store = MemoryBlockstore::default();
@@ -716,6 +1800,718 @@ mod tests {
         assert_eq!(tree.get_actor(actor_id).unwrap(), None);
     }
 
+    #[test]
+    fn delegated_address() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let f4_addr = Address::new_delegated(10, &[1; 20]).unwrap();
+        tree.set_actor(
+            100,
+            ActorState::new_empty(*DUMMY_ACCOUNT_ACTOR_CODE_ID, Some(f4_addr)),
+        )
+        .unwrap();
+        tree.set_actor(
+            101,
+            ActorState::new_empty(*DUMMY_ACCOUNT_ACTOR_CODE_ID, None),
+        )
+        .unwrap();
+
+        assert_eq!(tree.delegated_address(100).unwrap(), Some(f4_addr));
+        assert_eq!(tree.delegated_address(101).unwrap(), None);
+        assert_eq!(tree.delegated_address(102).unwrap(), None);
+    }
+
+    #[test]
+    fn find_dangling_refs() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let present = store.put_cbor(&"present", Blake2b256).unwrap();
+        let missing = Cid::new_v1(DAG_CBOR, Blake2b256.digest(b"missing"));
+
+        // Identity-hashed code CIDs are never dangling.
+        let actor = |state| {
+            ActorState::new(
+                *DUMMY_ACCOUNT_ACTOR_CODE_ID,
+                state,
+                Default::default(),
+                0,
+                None,
+            )
+        };
+        tree.set_actor(100, actor(present)).unwrap();
+        tree.set_actor(101, actor(missing)).unwrap();
+        tree.flush().unwrap();
+
+        // Pending changes are checked too.
+        tree.set_actor(100, actor(missing)).unwrap();
+        tree.set_actor(102, actor(missing)).unwrap();
+        tree.set_actor(103, actor(present)).unwrap();
+
+        assert_eq!(
+            tree.find_dangling_refs().unwrap(),
+            vec![(100, missing), (101, missing), (102, missing)]
+        );
+    }
+
+    #[test]
+    fn for_each_id() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let act_s = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        for id in [1, 200, 1 << 40] {
+            tree.set_actor(id, act_s.clone()).unwrap();
+        }
+        tree.flush().unwrap();
+
+        let mut ids = Vec::new();
+        tree.for_each_id(|id, actor| {
+            assert_eq!(actor, &act_s);
+            ids.push(id);
+            Ok(())
+        })
+        .unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![1, 200, 1 << 40]);
+
+        // Keys which aren't ID addresses are a sign of corruption.
+        tree.hamt
+            .current_mut()
+            .unwrap()
+            .set(Address::new_actor(b"actor").to_bytes().into(), act_s)
+            .unwrap();
+        assert!(matches!(
+            tree.for_each_id(|_, _| Ok(())),
+            Err(ExecutionError::Fatal(_))
+        ));
+    }
+
+    #[test]
+    fn fork() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+        tree.set_actor(1, actor(1)).unwrap();
+        tree.flush().unwrap();
+        tree.set_actor(2, actor(1)).unwrap();
+        tree.begin_transaction(false);
+        tree.set_actor(3, actor(1)).unwrap();
+
+        // The fork has the pending changes and the transaction layer.
+        let mut fork = tree.fork();
+        assert_eq!(fork.get_actor(2).unwrap(), Some(actor(1)));
+        assert_eq!(fork.get_actor(3).unwrap(), Some(actor(1)));
+        fork.end_transaction(true).unwrap();
+        assert_eq!(fork.get_actor(3).unwrap(), None);
+
+        // Changes to either tree aren't visible to the other.
+        fork.set_actor(1, actor(2)).unwrap();
+        fork.delete_actor(2).unwrap();
+        tree.set_actor(4, actor(1)).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(1)));
+        assert_eq!(tree.get_actor(2).unwrap(), Some(actor(1)));
+        assert_eq!(tree.get_actor(3).unwrap(), Some(actor(1)));
+        assert_eq!(fork.get_actor(4).unwrap(), None);
+
+        tree.end_transaction(false).unwrap();
+        let root = tree.flush().unwrap();
+        let fork_root = fork.flush().unwrap();
+        assert_ne!(root, fork_root);
+
+        // Both flushed to the shared store.
+        let fork = StateTree::new_from_root(&store, &fork_root).unwrap();
+        assert_eq!(fork.get_actor(1).unwrap(), Some(actor(2)));
+        assert_eq!(fork.get_actor(2).unwrap(), None);
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.get_actor(4).unwrap(), Some(actor(1)));
+    }
+
+    #[test]
+    fn state_info() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        assert_eq!(tree.state_info().unwrap(), Some(StateInfo0::default()));
+
+        tree.set_state_info(StateInfo0::default()).unwrap();
+        assert_eq!(tree.state_info().unwrap(), Some(StateInfo0::default()));
+        let root = tree.flush().unwrap();
+        let state_root: StateRoot = store.get_cbor(&root).unwrap().unwrap();
+        let info: StateInfo0 = store.get_cbor(&state_root.info).unwrap().unwrap();
+        assert_eq!(info, StateInfo0::default());
+
+        let mut tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.state_info().unwrap(), Some(StateInfo0::default()));
+
+        // State info can't be set inside of a transaction.
+        tree.begin_transaction(false);
+        assert!(matches!(
+            tree.set_state_info(StateInfo0::default()),
+            Err(ExecutionError::Fatal(_))
+        ));
+        tree.end_transaction(false).unwrap();
+    }
+
+    #[test]
+    fn merge_from() {
+        let store = MemoryBlockstore::default();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+
+        let mut other = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        other.set_actor(2, actor(20)).unwrap();
+        other.set_actor(3, actor(30)).unwrap();
+        let other_root = other.flush().unwrap();
+
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        tree.set_actor(1, actor(1)).unwrap();
+        tree.set_actor(2, actor(2)).unwrap();
+
+        // Collisions fail the merge before anything is merged.
+        assert!(matches!(
+            tree.merge_from(&other_root, CollisionPolicy::Error),
+            Err(ExecutionError::Syscall(SyscallError(
+                _,
+                ErrorNumber::IllegalArgument
+            )))
+        ));
+        assert_eq!(tree.get_actor(3).unwrap(), None);
+
+        // Merges are reverted along with their transaction.
+        tree.begin_transaction(false);
+        assert_eq!(
+            tree.merge_from(&other_root, CollisionPolicy::Skip).unwrap(),
+            1
+        );
+        assert_eq!(tree.get_actor(2).unwrap(), Some(actor(2)));
+        assert_eq!(tree.get_actor(3).unwrap(), Some(actor(30)));
+        tree.end_transaction(true).unwrap();
+        assert_eq!(tree.get_actor(3).unwrap(), None);
+
+        assert_eq!(
+            tree.merge_from(&other_root, CollisionPolicy::Overwrite)
+                .unwrap(),
+            2
+        );
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(1)));
+        assert_eq!(tree.get_actor(2).unwrap(), Some(actor(20)));
+        assert_eq!(tree.get_actor(3).unwrap(), Some(actor(30)));
+
+        // Merging is rejected in read-only mode.
+        tree.begin_transaction(true);
+        assert!(tree
+            .merge_from(&other_root, CollisionPolicy::Overwrite)
+            .is_err());
+        tree.end_transaction(false).unwrap();
+    }
+
+    #[test]
+    fn cache_limits() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+        for id in 1..=4 {
+            tree.set_actor(id, actor(id)).unwrap();
+        }
+        let root = tree.flush().unwrap();
+
+        let mut tree = StateTree::new_from_root(&store, &root)
+            .unwrap()
+            .with_cache_limits(CacheLimits {
+                actors: Some(2),
+                addresses: None,
+            });
+
+        // Nothing is evicted inside of a transaction, as a revert would need it.
+        tree.begin_transaction(false);
+        for id in 1..=4 {
+            tree.get_actor(id).unwrap();
+        }
+        tree.set_actor(1, actor(10)).unwrap();
+        assert_eq!(tree.actor_cache.borrow().len(), 4);
+
+        // The dirty actor is kept, along with the most recently used clean one.
+        tree.end_transaction(false).unwrap();
+        assert_eq!(tree.actor_cache.borrow().len(), 2);
+        assert!(tree.actor_cache.borrow().get(&1).is_some());
+        assert!(tree.actor_cache.borrow().get(&4).is_some());
+
+        // Evicted actors are loaded again from the HAMT.
+        assert_eq!(tree.get_actor(2).unwrap(), Some(actor(2)));
+        assert_eq!(tree.actor_cache.borrow().len(), 3);
+        let root = tree.flush().unwrap();
+        assert_eq!(tree.actor_cache.borrow().len(), 2);
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(10)));
+
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(10)));
+        assert_eq!(tree.get_actor(3).unwrap(), Some(actor(3)));
+    }
+
+    #[test]
+    fn unique_delegated_addresses() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        tree.set_flush_policy(Box::new(check_unique_delegated_addresses));
+
+        let f4_addr = Address::new_delegated(10, &[1; 20]).unwrap();
+        let actor = |addr| ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, addr);
+        tree.set_actor(100, actor(Some(f4_addr))).unwrap();
+        tree.set_actor(101, actor(None)).unwrap();
+        tree.set_actor(102, actor(None)).unwrap();
+        let root = tree.flush().unwrap();
+
+        // Bypass the checks of set_actor_checked, as a buggy migration would.
+        tree.set_actor(102, actor(Some(f4_addr))).unwrap();
+        tree.set_actor(103, actor(Some(f4_addr))).unwrap();
+        match tree.flush() {
+            Err(ExecutionError::Fatal(e)) => assert_eq!(
+                e.to_string(),
+                format!("actors 102 and 103 share the delegated address {f4_addr}")
+            ),
+            res => panic!("unexpected flush result: {res:?}"),
+        }
+        // The changes are kept, and can be fixed up.
+        assert!(tree.flush().is_err());
+        tree.set_actor(102, actor(None)).unwrap();
+        tree.delete_actor(103).unwrap();
+        assert_eq!(tree.flush().unwrap(), root);
+    }
+
+    #[test]
+    fn flush_and_commit() {
+        let base = MemoryBlockstore::default();
+        let mut tree =
+            StateTree::new(BufferedBlockstore::new(&base), StateTreeVersion::V5).unwrap();
+
+        let orphan = tree.store().put_cbor(&"orphan", Blake2b256).unwrap();
+        let state = tree.store().put_cbor(&"state", Blake2b256).unwrap();
+        let act_s = ActorState::new(empty_cid(), state, Default::default(), 1, None);
+        tree.set_actor(1, act_s.clone()).unwrap();
+
+        // Only the reachable blocks are committed to the backing store.
+        let root = tree.flush_and_commit().unwrap();
+        assert!(base.has(&root).unwrap());
+        assert!(base.has(&state).unwrap());
+        assert!(!base.has(&orphan).unwrap());
+
+        // Dropping a tree reverts its changes, even if they were flushed to the staged store.
+        let mut tree = StateTree::new_from_root(BufferedBlockstore::new(&base), &root).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(act_s.clone()));
+        tree.set_actor(2, act_s).unwrap();
+        let reverted = tree.flush().unwrap();
+        drop(tree);
+        assert!(!base.has(&reverted).unwrap());
+    }
+
+    #[test]
+    fn flush_policy() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let act_s = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        tree.set_actor(1, act_s.clone()).unwrap();
+        tree.set_actor(2, act_s.clone()).unwrap();
+        let root = tree.flush().unwrap();
+
+        // At most one new actor per flush.
+        tree.set_flush_policy(Box::new(|summary: &PendingSummary| {
+            if summary.created.len() > 1 {
+                return Err(syscall_error!(Forbidden; "too many new actors").into());
+            }
+            Ok(())
+        }));
+
+        tree.set_actor(
+            1,
+            ActorState {
+                sequence: 2,
+                ..act_s.clone()
+            },
+        )
+        .unwrap();
+        tree.delete_actor(2).unwrap();
+        tree.set_actor(3, act_s.clone()).unwrap();
+        tree.set_actor(4, act_s.clone()).unwrap();
+
+        // The flush is vetoed, and nothing is written.
+        assert!(tree.flush().is_err());
+        assert_eq!(
+            StateTree::new_from_root(&store, &root)
+                .unwrap()
+                .get_actor(3)
+                .unwrap(),
+            None
+        );
+
+        // The pending changes are kept, and can be fixed up.
+        assert_eq!(tree.get_actor(4).unwrap(), Some(act_s.clone()));
+        tree.delete_actor(4).unwrap();
+
+        tree.set_flush_policy(Box::new(|summary: &PendingSummary| {
+            assert_eq!(
+                summary
+                    .created
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>(),
+                [3]
+            );
+            assert_eq!(summary.updated.len(), 1);
+            assert_eq!(summary.updated[0].0, 1);
+            assert_eq!(summary.updated[0].1.sequence, 2);
+            let mut deleted = summary.deleted.clone();
+            deleted.sort_unstable();
+            assert_eq!(deleted, [2, 4]);
+            Ok(())
+        }));
+        let root = tree.flush().unwrap();
+
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+        assert_eq!(tree.get_actor(3).unwrap(), Some(act_s));
+    }
+
+    #[test]
+    fn archive() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let act_s = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        tree.set_actor(1, act_s.clone()).unwrap();
+        tree.set_actor(2, act_s.clone()).unwrap();
+        tree.set_actor(3, act_s.clone()).unwrap();
+
+        // Deletions aren't archived until archive mode is enabled.
+        tree.set_archive_epoch(10);
+        tree.delete_actor(1).unwrap();
+        tree.enable_archive();
+        tree.delete_actor(2).unwrap();
+        tree.set_archive_epoch(11);
+
+        // Deletions in reverted transactions are forgotten.
+        tree.begin_transaction(false);
+        tree.delete_actor(3).unwrap();
+        tree.end_transaction(true).unwrap();
+
+        let root = tree.flush().unwrap();
+
+        assert_eq!(tree.archived_actor(1, 10), None);
+        assert_eq!(tree.archived_actor(2, 10), Some(&act_s));
+        assert_eq!(tree.archived_actor(2, 11), None);
+        assert_eq!(tree.archived_actor(3, 11), None);
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+        assert_eq!(tree.get_actor(3).unwrap(), Some(act_s.clone()));
+
+        // The archive isn't part of the state.
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+        assert_eq!(tree.archived_actor(2, 10), None);
+    }
+
+    #[test]
+    fn actor_state_size() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let leaf = store.put_cbor(&"leaf", Blake2b256).unwrap();
+        let middle = store.put_cbor(&(leaf, leaf), Blake2b256).unwrap();
+        let root = store
+            .put_cbor(&(middle, leaf, empty_cid()), Blake2b256)
+            .unwrap();
+        let block_size = |cid| store.get(&cid).unwrap().unwrap().len() as u64;
+
+        let actor_id = 1;
+        tree.set_actor(
+            actor_id,
+            ActorState::new(empty_cid(), root, Default::default(), 1, None),
+        )
+        .unwrap();
+
+        // Each block is only counted once, however many times it's linked.
+        assert_eq!(
+            tree.actor_state_size(actor_id).unwrap(),
+            block_size(root) + block_size(middle) + block_size(leaf)
+        );
+
+        let err = tree.actor_state_size(2).unwrap_err();
+        assert!(matches!(
+            err,
+            ExecutionError::Syscall(SyscallError(_, ErrorNumber::NotFound))
+        ));
+    }
+
+    #[test]
+    fn diff() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+
+        for id in 1..=3 {
+            tree.set_actor(id, actor(0)).unwrap();
+        }
+        let before = tree.flush().unwrap();
+
+        tree.set_actor(2, actor(1)).unwrap();
+        tree.delete_actor(3).unwrap();
+        tree.set_actor(4, actor(0)).unwrap();
+        // Setting an actor to the same state isn't a change.
+        tree.set_actor(1, actor(0)).unwrap();
+        let expected = vec![
+            (
+                2,
+                ActorChange::Modified {
+                    from: actor(0),
+                    to: actor(1),
+                },
+            ),
+            (3, ActorChange::Removed(actor(0))),
+            (4, ActorChange::Added(actor(0))),
+        ];
+
+        let old_tree = StateTree::new_from_root(&store, &before).unwrap();
+        let after = tree.flush().unwrap();
+        assert_eq!(old_tree.diff(&after).unwrap(), expected);
+
+        // Pending changes are taken into account.
+        let mut pending = StateTree::new_from_root(&store, &after).unwrap();
+        pending.delete_actor(4).unwrap();
+        assert_eq!(
+            pending.diff(&after).unwrap(),
+            vec![(4, ActorChange::Added(actor(0)))]
+        );
+    }
+
+    #[test]
+    fn named_snapshots() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+
+        let outer = tree.snapshot();
+        tree.set_actor(1, actor(1)).unwrap();
+
+        let inner = tree.snapshot();
+        tree.set_actor(1, actor(2)).unwrap();
+        tree.set_actor(2, actor(2)).unwrap();
+        tree.revert_to(inner).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(1)));
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+
+        // Reverting an outer snapshot also ends the snapshots taken after it.
+        let inner = tree.snapshot();
+        tree.set_actor(2, actor(2)).unwrap();
+        let middle = tree.snapshot();
+        tree.set_actor(3, actor(3)).unwrap();
+        tree.revert_to(inner).unwrap();
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+        assert_eq!(tree.get_actor(3).unwrap(), None);
+        assert!(tree.commit(middle).is_err());
+
+        // Consumed snapshots can't be used again.
+        assert!(tree.revert_to(inner).is_err());
+
+        tree.commit(outer).unwrap();
+        assert!(!tree.in_transaction());
+        assert!(tree.commit(outer).is_err());
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(1)));
+    }
+
+    #[test]
+    fn mutate_in_place_reverts() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+        let set_sequence = |tree: &mut StateTree<_>, sequence| {
+            tree.mutate_actor(1, |actor| {
+                actor.sequence = sequence;
+                Ok(())
+            })
+            .unwrap()
+        };
+        tree.set_actor(1, actor(0)).unwrap();
+
+        tree.begin_transaction(false);
+        set_sequence(&mut tree, 1);
+        // Mutated in place, the previous state is already recorded.
+        set_sequence(&mut tree, 2);
+
+        tree.begin_transaction(false);
+        set_sequence(&mut tree, 3);
+        set_sequence(&mut tree, 4);
+        // Changes made by a failed mutation are only undone with the transaction.
+        tree.mutate_actor(1, |actor| {
+            actor.sequence = 99;
+            Err(syscall_error!(IllegalArgument; "failed").into())
+        })
+        .unwrap_err();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(99)));
+        tree.end_transaction(true).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(2)));
+
+        tree.end_transaction(true).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(0)));
+
+        // Outside of transactions, there's nothing to revert.
+        set_sequence(&mut tree, 5);
+        set_sequence(&mut tree, 6);
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(6)));
+    }
+
+    #[test]
+    fn set_actors() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+        tree.set_actor(1, actor(0)).unwrap();
+
+        tree.begin_transaction(false);
+        tree.set_actors((1..=3).map(|id| (id, actor(id)))).unwrap();
+        for id in 1..=3 {
+            assert_eq!(tree.get_actor(id).unwrap(), Some(actor(id)));
+        }
+        tree.end_transaction(true).unwrap();
+
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(0)));
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+        assert_eq!(tree.get_actor(3).unwrap(), None);
+
+        tree.begin_transaction(true);
+        let err = tree.set_actors([(2, actor(2))]).unwrap_err();
+        assert!(matches!(
+            err,
+            ExecutionError::Syscall(SyscallError(_, ErrorNumber::ReadOnly))
+        ));
+        tree.end_transaction(false).unwrap();
+    }
+
+    #[test]
+    fn flush_with_stats() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |sequence| {
+            ActorState::new(empty_cid(), empty_cid(), Default::default(), sequence, None)
+        };
+
+        tree.set_actors((1..=3).map(|id| (id, actor(0)))).unwrap();
+        let (_, stats) = tree.flush_with_stats().unwrap();
+        assert_eq!(
+            stats,
+            FlushStats {
+                actors_written: 3,
+                actors_deleted: 0,
+                actors_skipped_clean: 0,
+            }
+        );
+
+        tree.set_actor(1, actor(1)).unwrap();
+        tree.delete_actor(2).unwrap();
+        let (root, stats) = tree.flush_with_stats().unwrap();
+        assert_eq!(
+            stats,
+            FlushStats {
+                actors_written: 1,
+                actors_deleted: 1,
+                actors_skipped_clean: 1,
+            }
+        );
+        assert_eq!(tree.flush().unwrap(), root);
+    }
+
+    #[test]
+    fn actor_state_builder() {
+        let addr = Address::new_delegated(10, &[1, 2, 3]).unwrap();
+        let actor = ActorState::builder(empty_cid())
+            .sequence(2)
+            .balance(TokenAmount::from_atto(5))
+            .delegated_address(addr)
+            .build();
+        assert_eq!(
+            actor,
+            ActorState::new(
+                empty_cid(),
+                *EMPTY_ARR_CID,
+                TokenAmount::from_atto(5),
+                2,
+                Some(addr)
+            )
+        );
+        assert_eq!(actor.delegated_address(), Some(&addr));
+        assert!(actor.has_delegated_address());
+
+        let actor = ActorState::builder(empty_cid()).build();
+        assert_eq!(actor, ActorState::new_empty(empty_cid(), None));
+        assert!(!actor.has_delegated_address());
+    }
+
+    #[cfg(feature = "arb")]
+    #[test]
+    fn actor_state_cbor_roundtrip() {
+        fn roundtrip(actor: ActorState) -> bool {
+            let bytes = fvm_ipld_encoding::to_vec(&actor).unwrap();
+            fvm_ipld_encoding::from_slice::<ActorState>(&bytes).unwrap() == actor
+        }
+        quickcheck::quickcheck(roundtrip as fn(ActorState) -> bool);
+    }
+
+    #[test]
+    fn load_v4() {
+        let store = MemoryBlockstore::default();
+
+        // V4 actors are encoded without the delegated address.
+        let mut hamt: Hamt<_, _> = Hamt::new_with_bit_width(&store, HAMT_BIT_WIDTH);
+        let v4_actor = (empty_cid(), empty_cid(), 3u64, TokenAmount::from_atto(7));
+        // V5 actors must have all fields.
+        let encoded = fvm_ipld_encoding::to_vec(&v4_actor).unwrap();
+        assert!(fvm_ipld_encoding::from_slice::<ActorState>(&encoded).is_err());
+        hamt.set(Address::new_id(1).to_bytes().into(), v4_actor)
+            .unwrap();
+        let root = store
+            .put_cbor(
+                &StateRoot {
+                    version: StateTreeVersion::V4,
+                    actors: hamt.flush().unwrap(),
+                    info: store.put_cbor(&StateInfo0::default(), Blake2b256).unwrap(),
+                },
+                Blake2b256,
+            )
+            .unwrap();
+
+        let mut tree = StateTree::new_from_root(&store, &root).unwrap();
+        let expected =
+            ActorState::new(empty_cid(), empty_cid(), TokenAmount::from_atto(7), 3, None);
+        assert_eq!(tree.get_actor(1).unwrap(), Some(expected.clone()));
+        let mut actors = Vec::new();
+        tree.for_each(|addr, actor| {
+            actors.push((addr, actor.clone()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(actors, vec![(Address::new_id(1), expected.clone())]);
+
+        // Mutations are rejected, and the root is unchanged.
+        assert!(matches!(
+            tree.set_actor(2, expected).unwrap_err(),
+            ExecutionError::Fatal(_)
+        ));
+        assert_eq!(tree.flush().unwrap(), root);
+    }
+
     #[test]
     fn get_set_non_id() {
         let store = MemoryBlockstore::default();
@@ -762,6 +2558,84 @@ mod tests {
         let assigned_addr = tree.register_new_address(&addr).unwrap();
 
         assert_eq!(assigned_addr, 100);
+
+        // Resolve it
+        assert_eq!(
+            tree.resolve(&addr).unwrap(),
+            Some((100, Address::new_id(100)))
+        );
+        assert_eq!(
+            tree.resolve(&Address::new_id(7)).unwrap(),
+            Some((7, Address::new_id(7)))
+        );
+        let unknown = Address::new_secp256k1(&[3; SECP_PUB_LEN]).unwrap();
+        assert_eq!(tree.resolve(&unknown).unwrap(), None);
+
+        // Resolve or assign
+        assert_eq!(tree.resolve_or_assign(&addr).unwrap(), 100);
+        assert_eq!(tree.resolve_or_assign(&Address::new_id(7)).unwrap(), 7);
+        assert_eq!(tree.resolve_or_assign(&unknown).unwrap(), 101);
+        assert_eq!(tree.resolve_cache.borrow().get(&unknown), Some(&101));
+        assert_eq!(tree.resolve_or_assign(&unknown).unwrap(), 101);
+    }
+
+    #[test]
+    fn register_new_addresses() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let init_state = init_actor::State::new_test(&store);
+        let state_cid = tree.store().put_cbor(&init_state, Blake2b256).unwrap();
+        let act_s = ActorState::new(
+            *DUMMY_INIT_ACTOR_CODE_ID,
+            state_cid,
+            Default::default(),
+            1,
+            None,
+        );
+        tree.set_actor(INIT_ACTOR_ID, act_s).unwrap();
+
+        let addrs: Vec<_> = (1..=3)
+            .map(|i| Address::new_secp256k1(&[i; SECP_PUB_LEN]).unwrap())
+            .collect();
+        assert_eq!(
+            tree.register_new_addresses(&addrs).unwrap(),
+            [100, 101, 102]
+        );
+        for (addr, id) in addrs.iter().zip(100..) {
+            assert_eq!(tree.lookup_id(addr).unwrap(), Some(id));
+        }
+
+        // IDs keep being allocated after the batch.
+        let addr = Address::new_secp256k1(&[4; SECP_PUB_LEN]).unwrap();
+        assert_eq!(tree.register_new_address(&addr).unwrap(), 103);
+        assert!(tree.register_new_addresses(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reserve_id_range() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let init_state = init_actor::State::new_test(&store);
+        let state_cid = tree.store().put_cbor(&init_state, Blake2b256).unwrap();
+        let act_s = ActorState::new(
+            *DUMMY_INIT_ACTOR_CODE_ID,
+            state_cid,
+            Default::default(),
+            1,
+            None,
+        );
+        tree.set_actor(INIT_ACTOR_ID, act_s).unwrap();
+
+        tree.reserve_id_range(200, 210).unwrap();
+        assert_eq!(tree.reserved_id_ranges(), &[200..=210]);
+
+        let addr = Address::new_secp256k1(&[2; SECP_PUB_LEN]).unwrap();
+        assert_eq!(tree.register_new_address(&addr).unwrap(), 211);
+
+        // IDs already handed out by the init actor can't be reserved.
+        assert!(tree.reserve_id_range(205, 220).is_err());
+        assert!(tree.reserve_id_range(230, 220).is_err());
+        assert_eq!(tree.reserved_id_ranges(), &[200..=210]);
     }
 
     #[test]
@@ -868,6 +2742,42 @@ mod tests {
         assert_eq!(tree.get_actor(actor_id).unwrap(), None);
     }
 
+    #[test]
+    fn read_only_transaction() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let actor_id: ActorID = 1;
+        let act_s = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        tree.set_actor(actor_id, act_s.clone()).unwrap();
+
+        tree.begin_transaction(true);
+        assert!(tree.is_read_only());
+
+        // Nested transactions stay read-only, even if they don't ask for it.
+        tree.begin_transaction(false);
+        let err = tree
+            .mutate_actor(actor_id, |actor| {
+                actor.sequence = 2;
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ExecutionError::Syscall(SyscallError(_, ErrorNumber::ReadOnly))
+        ));
+        let err = tree.delete_actor(actor_id).unwrap_err();
+        assert!(matches!(
+            err,
+            ExecutionError::Syscall(SyscallError(_, ErrorNumber::ReadOnly))
+        ));
+        tree.end_transaction(false).unwrap();
+        tree.end_transaction(false).unwrap();
+
+        assert!(!tree.is_read_only());
+        assert_eq!(tree.get_actor(actor_id).unwrap(), Some(act_s));
+    }
+
     #[test]
     fn unsupported_versions() {
         let unsupported = vec![
@@ -885,69 +2795,43 @@ mod tests {
         }
     }
 
+    /// Pins the encoding of an empty V5 state tree to CIDs lotus produces: the empty HAMT of
+    /// go-hamt-ipld (also pinned by the HAMT tests), and the empty object, which is what cbor-gen
+    /// encodes `StateInfo0` as.
     #[test]
-    fn history_map() {
-        let mut map = HistoryMap::<i32, &'static str>::default();
-
-        // Basic history tests.
-        assert_eq!(map.get(&1), None);
-        assert_eq!(map.history_len(), 0);
-        map.insert(1, "foo");
-        assert_eq!(map.history_len(), 1);
-        assert_eq!(map.get(&1), Some(&"foo"));
-        map.insert(2, "bar");
-        assert_eq!(map.history_len(), 2);
-        assert_eq!(map.get(&1), Some(&"foo"));
-        assert_eq!(map.get(&2), Some(&"bar"));
-        map.insert(1, "baz");
-        assert_eq!(map.history_len(), 3);
-        assert_eq!(map.get(&1), Some(&"baz"));
-        map.rollback(4); // doesn't panic.
-        assert_eq!(map.history_len(), 3);
-        map.rollback(3); // no-op.
-        assert_eq!(map.history_len(), 3);
-        assert_eq!(map.get(&1), Some(&"baz"));
-        map.rollback(2); // undoes the insert of 1 -> baz
-        assert_eq!(map.history_len(), 2);
-        assert_eq!(map.get(&1), Some(&"foo"));
-        assert_eq!(map.get(&2), Some(&"bar"));
-        map.rollback(1); // undoes the insert of 2 -> bar
-        assert_eq!(map.history_len(), 1);
-        assert_eq!(map.get(&1), Some(&"foo"));
-        assert_eq!(map.get(&2), None);
-        map.rollback(0); // empties the map
-        assert_eq!(map.history_len(), 0);
-        assert_eq!(map.get(&1), None);
-
-        // Inserts
-        assert_eq!(
-            map.get_or_try_insert_with(1, || -> Result<_, ()> { Ok("foo") })
-                .unwrap(),
-            &"foo",
-        );
-        assert_eq!(map.get(&1), Some(&"foo"));
-        assert_eq!(map.history_len(), 1);
+    fn state_root_encoding_matches_lotus() {
+        let empty_hamt =
+            Cid::try_from("bafy2bzaceamp42wmmgr2g2ymg46euououzfyck7szknvfacqscohrvaikwfay")
+                .unwrap();
+        let empty_object =
+            Cid::try_from("bafy2bzacebc3bt6cedhoyw34drrmjvazhu4oj25er2ebk4u445pzycvq4ta4a")
+                .unwrap();
 
-        // Doing it again changes nothing.
-        assert_eq!(
-            map.get_or_try_insert_with(1, || -> Result<_, ()> { panic!() })
-                .unwrap(),
-            &"foo",
-        );
-        assert_eq!(map.history_len(), 1);
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let root = tree.flush().unwrap();
 
-        // Bubbles the error and changes nothing.
+        let StateRoot {
+            version,
+            actors,
+            info,
+        } = store.get_cbor(&root).unwrap().unwrap();
+        assert_eq!(version, StateTreeVersion::V5);
+        assert_eq!(actors, empty_hamt);
+        assert_eq!(info, empty_object);
+        assert_eq!(store.get(&info).unwrap().unwrap(), [0x80]);
+
+        // The state root is a 3-tuple of the version followed by two tagged CIDs.
+        let mut expected = vec![0x83, 0x05];
+        for cid in [empty_hamt, empty_object] {
+            let cid = cid.to_bytes();
+            expected.extend_from_slice(&[0xd8, 0x2a, 0x58, cid.len() as u8 + 1, 0x00]);
+            expected.extend_from_slice(&cid);
+        }
+        assert_eq!(store.get(&root).unwrap().unwrap(), expected);
         assert_eq!(
-            map.get_or_try_insert_with(2, || { Err("err") })
-                .unwrap_err(),
-            "err",
+            root.to_string(),
+            "bafy2bzacea5kvshrbmhig767evdmtc7rmslswb5usgloeuzcoepdysahz6fnq"
         );
-        assert_eq!(map.get(&2), None);
-        assert_eq!(map.history_len(), 1);
-
-        // Undo the first insertion.
-        map.rollback(0); // empties the map
-        assert_eq!(map.history_len(), 0);
-        assert_eq!(map.get(&1), None);
     }
 }
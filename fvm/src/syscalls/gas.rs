@@ -22,6 +22,8 @@ pub fn charge_gas(
         .map(|_| ())
 }
 
+/// Returns the gas remaining for the current message, in whole gas units. Milligas remainders are
+/// rounded down, as actors only ever deal in full gas units.
 pub fn available(context: Context<'_, impl Kernel>) -> Result<u64> {
     Ok(context.kernel.gas_available().round_down() as u64)
 }
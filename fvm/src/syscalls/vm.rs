@@ -6,6 +6,7 @@ use fvm_shared::sys::SyscallSafe;
 
 use super::error::Abort;
 use super::Context;
+use crate::call_manager::NO_DATA_BLOCK_ID;
 use crate::kernel::Kernel;
 
 /// An uninhabited type. We use this in `abort` to make sure there's no way to return without
@@ -28,6 +29,20 @@ pub fn exit(
     message_len: u32,
 ) -> Result<Never, Abort> {
     let code = ExitCode::new(code);
+
+    // Check the return block here, while we can still report it as the actor's fault. This
+    // doesn't charge gas, so exiting costs the same as before the check.
+    if blk != NO_DATA_BLOCK_ID && !context.kernel.block_exists(blk) {
+        return Err(Abort::Exit(
+            ExitCode::SYS_ILLEGAL_EXIT_CODE,
+            format!(
+                "actor exited with code {} and an invalid return block {}",
+                code, blk
+            ),
+            NO_DATA_BLOCK_ID,
+        ));
+    }
+
     if !code.is_success() && code.is_system_error() {
         return Err(Abort::Exit(
             ExitCode::SYS_ILLEGAL_EXIT_CODE,
@@ -1,12 +1,14 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use std::borrow::Cow;
+
 use fvm_ipld_encoding::RawBytes;
 use fvm_shared::address::Address;
 use fvm_shared::econ::TokenAmount;
-use fvm_shared::error::ExitCode;
+use fvm_shared::error::{ErrorNumber, ExitCode};
 use fvm_shared::{ActorID, MethodNum};
 
-use crate::gas::GasCharge;
+use crate::gas::{Gas, GasCharge};
 use crate::kernel::SyscallError;
 
 /// Execution Trace, only for informational and debugging purposes.
@@ -28,4 +30,59 @@ pub enum ExecutionEvent {
     },
     CallReturn(ExitCode, RawBytes),
     CallError(SyscallError),
+    /// A block of `size` bytes persisted to the store by `actor` through `block_link`. Summing
+    /// these events gives the state growth caused by each actor.
+    BlockWrite {
+        actor: ActorID,
+        size: u64,
+    },
+}
+
+/// A deterministic view of an [`ExecutionEvent`], keeping the gas charges, the call boundaries and
+/// the blocks written but leaving out timings and payloads, so that the gas profile of an execution
+/// can be compared across runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GasTraceEntry {
+    /// Gas charged for an operation.
+    Charge { name: Cow<'static, str>, gas: Gas },
+    /// Start of a call.
+    Call {
+        from: ActorID,
+        to: Address,
+        method: MethodNum,
+    },
+    /// End of the current call, returning an exit code.
+    Return(ExitCode),
+    /// End of the current call, failing with a syscall error.
+    Error(ErrorNumber),
+    /// A block of `size` bytes persisted to the store by `actor`.
+    Write { actor: ActorID, size: u64 },
+}
+
+impl GasTraceEntry {
+    /// Extracts the gas trace from an execution trace, in order.
+    pub fn from_exec_trace(trace: &[ExecutionEvent]) -> Vec<Self> {
+        trace
+            .iter()
+            .map(|event| match event {
+                ExecutionEvent::GasCharge(charge) => GasTraceEntry::Charge {
+                    name: charge.name.clone(),
+                    gas: charge.total(),
+                },
+                ExecutionEvent::Call {
+                    from, to, method, ..
+                } => GasTraceEntry::Call {
+                    from: *from,
+                    to: *to,
+                    method: *method,
+                },
+                ExecutionEvent::CallReturn(code, _) => GasTraceEntry::Return(*code),
+                ExecutionEvent::CallError(err) => GasTraceEntry::Error(err.1),
+                ExecutionEvent::BlockWrite { actor, size } => GasTraceEntry::Write {
+                    actor: *actor,
+                    size: *size,
+                },
+            })
+            .collect()
+    }
 }
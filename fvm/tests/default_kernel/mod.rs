@@ -7,6 +7,7 @@ use std::rc::Rc;
 use fvm::kernel::default::DefaultKernel;
 use fvm::kernel::{Block, BlockRegistry};
 use fvm::Kernel;
+use fvm_shared::{ActorID, MethodNum};
 use multihash::Code;
 use num_traits::Zero;
 
@@ -16,20 +17,30 @@ mod ops;
 
 type TestingKernel = DefaultKernel<DummyCallManager>;
 
+/// build a kernel around `call_manager`, for tests which set up the machine or state tree first
+pub fn build_kernel(
+    call_manager: DummyCallManager,
+    caller: ActorID,
+    actor_id: ActorID,
+    method: MethodNum,
+) -> TestingKernel {
+    TestingKernel::new(
+        call_manager,
+        BlockRegistry::default(),
+        caller,
+        actor_id,
+        method,
+        Zero::zero(),
+    )
+}
+
 /// build a kernel for testing
 pub fn build_inspecting_test() -> anyhow::Result<(TestingKernel, Rc<RefCell<TestData>>)> {
     // call_manager is not dropped till the end of the function
     let (call_manager, test_data) = dummy::DummyCallManager::new_stub();
     // variable for value inspection, only upgrade after done mutating to avoid panic
 
-    let kern = TestingKernel::new(
-        call_manager,
-        BlockRegistry::default(),
-        0,
-        0,
-        0,
-        Zero::zero(),
-    );
+    let kern = build_kernel(call_manager, 0, 0, 0);
     Ok((kern, test_data))
 }
 
@@ -41,14 +52,7 @@ pub fn build_inspecting_gas_test(
     let (call_manager, test_data) = dummy::DummyCallManager::new_with_gas(gas_tracker);
     // variable for value inspection, only upgrade after done mutating to avoid panic
 
-    let kern = TestingKernel::new(
-        call_manager,
-        BlockRegistry::default(),
-        0,
-        0,
-        0,
-        Zero::zero(),
-    );
+    let kern = build_kernel(call_manager, 0, 0, 0);
     Ok((kern, test_data))
 }
 
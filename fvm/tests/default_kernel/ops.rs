@@ -5,10 +5,12 @@ use super::*;
 mod ipld {
 
     use cid::Cid;
-    use fvm::kernel::{IpldBlockOps, SupportedHashes};
-    use fvm::machine::Machine;
+    use fvm::kernel::{IpldBlockOps, SelfOps, SupportedHashes};
+    use fvm::machine::{Machine, Manifest};
+    use fvm::state_tree::ActorState;
     use fvm_ipld_blockstore::Blockstore;
-    use fvm_ipld_encoding::DAG_CBOR;
+    use fvm_ipld_encoding::{to_vec, DAG_CBOR};
+    use fvm_shared::{commcid, IPLD_RAW};
     use multihash::MultihashDigest;
     use pretty_assertions::{assert_eq, assert_ne};
 
@@ -237,6 +239,127 @@ mod ipld {
         Ok(())
     }
 
+    /// Builds a kernel for actor 0, installed with the given code and state root.
+    fn build_actor_test(code: Cid, root: Cid) -> anyhow::Result<TestingKernel> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        call_manager
+            .machine
+            .state_tree
+            .set_actor(0, ActorState::new(code, root, Zero::zero(), 0, None))?;
+        Ok(build_kernel(call_manager, 0, 0, 0))
+    }
+
+    fn user_code() -> Cid {
+        Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest("user actor".as_bytes()))
+    }
+
+    #[test]
+    fn link_reachability() -> anyhow::Result<()> {
+        let root = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("initial".as_bytes()));
+        let mut kern = build_actor_test(user_code(), root)?;
+
+        // Blocks linked by the actor are reachable.
+        let id = kern.block_create(IPLD_RAW, "foo".as_bytes())?;
+        let cid = kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        let id = kern.block_create(DAG_CBOR, &to_vec(&cid)?)?;
+        kern.block_link(id, Code::Blake2b256.into(), 32)?;
+
+        // So are built-in actor code CIDs and CIDs which aren't blocks, like commitments.
+        let account_code = *Manifest::dummy().get_account_code();
+        let commitment = commcid::data_commitment_v1_to_cid(&[5u8; 32]).unwrap();
+        let id = kern.block_create(DAG_CBOR, &to_vec(&(account_code, commitment))?)?;
+        kern.block_link(id, Code::Blake2b256.into(), 32)?;
+
+        // Blocks the actor never saw aren't.
+        let unknown = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest("bar".as_bytes()));
+        let id = kern.block_create(DAG_CBOR, &to_vec(&unknown)?)?;
+        expect_syscall_err!(NotFound, kern.block_link(id, Code::Blake2b256.into(), 32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_root_reachability() -> anyhow::Result<()> {
+        let initial_root = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("initial".as_bytes()));
+        let mut kern = build_actor_test(user_code(), initial_root)?;
+
+        let unknown = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("bar".as_bytes()));
+        expect_syscall_err!(NotFound, kern.set_root(unknown));
+
+        let id = kern.block_create(DAG_CBOR, &to_vec(&initial_root)?)?;
+        let cid = kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        kern.set_root(cid)?;
+        assert_eq!(kern.root()?, cid);
+
+        Ok(())
+    }
+
+    #[test]
+    fn builtin_actor_reachability() -> anyhow::Result<()> {
+        let initial_root = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("initial".as_bytes()));
+        let account_code = *Manifest::dummy().get_account_code();
+        let mut kern = build_actor_test(account_code, initial_root)?;
+
+        // Reachability isn't enforced for built-in actors, nor is the block parsed.
+        let unknown = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("bar".as_bytes()));
+        let id = kern.block_create(DAG_CBOR, &to_vec(&unknown)?)?;
+        kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        let id = kern.block_create(DAG_CBOR, &[0xd8, 0x2a, 0x40])?;
+        kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        kern.set_root(unknown)?;
+        assert_eq!(kern.root()?, unknown);
+
+        Ok(())
+    }
+
+    #[test]
+    fn scan_links_gas() -> anyhow::Result<()> {
+        let root = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("initial".as_bytes()));
+        let block = to_vec(&root)?;
+        let link = |code| -> anyhow::Result<DummyCallManager> {
+            let mut kern = build_actor_test(code, root)?;
+            let id = kern.block_create(DAG_CBOR, &block)?;
+            kern.block_link(id, Code::Blake2b256.into(), 32)?;
+            Ok(kern.into_inner().0)
+        };
+
+        // Only user actors are charged for scanning blocks for links.
+        let user = link(user_code())?;
+        let builtin = link(*Manifest::dummy().get_account_code())?;
+        let scan_price = user
+            .machine
+            .context()
+            .price_list
+            .on_block_scan_links(block.len())
+            .total();
+        assert_eq!(
+            user.gas_tracker.gas_used(),
+            builtin.gas_tracker.gas_used() + scan_price
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn bytes_written() -> anyhow::Result<()> {
+        let (mut kern, _) = build_inspecting_test()?;
+        assert_eq!(kern.bytes_written(), 0);
+
+        // Created blocks aren't written until they're linked.
+        let id = kern.block_create(IPLD_RAW, "foo".as_bytes())?;
+        kern.block_create(IPLD_RAW, "unlinked".as_bytes())?;
+        assert_eq!(kern.bytes_written(), 0);
+
+        kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        assert_eq!(kern.bytes_written(), 3);
+
+        let id = kern.block_create(IPLD_RAW, "barbaz".as_bytes())?;
+        kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        assert_eq!(kern.bytes_written(), 9);
+
+        Ok(())
+    }
+
     #[test]
     fn read() -> anyhow::Result<()> {
         let (mut kern, _) = build_inspecting_test()?;
@@ -350,6 +473,35 @@ mod ipld {
         Ok(())
     }
 
+    #[test]
+    fn read_offsets() -> anyhow::Result<()> {
+        let (mut kern, _) = build_inspecting_test()?;
+        let id = kern.block_create(IPLD_RAW, "hello world!".as_bytes())?;
+
+        // The return value is the number of bytes left in the block past the buffer, negative if
+        // the buffer isn't filled.
+        let mut buf = [0u8; 4];
+        assert_eq!(kern.block_read(id, 0, &mut buf)?, 8);
+        assert_eq!(&buf, b"hell");
+
+        let mut buf = [0u8; 4];
+        assert_eq!(kern.block_read(id, 5, &mut buf)?, 3);
+        assert_eq!(&buf, b" wor");
+
+        // Partial read near the end of the block.
+        let mut buf = [0u8; 4];
+        assert_eq!(kern.block_read(id, 10, &mut buf)?, -2);
+        assert_eq!(&buf, b"d!\0\0");
+
+        // Nothing is read at, or past, the end of the block.
+        let mut buf = [0u8; 4];
+        assert_eq!(kern.block_read(id, 12, &mut buf)?, -4);
+        assert_eq!(kern.block_read(id, 20, &mut buf)?, -12);
+        assert_eq!(buf, [0u8; 4]);
+
+        Ok(())
+    }
+
     #[test]
     fn read_unexpected() -> anyhow::Result<()> {
         let (mut kern, test_data) = build_inspecting_test()?;
@@ -434,6 +586,280 @@ mod ipld {
     }
 }
 
+mod actor {
+    use cid::Cid;
+    use fvm::init_actor;
+    use fvm::kernel::ActorOps;
+    use fvm::state_tree::ActorState;
+    use fvm_ipld_encoding::{CborStore, DAG_CBOR};
+    use fvm_shared::address::Address;
+    use multihash::MultihashDigest;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn resolve_address_and_code_cid() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let state_tree = &mut call_manager.machine.state_tree;
+
+        // Addresses are resolved through the init actor.
+        let init_state = init_actor::State::new_test(state_tree.store());
+        let init_code = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("init".as_bytes()));
+        let init_state_cid = state_tree.store().put_cbor(&init_state, Code::Blake2b256)?;
+        state_tree.set_actor(
+            init_actor::INIT_ACTOR_ID,
+            ActorState::new(init_code, init_state_cid, Zero::zero(), 0, None),
+        )?;
+
+        let addr = Address::new_secp256k1(&[1; 65])?;
+        let id = state_tree.register_new_address(&addr)?;
+        let code = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("account".as_bytes()));
+        state_tree.set_actor(id, ActorState::new_empty(code, None))?;
+
+        let kern = build_kernel(call_manager, 0, 0, 0);
+
+        assert_eq!(kern.resolve_address(&addr)?, id);
+        assert_eq!(kern.resolve_address(&Address::new_id(id))?, id);
+        assert_eq!(kern.get_actor_code_cid(id)?, code);
+
+        // Unknown addresses and actors aren't found.
+        let unknown = Address::new_secp256k1(&[2; 65])?;
+        expect_syscall_err!(NotFound, kern.resolve_address(&unknown));
+        expect_syscall_err!(NotFound, kern.get_actor_code_cid(id + 1));
+
+        Ok(())
+    }
+}
+
+mod message {
+    use fvm::kernel::MessageOps;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn context() -> anyhow::Result<()> {
+        let (call_manager, _) = dummy::DummyCallManager::new_stub();
+        let kern = build_kernel(call_manager, 100, 101, 42);
+
+        let ctx = kern.msg_context()?;
+        assert_eq!(ctx.caller, 100);
+        assert_eq!(ctx.receiver, 101);
+        assert_eq!(ctx.method_number, 42);
+
+        Ok(())
+    }
+}
+
+mod network {
+    use fvm::kernel::NetworkOps;
+    use fvm_shared::econ::TokenAmount;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn context() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let base_fee = TokenAmount::from_atto(567);
+        call_manager.machine.ctx.epoch = 1234;
+        call_manager.machine.ctx.base_fee = base_fee.clone();
+        let kern = build_kernel(call_manager, 0, 0, 0);
+
+        let ctx = kern.network_context()?;
+        assert_eq!(ctx.epoch, 1234);
+        assert_eq!(ctx.network_version, STUB_NETWORK_VER as u32);
+        assert_eq!(TokenAmount::from(ctx.base_fee), base_fee);
+
+        Ok(())
+    }
+}
+
+mod rand {
+    use fvm::kernel::RandomnessOps;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn randomness() -> anyhow::Result<()> {
+        let (mut call_manager, test_data) = dummy::DummyCallManager::new_stub();
+        call_manager.machine.ctx.epoch = 100;
+        let kern = build_kernel(call_manager, 0, 0, 0);
+
+        assert_eq!(
+            kern.get_randomness_from_tickets(1, 100, b"entropy")?,
+            dummy::draw_randomness(b"ticket", 1, 100, b"entropy")
+        );
+        assert_eq!(
+            kern.get_randomness_from_beacon(2, 50, b"entropy")?,
+            dummy::draw_randomness(b"beacon", 2, 50, b"entropy")
+        );
+        assert_ne!(
+            kern.get_randomness_from_tickets(1, 100, b"entropy")?,
+            kern.get_randomness_from_tickets(1, 100, b"other entropy")?
+        );
+
+        // Future epochs are rejected without reaching the externs, but the gas is still charged.
+        let charges = test_data.borrow().charge_gas_calls;
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.get_randomness_from_tickets(1, 101, b"entropy")
+        );
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.get_randomness_from_beacon(2, 101, b"entropy")
+        );
+        assert_eq!(test_data.borrow().charge_gas_calls, charges + 2);
+
+        Ok(())
+    }
+}
+
+mod self_ops {
+    use cid::Cid;
+    use fvm::kernel::{CircSupplyOps, SelfOps};
+    use fvm::state_tree::ActorState;
+    use fvm_ipld_encoding::DAG_CBOR;
+    use fvm_shared::address::Address;
+    use fvm_shared::econ::TokenAmount;
+    use multihash::MultihashDigest;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn current_balance() -> anyhow::Result<()> {
+        // The actor doesn't exist, so it has no balance.
+        let (kern, _) = build_inspecting_test()?;
+        assert_eq!(kern.current_balance()?, TokenAmount::zero());
+
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("state".as_bytes()));
+        let balance = TokenAmount::from_atto(1234);
+        call_manager
+            .machine
+            .state_tree
+            .set_actor(0, ActorState::new(cid, cid, balance.clone(), 0, None))?;
+        let kern = build_kernel(call_manager, 0, 0, 0);
+        assert_eq!(kern.current_balance()?, balance);
+
+        Ok(())
+    }
+
+    #[test]
+    fn total_fil_circ_supply() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let circ_supply = TokenAmount::from_whole(42);
+        call_manager.machine.ctx.circ_supply = circ_supply.clone();
+        let kern = build_kernel(call_manager, 0, 0, 0);
+        assert_eq!(kern.total_fil_circ_supply()?, circ_supply);
+
+        Ok(())
+    }
+
+    #[test]
+    fn total_fil_circ_supply_fn() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        call_manager.machine.ctx.epoch = 7;
+        call_manager
+            .machine
+            .ctx
+            .set_circ_supply_fn(|epoch| TokenAmount::from_whole(epoch * 10));
+        let kern = build_kernel(call_manager, 0, 0, 0);
+        assert_eq!(kern.total_fil_circ_supply()?, TokenAmount::from_whole(70));
+
+        Ok(())
+    }
+
+    #[test]
+    fn self_destruct() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("state".as_bytes()));
+        let balance = TokenAmount::from_atto(1234);
+        let state_tree = &mut call_manager.machine.state_tree;
+        state_tree.set_actor(100, ActorState::new(cid, cid, balance.clone(), 0, None))?;
+        state_tree.set_actor(101, ActorState::new(cid, cid, Zero::zero(), 0, None))?;
+        let mut kern = build_kernel(call_manager, 0, 100, 0);
+
+        // The actor can't be its own beneficiary, and stays in place.
+        expect_syscall_err!(Forbidden, kern.self_destruct(&Address::new_id(100)));
+        assert_eq!(kern.current_balance()?, balance);
+
+        // Otherwise, its balance is moved to the beneficiary.
+        kern.self_destruct(&Address::new_id(101))?;
+        let (call_manager, _) = kern.into_inner();
+        let state_tree = &call_manager.machine.state_tree;
+        assert_eq!(state_tree.get_actor(100)?, None);
+        assert_eq!(state_tree.get_actor(101)?.unwrap().balance, balance);
+
+        Ok(())
+    }
+}
+
+mod send {
+    use cid::Cid;
+    use fvm::call_manager::NO_DATA_BLOCK_ID;
+    use fvm::kernel::{ExecutionError, SelfOps, SyscallError};
+    use fvm::machine::Manifest;
+    use fvm::state_tree::ActorState;
+    use fvm_ipld_encoding::DAG_CBOR;
+    use fvm_shared::address::Address;
+    use fvm_shared::econ::TokenAmount;
+    use fvm_shared::error::{ErrorNumber, ExitCode};
+    use multihash::MultihashDigest;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn send_readonly() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let code = *Manifest::dummy().get_account_code();
+        let root = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("state".as_bytes()));
+        call_manager
+            .machine
+            .state_tree
+            .set_actor(1, ActorState::new(code, root, Zero::zero(), 0, None))?;
+
+        // The callee tries to update its state root.
+        let new_root = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest("new state".as_bytes()));
+        let result = Rc::new(RefCell::new(None));
+        let callee_result = result.clone();
+        call_manager.callee = Some(Box::new(move |kern: &mut dyn SelfOps| {
+            let res = kern.set_root(new_root);
+            let exit_code = if res.is_ok() {
+                ExitCode::OK
+            } else {
+                ExitCode::USR_ILLEGAL_STATE
+            };
+            *callee_result.borrow_mut() = Some(res);
+            exit_code
+        }));
+        let mut kern = build_kernel(call_manager, 0, 0, 0);
+
+        let ret = kern.send_readonly(
+            &Address::new_id(1),
+            2,
+            NO_DATA_BLOCK_ID,
+            &TokenAmount::zero(),
+            None,
+        )?;
+        assert_eq!(ret.exit_code, ExitCode::USR_ILLEGAL_STATE);
+        match result.borrow_mut().take() {
+            Some(Err(ExecutionError::Syscall(SyscallError(_, ErrorNumber::ReadOnly)))) => {}
+            other => panic!("expected a ReadOnly error, got {:?}", other),
+        }
+
+        let (call_manager, _) = kern.into_inner();
+        let actor = call_manager.machine.state_tree.get_actor(1)?.unwrap();
+        assert_eq!(actor.state, root);
+
+        Ok(())
+    }
+}
+
 mod gas {
     use fvm::gas::*;
     use fvm::kernel::GasOps;
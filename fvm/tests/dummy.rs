@@ -10,6 +10,7 @@ use fvm::call_manager::{Backtrace, CallManager, FinishRet, InvocationResult};
 use fvm::engine::Engine;
 use fvm::externs::{Chain, Consensus, Externs, Rand};
 use fvm::gas::{Gas, GasCharge, GasTimer, GasTracker};
+use fvm::kernel::BlockRegistry;
 use fvm::machine::limiter::MemoryLimiter;
 use fvm::machine::{Machine, MachineContext, Manifest, NetworkConfig};
 use fvm::state_tree::{ActorState, StateTree};
@@ -19,6 +20,7 @@ use fvm_ipld_encoding::{CborStore, DAG_CBOR};
 use fvm_shared::address::Address;
 use fvm_shared::bigint::Zero;
 use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
 use fvm_shared::event::StampedEvent;
 use fvm_shared::state::StateTreeVersion;
 use fvm_shared::version::NetworkVersion;
@@ -35,23 +37,41 @@ impl Externs for DummyExterns {}
 impl Rand for DummyExterns {
     fn get_chain_randomness(
         &self,
-        _pers: i64,
-        _round: fvm_shared::clock::ChainEpoch,
-        _entropy: &[u8],
+        pers: i64,
+        round: fvm_shared::clock::ChainEpoch,
+        entropy: &[u8],
     ) -> anyhow::Result<[u8; 32]> {
-        todo!()
+        Ok(draw_randomness(b"ticket", pers, round, entropy))
     }
 
     fn get_beacon_randomness(
         &self,
-        _pers: i64,
-        _round: fvm_shared::clock::ChainEpoch,
-        _entropy: &[u8],
+        pers: i64,
+        round: fvm_shared::clock::ChainEpoch,
+        entropy: &[u8],
     ) -> anyhow::Result<[u8; 32]> {
-        todo!()
+        Ok(draw_randomness(b"beacon", pers, round, entropy))
     }
 }
 
+/// Deterministic randomness returned by `DummyExterns`, derived like the node derives it from a
+/// ticket or beacon entry (`rbase`): the blake2b-256 hash of the personalization, the hash of
+/// `rbase`, the round and the entropy.
+pub fn draw_randomness(
+    rbase: &[u8],
+    pers: i64,
+    round: fvm_shared::clock::ChainEpoch,
+    entropy: &[u8],
+) -> [u8; 32] {
+    let params = blake2b_simd::Params::new().hash_length(32).clone();
+    let mut state = params.to_state();
+    state.update(&pers.to_be_bytes());
+    state.update(params.hash(rbase).as_bytes());
+    state.update(&round.to_be_bytes());
+    state.update(entropy);
+    state.finalize().as_bytes().try_into().unwrap()
+}
+
 impl Consensus for DummyExterns {
     fn verify_consensus_fault(
         &self,
@@ -184,11 +204,16 @@ impl Machine for DummyMachine {
 
     fn transfer(
         &mut self,
-        _from: fvm_shared::ActorID,
-        _to: fvm_shared::ActorID,
-        _value: &fvm_shared::econ::TokenAmount,
+        from: fvm_shared::ActorID,
+        to: fvm_shared::ActorID,
+        value: &fvm_shared::econ::TokenAmount,
     ) -> kernel::Result<()> {
-        todo!()
+        self.state_tree
+            .mutate_actor(from, |act| act.deduct_funds(value))?;
+        self.state_tree.mutate_actor(to, |act| {
+            act.deposit_funds(value);
+            Ok(())
+        })
     }
 
     fn into_store(self) -> Self::Blockstore {
@@ -208,9 +233,15 @@ impl Machine for DummyMachine {
     }
 }
 
+/// Behavior of the actor called through [`DummyCallManager`]'s `send`: it's given the kernel of
+/// the callee, and returns the exit code of the call.
+pub type DummyCallee = Box<dyn FnOnce(&mut dyn kernel::SelfOps) -> ExitCode>;
+
 /// Minimal *pseudo-functional* implementation CallManager
 pub struct DummyCallManager {
     pub machine: DummyMachine,
+    /// The actor invoked by the next `send`, if any.
+    pub callee: Option<DummyCallee>,
     pub gas_tracker: GasTracker,
     pub gas_premium: TokenAmount,
     pub origin: ActorID,
@@ -234,6 +265,7 @@ impl DummyCallManager {
         (
             Self {
                 machine: DummyMachine::new_stub().unwrap(),
+                callee: None,
                 gas_tracker: GasTracker::new(Gas::new(i64::MAX), Gas::new(0), false),
                 origin: 0,
                 nonce: 0,
@@ -254,6 +286,7 @@ impl DummyCallManager {
         (
             Self {
                 machine: DummyMachine::new_stub().unwrap(),
+                callee: None,
                 gas_tracker,
                 origin: 0,
                 nonce: 0,
@@ -285,6 +318,7 @@ impl CallManager for DummyCallManager {
         let limits = machine.new_limiter();
         Self {
             machine,
+            callee: None,
             gas_tracker: GasTracker::new(Gas::new(i64::MAX), Gas::new(0), false),
             gas_premium,
             origin,
@@ -295,26 +329,46 @@ impl CallManager for DummyCallManager {
         }
     }
 
+    /// Invokes the `callee` with a new kernel. Only ID addresses are supported.
     fn send<K: Kernel<CallManager = Self>>(
         &mut self,
-        _from: fvm_shared::ActorID,
-        _to: Address,
-        _method: fvm_shared::MethodNum,
-        _params: Option<kernel::Block>,
-        _value: &fvm_shared::econ::TokenAmount,
+        from: fvm_shared::ActorID,
+        to: Address,
+        method: fvm_shared::MethodNum,
+        params: Option<kernel::Block>,
+        value: &fvm_shared::econ::TokenAmount,
         _gas_limit: Option<Gas>,
     ) -> kernel::Result<InvocationResult> {
-        // Ok(InvocationResult::Return(None))
-        todo!()
+        let callee = self.callee.take().expect("no callee to send to");
+        let to = to.id().expect("dummy sends only support ID addresses");
+
+        let mut blocks = BlockRegistry::default();
+        if let Some(params) = params {
+            blocks.put(params)?;
+        }
+
+        // The kernel owns its call manager, so we swap a stub in while the callee runs.
+        let call_manager = std::mem::replace(self, DummyCallManager::new_stub().0);
+        let mut kernel = K::new(call_manager, blocks, from, to, method, value.clone());
+        let exit_code = callee(&mut kernel);
+        *self = kernel.into_inner().0;
+
+        Ok(InvocationResult {
+            exit_code,
+            value: None,
+        })
     }
 
     fn with_transaction(
         &mut self,
-        _read_only: bool,
-        _f: impl FnOnce(&mut Self) -> kernel::Result<InvocationResult>,
+        read_only: bool,
+        f: impl FnOnce(&mut Self) -> kernel::Result<InvocationResult>,
     ) -> kernel::Result<InvocationResult> {
-        // Ok(InvocationResult::Return(None))
-        todo!()
+        self.machine.state_tree.begin_transaction(read_only);
+        let res = f(self);
+        let revert = !matches!(&res, Ok(v) if v.exit_code.is_success());
+        self.machine.state_tree.end_transaction(revert)?;
+        res
     }
 
     fn finish(self) -> (FinishRet, Self::Machine) {
@@ -389,4 +443,6 @@ impl CallManager for DummyCallManager {
     fn append_event(&mut self, _evt: StampedEvent) {
         todo!()
     }
+
+    fn record_block_write(&mut self, _actor: ActorID, _size: u64) {}
 }
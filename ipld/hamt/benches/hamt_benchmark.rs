@@ -6,7 +6,7 @@ extern crate serde;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use fvm_ipld_encoding::tuple::*;
-use fvm_ipld_hamt::Hamt;
+use fvm_ipld_hamt::{BytesKey, Hamt};
 
 const ITEM_COUNT: u8 = 40;
 
@@ -83,6 +83,33 @@ fn delete(c: &mut Criterion) {
     });
 }
 
+fn get(c: &mut Criterion) {
+    let db = fvm_ipld_blockstore::MemoryBlockstore::default();
+    let mut a = Hamt::<_, _>::new(&db);
+    for i in 0..black_box(ITEM_COUNT) {
+        a.set(vec![i; 20].into(), BenchData::new(i)).unwrap();
+    }
+    let cid = a.flush().unwrap();
+    let a = Hamt::<_, BenchData>::load(&cid, &db).unwrap();
+
+    // Borrowed byte-slice keys don't need an owned `BytesKey` to be allocated per lookup.
+    c.bench_function("HAMT get (borrowed byte-slice key)", |b| {
+        b.iter(|| {
+            for i in 0..black_box(ITEM_COUNT) {
+                a.get(black_box([i; 20].as_ref())).unwrap();
+            }
+        })
+    });
+
+    c.bench_function("HAMT get (owned BytesKey)", |b| {
+        b.iter(|| {
+            for i in 0..black_box(ITEM_COUNT) {
+                a.get(&BytesKey::from(black_box([i; 20].as_ref()))).unwrap();
+            }
+        })
+    });
+}
+
 fn for_each(c: &mut Criterion) {
     let db = fvm_ipld_blockstore::MemoryBlockstore::default();
     let mut a = Hamt::<_, _>::new(&db);
@@ -99,5 +126,95 @@ fn for_each(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, insert, insert_load_flush, delete, for_each);
+fn clone_set(c: &mut Criterion) {
+    let db = fvm_ipld_blockstore::MemoryBlockstore::default();
+    let mut a = Hamt::<_, _>::new(&db);
+    for i in 0..10_000u32 {
+        a.set(i.to_be_bytes().to_vec().into(), BenchData::new(i as u8))
+            .unwrap();
+    }
+    a.flush().unwrap();
+    // Load every node, so they're all shared with the clones.
+    a.for_each(|_k, _v: &BenchData| Ok(())).unwrap();
+
+    c.bench_function("HAMT set on a clone of 10k entries", |b| {
+        b.iter(|| {
+            let mut a = black_box(&a).clone();
+            a.set(
+                black_box(0u32.to_be_bytes().to_vec().into()),
+                black_box(BenchData::new(1)),
+            )
+            .unwrap();
+        })
+    });
+}
+
+#[cfg(feature = "parallel")]
+fn flush_parallel(c: &mut Criterion) {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use cid::Cid;
+    use criterion::BatchSize;
+    use fvm_ipld_blockstore::Blockstore;
+
+    /// A thread-safe blockstore, as flushing in parallel requires.
+    #[derive(Default)]
+    struct SyncBlockstore(Mutex<HashMap<Cid, Vec<u8>>>);
+
+    impl Blockstore for SyncBlockstore {
+        fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().unwrap().get(k).cloned())
+        }
+
+        fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+            self.0.lock().unwrap().insert(*k, block.into());
+            Ok(())
+        }
+    }
+
+    let db = SyncBlockstore::default();
+    let build = || {
+        let mut a = Hamt::<_, _>::new(&db);
+        for i in 0..100_000u32 {
+            a.set(i.to_be_bytes().to_vec().into(), BenchData::new(i as u8))
+                .unwrap();
+        }
+        a
+    };
+
+    c.bench_function("HAMT flush 100k entries (serial)", |b| {
+        b.iter_batched(build, |mut a| a.flush().unwrap(), BatchSize::LargeInput)
+    });
+
+    c.bench_function("HAMT flush 100k entries (parallel)", |b| {
+        b.iter_batched(
+            build,
+            |mut a| a.flush_parallel().unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+#[cfg(not(feature = "parallel"))]
+criterion_group!(
+    benches,
+    insert,
+    insert_load_flush,
+    delete,
+    get,
+    for_each,
+    clone_set
+);
+#[cfg(feature = "parallel")]
+criterion_group!(
+    benches,
+    insert,
+    insert_load_flush,
+    delete,
+    get,
+    for_each,
+    clone_set,
+    flush_parallel
+);
 criterion_main!(benches);
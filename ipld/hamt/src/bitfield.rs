@@ -96,6 +96,12 @@ impl Bitfield {
         ])
     }
 
+    /// Returns true if no bit at or above `1 << bit_width` is set, i.e., if this bitfield is
+    /// valid for a node with `bit_width` bits of index.
+    pub fn fits_bit_width(&self, bit_width: u32) -> bool {
+        bit_width >= 8 || self.and(&Bitfield::zero().set_bits_le(1 << bit_width)) == *self
+    }
+
     pub fn zero() -> Self {
         Bitfield([0, 0, 0, 0])
     }
@@ -165,6 +171,20 @@ mod tests {
         assert!(!b.test_bit(18));
     }
 
+    #[test]
+    fn test_fits_bit_width() {
+        let mut b = Bitfield::zero();
+        assert!(b.fits_bit_width(1));
+
+        b.set_bit(31);
+        assert!(!b.fits_bit_width(4));
+        assert!(b.fits_bit_width(5));
+
+        b.set_bit(255);
+        assert!(!b.fits_bit_width(7));
+        assert!(b.fits_bit_width(8));
+    }
+
     #[test]
     fn test_cbor_serialization() {
         let mut b0 = Bitfield::zero();
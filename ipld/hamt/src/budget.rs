@@ -0,0 +1,47 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::cell::Cell;
+
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+
+use crate::Error;
+
+/// Blockstore wrapper limiting the number of blocks which can be fetched through it.
+///
+/// Once the budget is exhausted, reads fail with [`Error::FetchBudgetExceeded`]. A budget of `None`
+/// doesn't limit anything.
+pub(crate) struct FetchBudget<BS> {
+    store: BS,
+    remaining: Cell<Option<usize>>,
+}
+
+impl<BS> FetchBudget<BS> {
+    pub fn new(store: BS, budget: Option<usize>) -> Self {
+        Self {
+            store,
+            remaining: Cell::new(budget),
+        }
+    }
+}
+
+impl<BS> Blockstore for FetchBudget<BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(remaining) = self.remaining.get() {
+            if remaining == 0 {
+                return Err(Error::FetchBudgetExceeded.into());
+            }
+            self.remaining.set(Some(remaining - 1));
+        }
+        self.store.get(k)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        self.store.put_keyed(k, block)
+    }
+}
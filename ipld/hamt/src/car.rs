@@ -0,0 +1,76 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::collections::HashSet;
+use std::io::Write;
+
+use cid::Cid;
+use futures::executor::block_on;
+use futures::io::AllowStdIo;
+use futures::stream;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_car::CarHeader;
+use fvm_ipld_encoding::from_slice;
+use serde::de::DeserializeOwned;
+
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::Error;
+
+/// Writes a CARv1 file to `w`, with `root` as its only root, followed by the blocks of the root
+/// node and of every node reachable from it, read from `store`. Each node is written once, parents
+/// before their children.
+pub(crate) fn write_car<K, V, H, S, W>(root: Cid, store: &S, w: &mut W) -> Result<(), Error>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    S: Blockstore,
+    W: Write + Send,
+{
+    // Nodes are read lazily as the CAR is written. The first error stops the stream, and is
+    // returned once the writer is done.
+    let mut error = None;
+    let mut seen = HashSet::new();
+    let mut stack = vec![root];
+    let blocks = std::iter::from_fn(|| loop {
+        let cid = stack.pop()?;
+        if !seen.insert(cid) {
+            continue;
+        }
+        match next_block::<K, V, H, _>(cid, store, &mut stack) {
+            Ok(block) => return Some((cid, block)),
+            Err(e) => {
+                error = Some(e);
+                return None;
+            }
+        }
+    });
+
+    block_on(
+        CarHeader::from(vec![root])
+            .write_stream_async(&mut AllowStdIo::new(w), &mut stream::iter(blocks)),
+    )
+    .map_err(anyhow::Error::from)?;
+    error.map_or(Ok(()), Err)
+}
+
+/// Reads the block of the node `cid` from `store`, pushing its children onto `stack` in reverse,
+/// so they're written in bucket order.
+fn next_block<K, V, H, S>(cid: Cid, store: &S, stack: &mut Vec<Cid>) -> Result<Vec<u8>, Error>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    S: Blockstore,
+{
+    let block = store
+        .get(&cid)?
+        .ok_or_else(|| Error::CidNotFound(cid.to_string()))?;
+    let node: Node<K, V, H> = from_slice(&block)?;
+    for pointer in node.pointers.iter().rev() {
+        if let Pointer::Link { cid, .. } = pointer {
+            stack.push(*cid);
+        }
+    }
+    Ok(block)
+}
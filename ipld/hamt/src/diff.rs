@@ -0,0 +1,163 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::sync::Arc;
+
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::CborStore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::{Config, Error, Hash, HashAlgorithm};
+
+/// A change to an entry between two HAMTs.
+///
+/// Created by [`Hamt::diff`](crate::Hamt::diff).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    /// The entry only exists in the other HAMT.
+    Added(K, V),
+    /// The entry only exists in this HAMT.
+    Removed(K, V),
+    /// The key exists in both HAMTs, with different values.
+    Modified { key: K, from: V, to: V },
+}
+
+/// Appends the changes between the subtrees rooted at `ours` and `theirs` to `changes`.
+///
+/// Both nodes must be at the same depth, so that a bucket index leads to keys with the same hash
+/// prefix on both sides. Pairs of links to the same CID are skipped without being loaded.
+pub(crate) fn diff_nodes<K, V, H, S>(
+    ours: &Node<K, V, H>,
+    theirs: &Node<K, V, H>,
+    conf: &Config,
+    store: &S,
+    changes: &mut Vec<Change<K, V>>,
+) -> Result<(), Error>
+where
+    K: Hash + Eq + PartialOrd + Clone + Serialize + DeserializeOwned,
+    V: PartialEq + Clone + Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+    S: Blockstore,
+{
+    for idx in 0..1 << conf.bit_width {
+        match (ours.pointer_at(idx), theirs.pointer_at(idx)) {
+            (None, None) => {}
+            (Some(Pointer::Link { cid: a, .. }), Some(Pointer::Link { cid: b, .. })) if a == b => {}
+            (Some(a), Some(b))
+                if !matches!(a, Pointer::Values(_)) && !matches!(b, Pointer::Values(_)) =>
+            {
+                match (load(a, store)?, load(b, store)?) {
+                    (Some(a), Some(b)) => diff_nodes(a, b, conf, store, changes)?,
+                    (a, b) => diff_entries(entries(a, store)?, entries(b, store)?, changes),
+                }
+            }
+            (a, b) => {
+                let a = a.map(|p| entries_at(p, store)).transpose()?;
+                let b = b.map(|p| entries_at(p, store)).transpose()?;
+                diff_entries(a.unwrap_or_default(), b.unwrap_or_default(), changes);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Appends the changes between two sets of entries to `changes`. At least one of them is
+/// expected to be small (e.g., a single bucket), as entries are matched pairwise.
+fn diff_entries<K, V>(ours: Vec<(&K, &V)>, theirs: Vec<(&K, &V)>, changes: &mut Vec<Change<K, V>>)
+where
+    K: Eq + Clone,
+    V: PartialEq + Clone,
+{
+    for &(key, from) in &ours {
+        match theirs.iter().find(|(k, _)| *k == key) {
+            None => changes.push(Change::Removed(key.clone(), from.clone())),
+            Some(&(_, to)) if to != from => changes.push(Change::Modified {
+                key: key.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for &(key, value) in &theirs {
+        if !ours.iter().any(|(k, _)| *k == key) {
+            changes.push(Change::Added(key.clone(), value.clone()));
+        }
+    }
+}
+
+/// Collects the entries under `pointer`, loading nodes as needed.
+fn entries_at<'a, K, V, H, S>(
+    pointer: &'a Pointer<K, V, H>,
+    store: &S,
+) -> Result<Vec<(&'a K, &'a V)>, Error>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    S: Blockstore,
+{
+    match pointer {
+        Pointer::Values(kvs) => Ok(kvs.iter().map(|kv| (kv.key(), kv.value())).collect()),
+        _ => entries(load(pointer, store)?, store),
+    }
+}
+
+/// Collects the entries of the subtree rooted at `node`, if any, loading nodes as needed.
+fn entries<'a, K, V, H, S>(
+    node: Option<&'a Node<K, V, H>>,
+    store: &S,
+) -> Result<Vec<(&'a K, &'a V)>, Error>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    S: Blockstore,
+{
+    let mut entries = Vec::new();
+    let mut stack: Vec<&Node<K, V, H>> = node.into_iter().collect();
+    while let Some(node) = stack.pop() {
+        for pointer in &node.pointers {
+            match pointer {
+                Pointer::Values(kvs) => {
+                    entries.extend(kvs.iter().map(|kv| (kv.key(), kv.value())));
+                }
+                _ => stack.extend(load(pointer, store)?),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns the node a link or dirty pointer points to, loading it (and caching it in the link)
+/// if needed. Returns `None` for dead links when ignoring them, and for values.
+fn load<'a, K, V, H, S>(
+    pointer: &'a Pointer<K, V, H>,
+    store: &S,
+) -> Result<Option<&'a Node<K, V, H>>, Error>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    S: Blockstore,
+{
+    match pointer {
+        Pointer::Values(_) => Ok(None),
+        Pointer::Dirty(node) => Ok(Some(node)),
+        Pointer::Link { cid, cache } => {
+            if let Some(cached_node) = cache.get() {
+                return Ok(Some(cached_node));
+            }
+            let node = match store.get_cbor(cid)? {
+                Some(node) => node,
+                #[cfg(not(feature = "ignore-dead-links"))]
+                None => return Err(Error::CidNotFound(cid.to_string())),
+                #[cfg(feature = "ignore-dead-links")]
+                None => return Ok(None),
+            };
+            // Ignore error intentionally, the cache value will always be the same
+            Ok(Some(cache.get_or_init(|| Arc::new(node))))
+        }
+    }
+}
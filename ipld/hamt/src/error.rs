@@ -9,6 +9,7 @@ use thiserror::Error;
 
 /// HAMT Error
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     /// Maximum depth error
     #[error("Maximum depth reached")]
@@ -22,6 +23,16 @@ pub enum Error {
     /// Cid not found in store error
     #[error("Cid ({0}) did not match any in database")]
     CidNotFound(String),
+    /// An operation tried to fetch more nodes from the store than allowed
+    #[error("Node fetch budget exceeded")]
+    FetchBudgetExceeded,
+    /// A node has buckets out of the range allowed by the configured bit width, i.e., the HAMT
+    /// was built with a larger bit width
+    #[error("HAMT node doesn't match the configured bit width of {0}")]
+    BitWidthMismatch(u32),
+    /// An insertion would exceed one of the limits set in the [`Config`](crate::Config)
+    #[error("HAMT {limit} limit of {max} exceeded")]
+    LimitExceeded { limit: &'static str, max: usize },
     /// Dynamic error for when the error needs to be forwarded as is.
     #[error("{0}")]
     Dynamic(anyhow::Error),
@@ -3,18 +3,29 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 
 use std::borrow::Borrow;
+use std::cell::Cell;
+#[cfg(feature = "car")]
+use std::io::Write;
 use std::marker::PhantomData;
+use std::ops::Bound;
 
 use cid::Cid;
 use forest_hash_utils::BytesKey;
 use fvm_ipld_blockstore::Blockstore;
-use fvm_ipld_encoding::CborStore;
-use multihash::Code;
+use fvm_ipld_encoding::{to_vec, CborStore};
 use serde::de::DeserializeOwned;
 use serde::{Serialize, Serializer};
 
+use crate::budget::FetchBudget;
+#[cfg(feature = "car")]
+use crate::car;
+use crate::diff::{self, Change};
+use crate::hash_bits::HashBits;
+use crate::iter::{Cursor, IterFrom, IterWithLocation};
 use crate::node::Node;
-use crate::{Config, Error, Hash, HashAlgorithm, Sha256};
+use crate::stats::HamtStats;
+use crate::subtree::SubtreeView;
+use crate::{Config, Error, Hash, HashAlgorithm, HashedKey, KeyValuePair, Sha256};
 
 /// Implementation of the HAMT data structure for IPLD.
 ///
@@ -40,6 +51,11 @@ pub struct Hamt<BS, V, K = BytesKey, H = Sha256> {
     hash: PhantomData<H>,
     /// Remember the last flushed CID until it changes.
     flushed_cid: Option<Cid>,
+    /// Maximum number of nodes a single operation may fetch from the store.
+    fetch_budget: Option<usize>,
+    /// Number of entries, if known. It's unknown after loading a root from the store, until the
+    /// entries are counted.
+    len: Cell<Option<usize>>,
 }
 
 impl<BS, V, K, H> Serialize for Hamt<BS, V, K, H>
@@ -56,6 +72,45 @@ where
     }
 }
 
+/// Clones the in-memory state of the HAMT: its root node, its pending changes, and its
+/// configuration. Both HAMTs share the same backing store, so the nodes flushed by one of them can
+/// be loaded by the other.
+///
+/// Child nodes, whether loaded or modified, aren't copied but shared between both HAMTs. When one
+/// of them modifies a shared node, it copies it first, so only the nodes on the path to the
+/// modified key are duplicated, and the copies don't need to be loaded from the store again.
+/// Cloning a HAMT is therefore cheap, as only its root node is copied.
+///
+/// # Examples
+///
+/// ```
+/// use fvm_ipld_hamt::Hamt;
+///
+/// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+///
+/// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+/// map.set(1, "a".to_string()).unwrap();
+/// map.flush().unwrap();
+///
+/// let mut other = map.clone();
+/// other.set(1, "b".to_string()).unwrap();
+/// assert_eq!(map.get(&1).unwrap(), Some(&"a".to_string()));
+/// assert_eq!(other.get(&1).unwrap(), Some(&"b".to_string()));
+/// ```
+impl<BS: Clone, V: Clone, K: Clone, H> Clone for Hamt<BS, V, K, H> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            store: self.store.clone(),
+            conf: self.conf.clone(),
+            hash: Default::default(),
+            flushed_cid: self.flushed_cid,
+            fetch_budget: self.fetch_budget,
+            len: self.len.clone(),
+        }
+    }
+}
+
 impl<K: PartialEq, V: PartialEq, S: Blockstore, H: HashAlgorithm> PartialEq for Hamt<S, V, K, H> {
     fn eq(&self, other: &Self) -> bool {
         self.root == other.root
@@ -80,6 +135,8 @@ where
             conf,
             hash: Default::default(),
             flushed_cid: None,
+            fetch_budget: None,
+            len: Cell::new(Some(0)),
         }
     }
 
@@ -100,15 +157,25 @@ where
     }
 
     /// Lazily instantiate a hamt from this root Cid with a specified parameters.
+    ///
+    /// The bit width of a HAMT isn't recorded in its nodes, so it must match the one the HAMT was
+    /// built with. Loading fails with [`Error::BitWidthMismatch`] if the root node has more
+    /// buckets than `conf.bit_width` allows, but a bit width *larger* than the one the HAMT was
+    /// built with can't be detected, and lookups then silently miss existing keys.
     pub fn load_with_config(cid: &Cid, store: BS, conf: Config) -> Result<Self, Error> {
-        match store.get_cbor(cid)? {
-            Some(root) => Ok(Self {
-                root,
-                store,
-                conf,
-                hash: Default::default(),
-                flushed_cid: Some(*cid),
-            }),
+        match store.get_cbor::<Node<K, V, H>>(cid)? {
+            Some(root) => {
+                root.check_bit_width(conf.bit_width)?;
+                Ok(Self {
+                    root,
+                    store,
+                    conf,
+                    hash: Default::default(),
+                    flushed_cid: Some(*cid),
+                    fetch_budget: None,
+                    len: Cell::new(None),
+                })
+            }
             None => Err(Error::CidNotFound(cid.to_string())),
         }
     }
@@ -124,12 +191,29 @@ where
         )
     }
 
+    /// Limits the number of nodes any single operation (e.g., a `get`, `set` or a whole `for_each`)
+    /// may fetch from the store. Operations exceeding the budget fail with
+    /// [`Error::FetchBudgetExceeded`].
+    ///
+    /// Nodes which have already been loaded are cached and don't count towards the budget. This is
+    /// meant to bound the work done when exploring HAMTs from untrusted sources.
+    pub fn with_fetch_budget(mut self, budget: usize) -> Self {
+        self.fetch_budget = Some(budget);
+        self
+    }
+
     /// Sets the root based on the Cid of the root node using the Hamt store
+    ///
+    /// As with [`Hamt::load_with_config`], the HAMT at `cid` must have been built with the
+    /// configured bit width. Fails with [`Error::BitWidthMismatch`] if the root node has more
+    /// buckets than the bit width allows.
     pub fn set_root(&mut self, cid: &Cid) -> Result<(), Error> {
-        match self.store.get_cbor(cid)? {
+        match self.store.get_cbor::<Node<K, V, H>>(cid)? {
             Some(root) => {
+                root.check_bit_width(self.conf.bit_width)?;
                 self.root = root;
                 self.flushed_cid = Some(*cid);
+                self.len.set(None);
             }
             None => return Err(Error::CidNotFound(cid.to_string())),
         }
@@ -142,6 +226,30 @@ where
         &self.store
     }
 
+    /// Returns the CID of the root node if the HAMT was loaded or flushed, and hasn't been
+    /// modified since. Unlike [`Hamt::flush`], this never writes to the store.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+    /// map.set(1, "a".to_string()).unwrap();
+    /// assert_eq!(map.root_cid(), None);
+    ///
+    /// let cid = map.flush().unwrap();
+    /// assert_eq!(map.root_cid(), Some(cid));
+    ///
+    /// let loaded: Hamt<_, String, usize> = Hamt::load(&cid, &store).unwrap();
+    /// assert_eq!(loaded.root_cid(), Some(cid));
+    /// ```
+    pub fn root_cid(&self) -> Option<Cid> {
+        self.flushed_cid
+    }
+
     /// Inserts a key-value pair into the HAMT.
     ///
     /// If the HAMT did not have this key present, `None` is returned.
@@ -164,21 +272,136 @@ where
     /// map.set(37, "b".to_string()).unwrap();
     /// map.set(37, "c".to_string()).unwrap();
     /// ```
+    ///
+    /// Fails with [`Error::LimitExceeded`] if the insertion would exceed the
+    /// [`Config::max_entries`] or [`Config::max_value_bytes`] limits.
     pub fn set(&mut self, key: K, value: V) -> Result<Option<V>, Error>
     where
-        V: PartialEq,
+        K: Clone,
+        V: PartialEq + Clone,
     {
+        self.check_value_size(&value)?;
+        let at_capacity = self.at_capacity()?;
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
         let (old, modified) = self
             .root
-            .set(key, value, self.store.borrow(), &self.conf, true)?;
+            .set(key, value, &store, &self.conf, true, at_capacity)?;
 
         if modified {
             self.flushed_cid = None;
+            if old.is_none() {
+                self.len.set(self.len.get().map(|len| len + 1));
+            }
         }
 
         Ok(old)
     }
 
+    /// Inserts a key-value pair into the HAMT, returning whether the HAMT was actually modified.
+    ///
+    /// If the key is already present with an equal value, nothing is written: no node along the
+    /// path is marked as dirty and the last flushed CID stays valid, so the next flush is free.
+    ///
+    /// Values are compared with `PartialEq`, which is assumed to be consistent with the
+    /// serialized bytes of the values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(store);
+    /// assert!(map.put_if_changed(37, "a".to_string()).unwrap());
+    /// assert!(!map.put_if_changed(37, "a".to_string()).unwrap());
+    /// assert!(map.put_if_changed(37, "b".to_string()).unwrap());
+    /// assert_eq!(map.get(&37).unwrap(), Some(&"b".to_string()));
+    /// ```
+    pub fn put_if_changed(&mut self, key: K, value: V) -> Result<bool, Error>
+    where
+        K: Clone,
+        V: PartialEq + Clone,
+    {
+        self.check_value_size(&value)?;
+        let at_capacity = self.at_capacity()?;
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let (old, modified) = self
+            .root
+            .set(key, value, &store, &self.conf, true, at_capacity)?;
+
+        if modified {
+            self.flushed_cid = None;
+            if old.is_none() {
+                self.len.set(self.len.get().map(|len| len + 1));
+            }
+        }
+
+        Ok(modified)
+    }
+
+    /// Modifies the value at `key` in place, or inserts one if the key isn't present.
+    ///
+    /// If the key is present, `f` is called with a mutable reference to its value and its return
+    /// value is ignored. Otherwise, `f` is called with `None`, and the value it returns, if any, is
+    /// inserted. Unlike a [`Hamt::get`] followed by a [`Hamt::set`], this walks the HAMT only once
+    /// and doesn't clone the value.
+    ///
+    /// Returns whether the key was present.
+    ///
+    /// The limits of the [`Config`] are only checked for inserted values: values modified in place
+    /// may grow past [`Config::max_value_bytes`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, u64, usize> = Hamt::new(store);
+    /// let incr = |v: Option<&mut u64>| match v {
+    ///     Some(v) => {
+    ///         *v += 1;
+    ///         None
+    ///     }
+    ///     None => Some(1),
+    /// };
+    /// assert!(!map.modify(1, incr).unwrap());
+    /// assert!(map.modify(1, incr).unwrap());
+    /// assert_eq!(map.get(&1).unwrap(), Some(&2));
+    /// ```
+    pub fn modify<F>(&mut self, key: K, f: F) -> Result<bool, Error>
+    where
+        K: Clone,
+        V: PartialEq + Clone,
+        F: FnOnce(Option<&mut V>) -> Option<V>,
+    {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let hash = H::hash(&key);
+        match self
+            .root
+            .modify_in_place(&mut HashBits::new(&hash), &self.conf, &key, &store, f)?
+        {
+            Ok(_) => {
+                self.flushed_cid = None;
+                Ok(true)
+            }
+            Err(f) => {
+                if let Some(value) = f(None) {
+                    self.check_value_size(&value)?;
+                    let at_capacity = self.at_capacity()?;
+                    let store = FetchBudget::new(&self.store, self.fetch_budget);
+                    self.root
+                        .set(key, value, &store, &self.conf, true, at_capacity)?;
+                    self.flushed_cid = None;
+                    self.len.set(self.len.get().map(|len| len + 1));
+                }
+                Ok(false)
+            }
+        }
+    }
+
     /// Inserts a key-value pair into the HAMT only if that key does not already exist.
     ///
     /// If the HAMT did not have this key present, `true` is returned and the key/value is added.
@@ -205,22 +428,52 @@ where
     /// let c = map.set_if_absent(30, "c".to_string()).unwrap();
     /// assert_eq!(c, true);
     /// ```
+    ///
+    /// Fails with [`Error::LimitExceeded`] if the insertion would exceed the
+    /// [`Config::max_entries`] or [`Config::max_value_bytes`] limits.
     pub fn set_if_absent(&mut self, key: K, value: V) -> Result<bool, Error>
     where
-        V: PartialEq,
+        K: Clone,
+        V: PartialEq + Clone,
     {
+        self.check_value_size(&value)?;
+        let at_capacity = self.at_capacity()?;
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
         let set = self
             .root
-            .set(key, value, self.store.borrow(), &self.conf, false)
+            .set(key, value, &store, &self.conf, false, at_capacity)
             .map(|(_, set)| set)?;
 
         if set {
             self.flushed_cid = None;
+            self.len.set(self.len.get().map(|len| len + 1));
         }
 
         Ok(set)
     }
 
+    /// Fails with [`Error::LimitExceeded`] if `value` is larger than [`Config::max_value_bytes`].
+    fn check_value_size(&self, value: &V) -> Result<(), Error> {
+        if let Some(max) = self.conf.max_value_bytes {
+            if to_vec(value)?.len() > max {
+                return Err(Error::LimitExceeded {
+                    limit: "max_value_bytes",
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the HAMT holds [`Config::max_entries`] entries, so new keys must be
+    /// rejected. Whether a key is new is only known once the insertion reaches its bucket.
+    fn at_capacity(&self) -> Result<bool, Error> {
+        match self.conf.max_entries {
+            Some(max) => Ok(self.count()? >= max),
+            None => Ok(false),
+        }
+    }
+
     /// Returns a reference to the value corresponding to the key.
     ///
     /// The key may be any borrowed form of the map's key type, but
@@ -240,6 +493,19 @@ where
     /// assert_eq!(map.get(&1).unwrap(), Some(&"a".to_string()));
     /// assert_eq!(map.get(&2).unwrap(), None);
     /// ```
+    ///
+    /// With the default [`BytesKey`] keys, lookups can be made with a plain byte slice, without
+    /// allocating an owned key:
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, u64> = Hamt::new(&store);
+    /// map.set(b"foo".to_vec().into(), 1).unwrap();
+    /// assert_eq!(map.get(&b"foo"[..]).unwrap(), Some(&1));
+    /// ```
     #[inline]
     pub fn get<Q: ?Sized>(&self, k: &Q) -> Result<Option<&V>, Error>
     where
@@ -247,7 +513,8 @@ where
         Q: Hash + Eq,
         V: DeserializeOwned,
     {
-        match self.root.get(k, self.store.borrow(), &self.conf)? {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        match self.root.get(k, &store, &self.conf)? {
             Some(v) => Ok(Some(v)),
             None => Ok(None),
         }
@@ -278,7 +545,130 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
-        Ok(self.root.get(k, self.store.borrow(), &self.conf)?.is_some())
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        Ok(self.root.get(k, &store, &self.conf)?.is_some())
+    }
+
+    /// Returns `true` if all of the given keys exist in the HAMT.
+    ///
+    /// Like [`Hamt::get_many`], lookups are made in a single traversal, which stops at the first
+    /// absent key. The fetch budget, if any, is shared by all lookups.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    /// use std::rc::Rc;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(Rc::new(store));
+    /// map.set(1, "a".to_string()).unwrap();
+    /// map.set(2, "b".to_string()).unwrap();
+    /// assert_eq!(map.contains_all(&[&1, &2]).unwrap(), true);
+    /// assert_eq!(map.contains_all(&[&1, &3]).unwrap(), false);
+    /// ```
+    pub fn contains_all<Q: ?Sized>(&self, keys: &[&Q]) -> Result<bool, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        Ok(!self.lookup_many(keys, Some(false))?.1)
+    }
+
+    /// Returns `true` if any of the given keys exists in the HAMT.
+    ///
+    /// Like [`Hamt::get_many`], lookups are made in a single traversal, which stops at the first
+    /// present key. The fetch budget, if any, is shared by all lookups.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    /// use std::rc::Rc;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(Rc::new(store));
+    /// map.set(1, "a".to_string()).unwrap();
+    /// assert_eq!(map.contains_any(&[&3, &1]).unwrap(), true);
+    /// assert_eq!(map.contains_any(&[&2, &3]).unwrap(), false);
+    /// ```
+    pub fn contains_any<Q: ?Sized>(&self, keys: &[&Q]) -> Result<bool, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        Ok(self.lookup_many(keys, Some(true))?.1)
+    }
+
+    /// Returns the values corresponding to each of the given keys, in the same order as `keys`.
+    ///
+    /// Unlike calling [`Hamt::get`] for each key, lookups are sorted by key hash and made in a
+    /// single traversal, so nodes shared by several keys are only walked (and loaded) once. The
+    /// fetch budget, if any, is shared by all lookups.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    /// use std::rc::Rc;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(Rc::new(store));
+    /// map.set(1, "a".to_string()).unwrap();
+    /// map.set(2, "b".to_string()).unwrap();
+    /// assert_eq!(
+    ///     map.get_many(&[&2, &3, &1]).unwrap(),
+    ///     vec![Some(&"b".to_string()), None, Some(&"a".to_string())]
+    /// );
+    /// ```
+    pub fn get_many<Q: ?Sized>(&self, keys: &[&Q]) -> Result<Vec<Option<&V>>, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        Ok(self
+            .lookup_many(keys, None)?
+            .0
+            .into_iter()
+            .map(|kv| kv.map(|kv| kv.value()))
+            .collect())
+    }
+
+    /// Looks up the given keys in a single traversal, returning the entries found in the same
+    /// order as `keys`, and whether the traversal stopped early at a key whose presence matches
+    /// `stop_on`. See [`Node::get_many_values`].
+    #[allow(clippy::type_complexity)]
+    fn lookup_many<Q: ?Sized>(
+        &self,
+        keys: &[&Q],
+        stop_on: Option<bool>,
+    ) -> Result<(Vec<Option<&KeyValuePair<K, V>>>, bool), Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let hashes: Vec<HashedKey> = keys.iter().map(|k| H::hash(*k)).collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_unstable_by_key(|&i| hashes[i]);
+        let mut lookups: Vec<_> = order
+            .into_iter()
+            .map(|i| (HashBits::new(&hashes[i]), i))
+            .collect();
+
+        let mut found = vec![None; keys.len()];
+        let stopped = self.root.get_many_values(
+            &mut lookups,
+            keys,
+            &self.conf,
+            &store,
+            &mut found,
+            stop_on,
+        )?;
+        Ok((found, stopped))
     }
 
     /// Removes a key from the HAMT, returning the value at the key if the key
@@ -303,34 +693,320 @@ where
     /// ```
     pub fn delete<Q: ?Sized>(&mut self, k: &Q) -> Result<Option<(K, V)>, Error>
     where
-        K: Borrow<Q>,
+        K: Borrow<Q> + Clone,
+        V: Clone,
         Q: Hash + Eq,
     {
-        let deleted = self.root.remove_entry(k, self.store.borrow(), &self.conf)?;
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let deleted = self.root.remove_entry(k, &store, &self.conf)?;
 
         if deleted.is_some() {
             self.flushed_cid = None;
+            self.len.set(self.len.get().map(|len| len - 1));
         }
 
         Ok(deleted)
     }
 
+    /// Removes the given keys from the HAMT, returning the removed entries in the same order as
+    /// `keys`. If a key is given more than once, only its first occurrence gets the entry.
+    ///
+    /// Like [`Hamt::get_many`], deletions are sorted by key hash and made in a single traversal,
+    /// so nodes shared by several keys are only walked (and loaded, and modified) once. The HAMT
+    /// ends up in the same state as if the keys had been deleted one by one. If an error occurs,
+    /// some of the keys may already have been removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    /// use std::rc::Rc;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(Rc::new(store));
+    /// map.set(1, "a".to_string()).unwrap();
+    /// map.set(2, "b".to_string()).unwrap();
+    /// assert_eq!(
+    ///     map.delete_many(&[&2, &3, &1]).unwrap(),
+    ///     vec![Some((2, "b".to_string())), None, Some((1, "a".to_string()))]
+    /// );
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn delete_many<Q: ?Sized>(&mut self, keys: &[&Q]) -> Result<Vec<Option<(K, V)>>, Error>
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+        Q: Hash + Eq,
+    {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let hashes: Vec<HashedKey> = keys.iter().map(|k| H::hash(*k)).collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        // Stable, so that the first occurrence of a duplicated key gets the removed entry.
+        order.sort_by_key(|&i| hashes[i]);
+        let mut deletions: Vec<_> = order
+            .into_iter()
+            .map(|i| (HashBits::new(&hashes[i]), i))
+            .collect();
+
+        let mut removed: Vec<Option<(K, V)>> =
+            std::iter::repeat_with(|| None).take(keys.len()).collect();
+        let deleted = self.root.remove_many_entries(
+            &mut deletions,
+            keys,
+            &self.conf,
+            0,
+            &store,
+            &mut removed,
+        )?;
+
+        if deleted {
+            self.flushed_cid = None;
+            let count = removed.iter().filter(|entry| entry.is_some()).count();
+            self.len.set(self.len.get().map(|len| len - count));
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the entries for which `f` returns false, returning how many were removed.
+    ///
+    /// This visits every entry, loading the whole HAMT from the store, but walks it only once:
+    /// the nodes which lost entries are rebuilt on the way back up, and the others are left
+    /// untouched. If an error occurs, some of the entries may already have been removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, u64, usize> = Hamt::new(store);
+    /// for i in 0..10 {
+    ///     map.set(i, i as u64 * 10).unwrap();
+    /// }
+    /// assert_eq!(map.retain(|_, v| *v >= 50).unwrap(), 5);
+    /// assert_eq!(map.get(&4).unwrap(), None);
+    /// assert_eq!(map.get(&5).unwrap(), Some(&50));
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F) -> Result<usize, Error>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let removed = self.root.retain_entries(&mut f, &self.conf, 0, &store)?;
+
+        if removed > 0 {
+            self.flushed_cid = None;
+            self.len.set(self.len.get().map(|len| len - removed));
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes all entries from the HAMT, keeping its store and configuration.
+    ///
+    /// Returns the CID of the root before clearing, if it had been flushed since it was last
+    /// modified, so the previous state can still be loaded. Nodes of the previous state aren't
+    /// removed from the store.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+    /// map.set(1, "a".to_string()).unwrap();
+    /// let cid = map.flush().unwrap();
+    ///
+    /// assert_eq!(map.clear(), Some(cid));
+    /// assert!(map.is_empty());
+    ///
+    /// let old: Hamt<_, String, usize> = Hamt::load(&cid, &store).unwrap();
+    /// assert_eq!(old.get(&1).unwrap(), Some(&"a".to_string()));
+    /// ```
+    pub fn clear(&mut self) -> Option<Cid> {
+        self.root = Node::default();
+        self.len.set(Some(0));
+        self.flushed_cid.take()
+    }
+
     /// Flush root and return Cid for hamt
-    pub fn flush(&mut self) -> Result<Cid, Error> {
+    ///
+    /// Only the nodes modified since the last flush are written to the store, so flushing
+    /// repeatedly during a long batch of changes is cheap. Each returned root can be persisted as
+    /// a checkpoint to resume the batch from.
+    pub fn flush(&mut self) -> Result<Cid, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
         if let Some(cid) = self.flushed_cid {
             return Ok(cid);
         }
-        self.root.flush(self.store.borrow())?;
-        let cid = self.store.put_cbor(&self.root, Code::Blake2b256)?;
+        self.root.flush(self.store.borrow(), self.conf.hash_code)?;
+        let cid = self.store.put_cbor(&self.root, self.conf.hash_code)?;
         self.flushed_cid = Some(cid);
         Ok(cid)
     }
 
+    /// Like [`Hamt::flush`], but flushes independent dirty subtrees concurrently on the rayon
+    /// thread pool. This produces the same root, and is worth it for large batches of changes.
+    #[cfg(feature = "parallel")]
+    pub fn flush_parallel(&mut self) -> Result<Cid, Error>
+    where
+        K: Send + Sync + Clone,
+        V: Send + Sync + Clone,
+        BS: Sync,
+        H: Send + Sync,
+    {
+        if let Some(cid) = self.flushed_cid {
+            return Ok(cid);
+        }
+        self.root
+            .flush_parallel(self.store.borrow(), self.conf.hash_code)?;
+        let cid = self.store.put_cbor(&self.root, self.conf.hash_code)?;
+        self.flushed_cid = Some(cid);
+        Ok(cid)
+    }
+
+    /// Flushes the HAMT and returns the encoded root node.
+    ///
+    /// The shape of a HAMT only depends on its entries (and configuration), not on the sequence of
+    /// operations which built it, and the root node links to its children by CID. So two HAMTs
+    /// with the same entries produce identical bytes, which also hash to the CID returned by
+    /// [`Hamt::flush`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut a: Hamt<_, _, usize> = Hamt::new(&store);
+    /// a.set(1, "a".to_string()).unwrap();
+    /// a.set(2, "b".to_string()).unwrap();
+    ///
+    /// let mut b: Hamt<_, _, usize> = Hamt::new(&store);
+    /// b.set(2, "b".to_string()).unwrap();
+    /// b.set(3, "c".to_string()).unwrap();
+    /// b.set(1, "a".to_string()).unwrap();
+    /// b.delete(&3).unwrap();
+    ///
+    /// assert_eq!(a.canonical_bytes().unwrap(), b.canonical_bytes().unwrap());
+    /// ```
+    pub fn canonical_bytes(&mut self) -> Result<Vec<u8>, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.flush()?;
+        Ok(to_vec(&self.root)?)
+    }
+
+    /// Flushes the HAMT and writes all its nodes to `w` as a CARv1 file, with the root node as its
+    /// only root. Returns the root CID.
+    ///
+    /// Nodes are read back from the store, so this fetches every node of the HAMT, even those
+    /// already loaded. Each node is written once, parents before their children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+    /// map.set(1, "a".to_string()).unwrap();
+    ///
+    /// let mut car = Vec::new();
+    /// let cid = map.write_car(&mut car).unwrap();
+    /// assert_eq!(cid, map.flush().unwrap());
+    /// ```
+    #[cfg(feature = "car")]
+    pub fn write_car<W: Write + Send>(&mut self, w: &mut W) -> Result<Cid, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let root = self.flush()?;
+        car::write_car::<K, V, H, _, _>(root, &self.store, w)?;
+        Ok(root)
+    }
+
     /// Returns true if the HAMT has no entries
     pub fn is_empty(&self) -> bool {
         self.root.is_empty()
     }
 
+    /// Returns the number of entries, if it's known without walking the HAMT.
+    ///
+    /// The count is maintained by the methods inserting and removing entries, starting from an
+    /// empty HAMT, so it's `None` after [`Hamt::load`] or [`Hamt::set_root`] until the entries are
+    /// counted by [`Hamt::count`], which also happens when an entry is inserted with
+    /// [`Config::max_entries`] set. It's only accurate as long as the HAMT is modified through
+    /// this API: nodes written to the store by other means aren't accounted for.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+    /// map.set(1, "a".to_string()).unwrap();
+    /// map.set(2, "b".to_string()).unwrap();
+    /// assert_eq!(map.len(), Some(2));
+    ///
+    /// let cid = map.flush().unwrap();
+    /// let map: Hamt<_, String, usize> = Hamt::load(&cid, &store).unwrap();
+    /// assert_eq!(map.len(), None);
+    /// ```
+    pub fn len(&self) -> Option<usize> {
+        self.len.get()
+    }
+
+    /// Returns the number of entries.
+    ///
+    /// This is O(1) when the count is known (see [`Hamt::len`]). Otherwise, i.e., after
+    /// [`Hamt::load`] or [`Hamt::set_root`], the whole HAMT is walked, loading every node from the
+    /// store, and the count is kept up to date from then on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+    /// map.set(1, "a".to_string()).unwrap();
+    /// map.set(2, "b".to_string()).unwrap();
+    /// let cid = map.flush().unwrap();
+    ///
+    /// let mut map: Hamt<_, String, usize> = Hamt::load(&cid, &store).unwrap();
+    /// assert_eq!(map.count().unwrap(), 2);
+    /// map.delete(&1).unwrap();
+    /// assert_eq!(map.len(), Some(1));
+    /// ```
+    pub fn count(&self) -> Result<usize, Error> {
+        if let Some(len) = self.len.get() {
+            return Ok(len);
+        }
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let len = self.root.count(&store)?;
+        self.len.set(Some(len));
+        Ok(len)
+    }
+
     /// Iterates over each KV in the Hamt and runs a function on the values.
     ///
     /// This function will constrain all values to be of the same type
@@ -359,7 +1035,249 @@ where
         V: DeserializeOwned,
         F: FnMut(&K, &V) -> anyhow::Result<()>,
     {
-        self.root.for_each(self.store.borrow(), &mut f)
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        self.root.for_each(&store, &mut f)
+    }
+
+    /// Returns an iterator over the entries of the HAMT.
+    ///
+    /// Entries are visited in the same order as [`Hamt::for_each`]. Child nodes are loaded from
+    /// the store lazily, as the iteration reaches them, and the iterator borrows the HAMT
+    /// immutably until it's dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(store);
+    /// map.set(1, 1).unwrap();
+    /// map.set(4, 2).unwrap();
+    ///
+    /// let total: u64 = map.iter().map(|entry| entry.unwrap().1).sum();
+    /// assert_eq!(total, 3);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = Result<(&K, &V), Error>> + '_ {
+        self.iter_with_location()
+            .map(|entry| entry.map(|(k, v, _)| (k, v)))
+    }
+
+    /// Returns an iterator over the entries of the HAMT whose key hashes are greater than or equal
+    /// to the hash of `start`, which doesn't need to be in the HAMT.
+    ///
+    /// Entries are yielded in the order of the hashes of their keys, which is unrelated to the
+    /// order of the keys themselves. Each entry comes with its [`Cursor`], which can be passed
+    /// to [`Hamt::iter_after`] to resume the iteration after it, e.g., to paginate reads.
+    pub fn iter_from<Q: ?Sized>(&self, start: &Q) -> IterFrom<BS, V, K, H>
+    where
+        K: Borrow<Q>,
+        Q: Hash,
+    {
+        IterFrom::new(
+            FetchBudget::new(&self.store, self.fetch_budget),
+            &self.root,
+            self.conf.bit_width,
+            Bound::Included(H::hash(start)),
+        )
+    }
+
+    /// Returns an iterator over the entries of the HAMT after `cursor` in hash order, or over
+    /// all of them if `cursor` is `None`. See [`Hamt::iter_from`].
+    ///
+    /// Entries whose key hashes collide with the hash at `cursor` are skipped, which can only
+    /// happen with a non-cryptographic [`HashAlgorithm`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(store);
+    /// for i in 0..10 {
+    ///     map.set(i, i).unwrap();
+    /// }
+    ///
+    /// // Read the entries in pages of 3.
+    /// let mut cursor = None;
+    /// let mut total = 0;
+    /// loop {
+    ///     let page = map
+    ///         .iter_after(cursor.as_ref())
+    ///         .take(3)
+    ///         .collect::<Result<Vec<_>, _>>()
+    ///         .unwrap();
+    ///     match page.last() {
+    ///         Some(&(_, _, last)) => cursor = Some(last),
+    ///         None => break,
+    ///     }
+    ///     total += page.iter().map(|(_, v, _)| **v).sum::<usize>();
+    /// }
+    /// assert_eq!(total, 45);
+    /// ```
+    pub fn iter_after(&self, cursor: Option<&Cursor>) -> IterFrom<BS, V, K, H> {
+        IterFrom::new(
+            FetchBudget::new(&self.store, self.fetch_budget),
+            &self.root,
+            self.conf.bit_width,
+            cursor.map_or(Bound::Unbounded, |cursor| Bound::Excluded(cursor.hash)),
+        )
+    }
+
+    /// Returns an iterator over the entries of the HAMT, along with the [`Location`] of the node
+    /// each entry is stored in.
+    ///
+    /// Nodes which have not been flushed since they were last modified have no CID, so their
+    /// location only carries the depth.
+    ///
+    /// [`Location`]: crate::Location
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(store);
+    /// map.set(1, 1).unwrap();
+    /// let cid = map.flush().unwrap();
+    ///
+    /// for entry in map.iter_with_location() {
+    ///     let (_, _, location) = entry.unwrap();
+    ///     assert_eq!(location.depth, 0);
+    ///     assert_eq!(location.node_cid, Some(cid));
+    /// }
+    /// ```
+    pub fn iter_with_location(&self) -> IterWithLocation<BS, V, K, H> {
+        IterWithLocation::new(
+            FetchBudget::new(&self.store, self.fetch_budget),
+            &self.root,
+            self.flushed_cid,
+        )
+    }
+
+    /// Walks the whole HAMT and returns statistics about its shape, such as the number of nodes,
+    /// the depth of the trie and how entries are spread across depths.
+    ///
+    /// Nodes which haven't been loaded yet are fetched from the store.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(store);
+    /// map.set(1, 1).unwrap();
+    /// map.set(2, 2).unwrap();
+    ///
+    /// let stats = map.stats().unwrap();
+    /// assert_eq!(stats.node_count, 1);
+    /// assert_eq!(stats.entry_count, 2);
+    /// assert_eq!(stats.entries_per_depth, vec![2]);
+    /// ```
+    pub fn stats(&self) -> Result<HamtStats, Error> {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        HamtStats::collect(&self.root, &store)
+    }
+
+    /// Computes the entries changed between this HAMT and the one rooted at `other`, which is
+    /// loaded from the same store. Changes are relative to this HAMT (e.g., entries only present
+    /// at `other` are [`Change::Added`]). Pending (unflushed) changes to this HAMT are taken into
+    /// account.
+    ///
+    /// Both HAMTs must use the same bit width and hash algorithm. Subtrees with the same CID on
+    /// both sides are skipped without being loaded, so when the HAMTs share most of their
+    /// structure, the cost is proportional to the size of the changes rather than the size of the
+    /// HAMTs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::{Change, Hamt};
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new(&store);
+    /// map.set(1, "a".to_string()).unwrap();
+    /// map.set(2, "b".to_string()).unwrap();
+    /// let cid = map.flush().unwrap();
+    ///
+    /// map.set(2, "c".to_string()).unwrap();
+    /// map.set(3, "d".to_string()).unwrap();
+    ///
+    /// let mut changes = map.diff(&cid).unwrap();
+    /// changes.sort_by_key(|change| match change {
+    ///     Change::Added(k, _) | Change::Removed(k, _) | Change::Modified { key: k, .. } => *k,
+    /// });
+    /// assert_eq!(
+    ///     changes,
+    ///     vec![
+    ///         Change::Modified { key: 2, from: "c".to_string(), to: "b".to_string() },
+    ///         Change::Removed(3, "d".to_string()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn diff(&self, other: &Cid) -> Result<Vec<Change<K, V>>, Error>
+    where
+        K: Clone,
+        V: PartialEq + Clone,
+    {
+        let store = FetchBudget::new(&self.store, self.fetch_budget);
+        let other: Node<K, V, H> = store
+            .get_cbor(other)?
+            .ok_or_else(|| Error::CidNotFound(other.to_string()))?;
+        other.check_bit_width(self.conf.bit_width)?;
+
+        let mut changes = Vec::new();
+        diff::diff_nodes(&self.root, &other, &self.conf, &store, &mut changes)?;
+        Ok(changes)
+    }
+
+    /// Returns a read-only view over the entries whose key hashes start with `prefix_bits`, or
+    /// `None` if there are no such entries.
+    ///
+    /// `prefix_bits` holds one bucket index per level of the HAMT, each being the next
+    /// `bit_width` bits of the key hash, so every index must be lower than `2^bit_width`. An
+    /// empty prefix covers the whole HAMT.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::Hamt;
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut map: Hamt<_, _, usize> = Hamt::new_with_bit_width(store, 4);
+    /// for i in 0..100 {
+    ///     map.set(i, i).unwrap();
+    /// }
+    ///
+    /// let mut count = 0;
+    /// for idx in 0..16 {
+    ///     if let Some(view) = map.subtree(&[idx]).unwrap() {
+    ///         view.for_each(|_, _| {
+    ///             count += 1;
+    ///             Ok(())
+    ///         })
+    ///         .unwrap();
+    ///     }
+    /// }
+    /// assert_eq!(count, 100);
+    /// ```
+    pub fn subtree(&self, prefix_bits: &[u8]) -> Result<Option<SubtreeView<BS, V, K, H>>, Error> {
+        SubtreeView::new(
+            &self.store,
+            self.fetch_budget,
+            &self.conf,
+            &self.root,
+            prefix_bits,
+        )
     }
 
     /// Consumes this HAMT and returns the Blockstore it owns.
@@ -0,0 +1,314 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::ops::Bound;
+use std::slice;
+use std::sync::Arc;
+
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::CborStore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::budget::FetchBudget;
+use crate::hash_bits::HashBits;
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::{Error, Hash, HashAlgorithm, HashedKey, KeyValuePair};
+
+/// Structural position of an entry within the HAMT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// Depth of the node holding the entry, where the root node is at depth 0.
+    pub depth: u8,
+    /// CID of the node holding the entry. This is only `Some` if the node has been flushed
+    /// and hasn't been modified since; dirty nodes have no CID yet.
+    pub node_cid: Option<Cid>,
+}
+
+/// Iterator over the entries of a HAMT, yielding the location of each entry along with it.
+///
+/// Child nodes are loaded from the store lazily, as the iteration reaches them, and are kept in
+/// the link caches of the HAMT.
+///
+/// Created by [`Hamt::iter_with_location`](crate::Hamt::iter_with_location).
+pub struct IterWithLocation<'a, BS, V, K, H> {
+    store: FetchBudget<&'a BS>,
+    /// Pointers of the nodes still being visited, from the root down to the current node.
+    stack: Vec<(slice::Iter<'a, Pointer<K, V, H>>, Location)>,
+    /// Key-value pairs of the bucket currently being visited.
+    current: Option<(slice::Iter<'a, KeyValuePair<K, V>>, Location)>,
+}
+
+impl<'a, BS, V, K, H> IterWithLocation<'a, BS, V, K, H> {
+    pub(crate) fn new(
+        store: FetchBudget<&'a BS>,
+        root: &'a Node<K, V, H>,
+        root_cid: Option<Cid>,
+    ) -> Self {
+        Self {
+            store,
+            stack: vec![(
+                root.pointers.iter(),
+                Location {
+                    depth: 0,
+                    node_cid: root_cid,
+                },
+            )],
+            current: None,
+        }
+    }
+}
+
+impl<'a, BS, V, K, H> Iterator for IterWithLocation<'a, BS, V, K, H>
+where
+    BS: Blockstore,
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    type Item = Result<(&'a K, &'a V, Location), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((kvs, location)) = &mut self.current {
+                if let Some(kv) = kvs.next() {
+                    return Some(Ok((kv.key(), kv.value(), *location)));
+                }
+                self.current = None;
+            }
+
+            let (pointers, location) = self.stack.last_mut()?;
+            let location = *location;
+            match pointers.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Pointer::Values(kvs)) => {
+                    self.current = Some((kvs.iter(), location));
+                }
+                Some(Pointer::Dirty(node)) => {
+                    self.stack.push((
+                        node.pointers.iter(),
+                        Location {
+                            depth: location.depth + 1,
+                            node_cid: None,
+                        },
+                    ));
+                }
+                Some(Pointer::Link { cid, cache }) => {
+                    let node = if let Some(cached_node) = cache.get() {
+                        cached_node
+                    } else {
+                        let node = match self.store.get_cbor(cid) {
+                            Ok(Some(node)) => node,
+                            Ok(None) => {
+                                #[cfg(not(feature = "ignore-dead-links"))]
+                                {
+                                    self.stack.clear();
+                                    return Some(Err(Error::CidNotFound(cid.to_string())));
+                                }
+
+                                #[cfg(feature = "ignore-dead-links")]
+                                continue;
+                            }
+                            Err(e) => {
+                                self.stack.clear();
+                                return Some(Err(e.into()));
+                            }
+                        };
+                        // Ignore error intentionally, the cache value will always be the same
+                        cache.get_or_init(|| Arc::new(node))
+                    };
+                    self.stack.push((
+                        node.pointers.iter(),
+                        Location {
+                            depth: location.depth + 1,
+                            node_cid: Some(*cid),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Position of an entry in the hash order of a HAMT, used to resume an iteration.
+///
+/// HAMT entries are ordered by the hashes of their keys, not by the keys themselves, so a cursor
+/// holds the hash of the last key visited. Cursors are stable across loads of the same root, and
+/// can be converted to bytes (see [`Cursor::as_bytes`]) to be handed to clients and back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cursor {
+    pub(crate) hash: HashedKey,
+}
+
+impl Cursor {
+    /// Returns the serialized form of the cursor.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Parses a cursor serialized with [`Cursor::as_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let hash = bytes
+            .try_into()
+            .map_err(|_| Error::from("invalid cursor length"))?;
+        Ok(Self { hash })
+    }
+}
+
+/// Iterator over the entries of a HAMT in hash order, starting from a given position and yielding
+/// the [`Cursor`] of each entry along with it.
+///
+/// Created by [`Hamt::iter_from`](crate::Hamt::iter_from) and
+/// [`Hamt::iter_after`](crate::Hamt::iter_after).
+pub struct IterFrom<'a, BS, V, K, H> {
+    store: FetchBudget<&'a BS>,
+    bit_width: u32,
+    /// Where to start the iteration from.
+    start: Bound<HashedKey>,
+    /// Bucket indices of `start` at each depth.
+    start_path: Vec<u32>,
+    /// Nodes still being visited, from the root down to the current node.
+    stack: Vec<IterFromFrame<'a, K, V, H>>,
+    /// Entries of the bucket currently being visited, in reverse hash order.
+    current: Vec<(&'a K, &'a V, HashedKey)>,
+}
+
+struct IterFromFrame<'a, K, V, H> {
+    node: &'a Node<K, V, H>,
+    /// Next bucket index to visit.
+    idx: u32,
+    /// True if this node is on the path to the start position, i.e., the buckets before the
+    /// start position's bucket must be skipped.
+    on_start_path: bool,
+}
+
+impl<'a, BS, V, K, H> IterFrom<'a, BS, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+{
+    pub(crate) fn new(
+        store: FetchBudget<&'a BS>,
+        root: &'a Node<K, V, H>,
+        bit_width: u32,
+        start: Bound<HashedKey>,
+    ) -> Self {
+        let start_path = match &start {
+            Bound::Included(hash) | Bound::Excluded(hash) => {
+                let mut bits = HashBits::new(hash);
+                std::iter::from_fn(|| bits.next(bit_width).ok()).collect()
+            }
+            Bound::Unbounded => Vec::new(),
+        };
+        let mut iter = Self {
+            store,
+            bit_width,
+            start,
+            start_path,
+            stack: Vec::new(),
+            current: Vec::new(),
+        };
+        iter.push_node(root, 0, true);
+        iter
+    }
+
+    fn push_node(&mut self, node: &'a Node<K, V, H>, depth: usize, on_start_path: bool) {
+        let on_start_path = on_start_path && !matches!(self.start, Bound::Unbounded);
+        self.stack.push(IterFromFrame {
+            node,
+            idx: if on_start_path {
+                self.start_path.get(depth).copied().unwrap_or(0)
+            } else {
+                0
+            },
+            on_start_path,
+        });
+    }
+
+    fn after_start(&self, hash: &HashedKey) -> bool {
+        match &self.start {
+            Bound::Included(start) => hash >= start,
+            Bound::Excluded(start) => hash > start,
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl<'a, BS, V, K, H> Iterator for IterFrom<'a, BS, V, K, H>
+where
+    BS: Blockstore,
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    H: HashAlgorithm,
+{
+    type Item = Result<(&'a K, &'a V, Cursor), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v, hash)) = self.current.pop() {
+                return Some(Ok((k, v, Cursor { hash })));
+            }
+
+            let depth = self.stack.len().checked_sub(1)?;
+            let frame = &mut self.stack[depth];
+            if frame.idx >> self.bit_width != 0 {
+                self.stack.pop();
+                continue;
+            }
+            let (parent, idx) = (frame.node, frame.idx);
+            frame.idx += 1;
+            let on_start_path =
+                frame.on_start_path && self.start_path.get(depth).copied() == Some(idx);
+
+            let node: &Node<K, V, H> = match parent.pointer_at(idx) {
+                None => continue,
+                Some(Pointer::Values(kvs)) => {
+                    let mut entries: Vec<_> = kvs
+                        .iter()
+                        .map(|kv| (kv.key(), kv.value(), H::hash(kv.key())))
+                        .filter(|(_, _, hash)| !on_start_path || self.after_start(hash))
+                        .collect();
+                    // Entries of a bucket are sorted by key, we want them by hash. The sort is
+                    // stable, so colliding hashes stay in key order. We then reverse them, as
+                    // they're popped from the end.
+                    entries.sort_by_key(|entry| entry.2);
+                    entries.reverse();
+                    self.current = entries;
+                    continue;
+                }
+                Some(Pointer::Dirty(node)) => node,
+                Some(Pointer::Link { cid, cache }) => {
+                    if let Some(cached_node) = cache.get() {
+                        cached_node
+                    } else {
+                        let node = match self.store.get_cbor(cid) {
+                            Ok(Some(node)) => node,
+                            Ok(None) => {
+                                #[cfg(not(feature = "ignore-dead-links"))]
+                                {
+                                    self.stack.clear();
+                                    return Some(Err(Error::CidNotFound(cid.to_string())));
+                                }
+
+                                #[cfg(feature = "ignore-dead-links")]
+                                continue;
+                            }
+                            Err(e) => {
+                                self.stack.clear();
+                                return Some(Err(e.into()));
+                            }
+                        };
+                        // Ignore error intentionally, the cache value will always be the same
+                        cache.get_or_init(|| Arc::new(node))
+                    }
+                }
+            };
+            self.push_node(node, depth + 1, on_start_path);
+        }
+    }
+}
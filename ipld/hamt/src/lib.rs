@@ -11,27 +11,43 @@
 //! The Hamt is a data structure that mimmics a HashMap which has the features of being sharded, persisted, and indexable by a Cid. The Hamt supports a variable bit width to adjust the amount of possible pointers that can exist at each height of the tree. Hamt can be modified at any point, but the underlying values are only persisted to the store when the [flush](struct.Hamt.html#method.flush) is called.
 
 mod bitfield;
+mod budget;
+#[cfg(feature = "car")]
+mod car;
+mod diff;
 mod error;
 mod hamt;
 mod hash;
 mod hash_algorithm;
 mod hash_bits;
+mod iter;
 mod node;
 mod pointer;
+mod stats;
+mod subtree;
 
 pub use forest_hash_utils::{BytesKey, Hash};
+use multihash::Code;
 use serde::{Deserialize, Serialize};
 
+pub use self::diff::Change;
 pub use self::error::Error;
 pub use self::hamt::Hamt;
 pub use self::hash::*;
 pub use self::hash_algorithm::*;
+pub use self::iter::{Cursor, IterFrom, IterWithLocation, Location};
+pub use self::stats::HamtStats;
+pub use self::subtree::SubtreeView;
 
 /// Default bit width for indexing a hash at each depth level
 const DEFAULT_BIT_WIDTH: u32 = 8;
 
 /// Configuration options for a HAMT instance.
+///
+/// New options may be added in future releases, so start from [`Config::default`] and use the
+/// `with_*` setters (or assign the fields) rather than a struct literal.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Config {
     /// The `bit_width` drives how wide and high the tree is going to be.
     /// Each node in the tree will have `2^bit_width` number of slots for child nodes,
@@ -58,6 +74,24 @@ pub struct Config {
 
     /// Maximum number of key-value pairs in a bucket before it's pushed down.
     pub max_array_width: usize,
+
+    /// Multihash used to compute the CIDs of the nodes when flushing.
+    ///
+    /// Changing it changes the CID of every node, so it must match the hash used by any other
+    /// implementation the HAMT is shared with.
+    pub hash_code: Code,
+
+    /// Maximum number of key-value pairs in the HAMT. Inserting a new key in a full HAMT fails
+    /// with [`Error::LimitExceeded`].
+    ///
+    /// Enforcing the limit requires knowing the number of entries. It's tracked as the HAMT is
+    /// modified, but a HAMT loaded from the store is walked in full to count its entries the first
+    /// time an entry is inserted. See [`Hamt::len`].
+    pub max_entries: Option<usize>,
+
+    /// Maximum size of the encoded values. Inserting a larger value fails with
+    /// [`Error::LimitExceeded`].
+    pub max_value_bytes: Option<usize>,
 }
 
 impl Default for Config {
@@ -66,13 +100,54 @@ impl Default for Config {
             bit_width: DEFAULT_BIT_WIDTH,
             min_data_depth: 0,
             max_array_width: 3,
+            hash_code: Code::Blake2b256,
+            max_entries: None,
+            max_value_bytes: None,
         }
     }
 }
 
+impl Config {
+    /// Sets [`Config::bit_width`].
+    pub fn with_bit_width(mut self, bit_width: u32) -> Self {
+        self.bit_width = bit_width;
+        self
+    }
+
+    /// Sets [`Config::min_data_depth`].
+    pub fn with_min_data_depth(mut self, min_data_depth: u32) -> Self {
+        self.min_data_depth = min_data_depth;
+        self
+    }
+
+    /// Sets [`Config::max_array_width`].
+    pub fn with_max_array_width(mut self, max_array_width: usize) -> Self {
+        self.max_array_width = max_array_width;
+        self
+    }
+
+    /// Sets [`Config::hash_code`].
+    pub fn with_hash_code(mut self, hash_code: Code) -> Self {
+        self.hash_code = hash_code;
+        self
+    }
+
+    /// Sets [`Config::max_entries`].
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Sets [`Config::max_value_bytes`].
+    pub fn with_max_value_bytes(mut self, max_value_bytes: usize) -> Self {
+        self.max_value_bytes = Some(max_value_bytes);
+        self
+    }
+}
+
 type HashedKey = [u8; 32];
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct KeyValuePair<K, V>(K, V);
 
 impl<K, V> KeyValuePair<K, V> {
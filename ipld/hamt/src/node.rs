@@ -5,11 +5,13 @@
 use std::borrow::Borrow;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
+use cid::Cid;
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::CborStore;
 use multihash::Code;
-use once_cell::unsync::OnceCell;
+use once_cell::sync::OnceCell;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
@@ -27,6 +29,16 @@ pub(crate) struct Node<K, V, H> {
     hash: PhantomData<H>,
 }
 
+impl<K: Clone, V: Clone, H> Clone for Node<K, V, H> {
+    fn clone(&self) -> Self {
+        Self {
+            bitfield: self.bitfield,
+            pointers: self.pointers.clone(),
+            hash: Default::default(),
+        }
+    }
+}
+
 impl<K: PartialEq, V: PartialEq, H> PartialEq for Node<K, V, H> {
     fn eq(&self, other: &Self) -> bool {
         (self.bitfield == other.bitfield) && (self.pointers == other.pointers)
@@ -80,6 +92,8 @@ where
     H: HashAlgorithm,
     V: Serialize + DeserializeOwned,
 {
+    /// Inserts `value` at `key`. If `at_capacity` is set, the HAMT holds [`Config::max_entries`]
+    /// entries, and inserting a new key fails with [`Error::LimitExceeded`].
     pub fn set<S: Blockstore>(
         &mut self,
         key: K,
@@ -87,9 +101,11 @@ where
         store: &S,
         conf: &Config,
         overwrite: bool,
+        at_capacity: bool,
     ) -> Result<(Option<V>, bool), Error>
     where
-        V: PartialEq,
+        K: Clone,
+        V: PartialEq + Clone,
     {
         let hash = H::hash(&key);
         self.modify_value(
@@ -100,6 +116,7 @@ where
             value,
             store,
             overwrite,
+            at_capacity,
         )
     }
 
@@ -125,7 +142,8 @@ where
         conf: &Config,
     ) -> Result<Option<(K, V)>, Error>
     where
-        K: Borrow<Q>,
+        K: Borrow<Q> + Clone,
+        V: Clone,
         Q: Eq + Hash,
         S: Blockstore,
     {
@@ -159,7 +177,7 @@ where
                         };
 
                         // Ignore error intentionally, the cache value will always be the same
-                        let cache_node = cache.get_or_init(|| node);
+                        let cache_node = cache.get_or_init(|| Arc::new(node));
                         cache_node.for_each(store, f)?
                     }
                 }
@@ -174,6 +192,37 @@ where
         Ok(())
     }
 
+    /// Counts the entries under this node, without visiting them one by one.
+    pub(crate) fn count<S: Blockstore>(&self, store: &S) -> Result<usize, Error> {
+        let mut count = 0;
+        for p in &self.pointers {
+            count += match p {
+                Pointer::Link { cid, cache } => {
+                    if let Some(cached_node) = cache.get() {
+                        cached_node.count(store)?
+                    } else {
+                        let node = if let Some(node) = store.get_cbor(cid)? {
+                            node
+                        } else {
+                            #[cfg(not(feature = "ignore-dead-links"))]
+                            return Err(Error::CidNotFound(cid.to_string()));
+
+                            #[cfg(feature = "ignore-dead-links")]
+                            continue;
+                        };
+
+                        // Ignore error intentionally, the cache value will always be the same
+                        let cache_node = cache.get_or_init(|| Arc::new(node));
+                        cache_node.count(store)?
+                    }
+                }
+                Pointer::Dirty(node) => node.count(store)?,
+                Pointer::Values(kvs) => kvs.len(),
+            };
+        }
+        Ok(count)
+    }
+
     /// Search for a key.
     fn search<Q: ?Sized, S: Blockstore>(
         &self,
@@ -189,7 +238,7 @@ where
         self.get_value(&mut HashBits::new(&hash), conf, q, store)
     }
 
-    fn get_value<Q: ?Sized, S: Blockstore>(
+    pub(crate) fn get_value<Q: ?Sized, S: Blockstore>(
         &self,
         hashed_key: &mut HashBits,
         conf: &Config,
@@ -209,13 +258,13 @@ where
         let cindex = self.index_for_bit_pos(idx);
         let child = self.get_child(cindex);
 
-        let node = match child {
+        let node: &Node<K, V, H> = match child {
             Pointer::Link { cid, cache } => {
                 if let Some(cached_node) = cache.get() {
                     // Link node is cached
                     cached_node
                 } else {
-                    let node: Box<Node<K, V, H>> = if let Some(node) = store.get_cbor(cid)? {
+                    let node: Node<K, V, H> = if let Some(node) = store.get_cbor(cid)? {
                         node
                     } else {
                         #[cfg(not(feature = "ignore-dead-links"))]
@@ -225,7 +274,7 @@ where
                         return Ok(None);
                     };
                     // Intentionally ignoring error, cache will always be the same.
-                    cache.get_or_init(|| node)
+                    cache.get_or_init(|| Arc::new(node))
                 }
             }
             Pointer::Dirty(node) => node,
@@ -237,7 +286,272 @@ where
         node.get_value(hashed_key, conf, key, store)
     }
 
-    /// Internal method to modify values.
+    /// Looks up a batch of keys in a single traversal.
+    ///
+    /// `lookups` holds the hash bits of each key along with its position in `keys` and `found`.
+    /// It must be sorted by hash, so that keys sharing a path through the HAMT are adjacent and
+    /// each node along that path is resolved only once.
+    ///
+    /// If `stop_on` is set, the traversal stops at the first key whose presence matches it, and
+    /// true is returned. The remaining entries of `found` are then left unset.
+    pub(crate) fn get_many_values<'a, Q: ?Sized, S: Blockstore>(
+        &'a self,
+        lookups: &mut [(HashBits, usize)],
+        keys: &[&Q],
+        conf: &Config,
+        store: &S,
+        found: &mut [Option<&'a KeyValuePair<K, V>>],
+        stop_on: Option<bool>,
+    ) -> Result<bool, Error>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash,
+    {
+        let idxs = lookups
+            .iter_mut()
+            .map(|(hashed_key, _)| hashed_key.next(conf.bit_width))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut start = 0;
+        while start < lookups.len() {
+            let idx = idxs[start];
+            let end = start + idxs[start..].iter().take_while(|&&i| i == idx).count();
+            let group = &mut lookups[start..end];
+            start = end;
+
+            let node: &Node<K, V, H> = match self.pointer_at(idx) {
+                None if stop_on == Some(false) => return Ok(true),
+                None => continue,
+                Some(Pointer::Values(vals)) => {
+                    for &mut (_, i) in group {
+                        found[i] = vals.iter().find(|kv| keys[i].eq(kv.key().borrow()));
+                        if stop_on == Some(found[i].is_some()) {
+                            return Ok(true);
+                        }
+                    }
+                    continue;
+                }
+                Some(Pointer::Dirty(node)) => node,
+                Some(Pointer::Link { cid, cache }) => {
+                    if let Some(cached_node) = cache.get() {
+                        cached_node
+                    } else {
+                        let node: Node<K, V, H> = if let Some(node) = store.get_cbor(cid)? {
+                            node
+                        } else {
+                            #[cfg(not(feature = "ignore-dead-links"))]
+                            return Err(Error::CidNotFound(cid.to_string()));
+
+                            #[cfg(feature = "ignore-dead-links")]
+                            continue;
+                        };
+                        // Intentionally ignoring error, cache will always be the same.
+                        cache.get_or_init(|| Arc::new(node))
+                    }
+                }
+            };
+            if node.get_many_values(group, keys, conf, store, found, stop_on)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Removes a batch of keys in a single traversal, returning true if any of them was removed.
+    ///
+    /// `deletions` holds the hash bits of each key along with its position in `keys` and
+    /// `removed`. Like in `get_many_values`, it must be sorted by hash.
+    pub(crate) fn remove_many_entries<Q: ?Sized, S: Blockstore>(
+        &mut self,
+        deletions: &mut [(HashBits, usize)],
+        keys: &[&Q],
+        conf: &Config,
+        depth: u32,
+        store: &S,
+        removed: &mut [Option<(K, V)>],
+    ) -> Result<bool, Error>
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+        Q: Eq + Hash,
+    {
+        let idxs = deletions
+            .iter_mut()
+            .map(|(hashed_key, _)| hashed_key.next(conf.bit_width))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut any_removed = false;
+        let mut start = 0;
+        while start < deletions.len() {
+            let idx = idxs[start];
+            let end = start + idxs[start..].iter().take_while(|&&i| i == idx).count();
+            let group = &mut deletions[start..end];
+            start = end;
+
+            if !self.bitfield.test_bit(idx) {
+                continue;
+            }
+            let cindex = self.index_for_bit_pos(idx);
+            let child = self.get_child_mut(cindex);
+
+            let emptied = match child {
+                Pointer::Link { cid, cache } => {
+                    let child_node = Self::load_link_mut(cid, cache, store)?;
+                    if !child_node.remove_many_entries(
+                        group,
+                        keys,
+                        conf,
+                        depth + 1,
+                        store,
+                        removed,
+                    )? {
+                        continue;
+                    }
+                    *child = Pointer::Dirty(cache.take().expect("loaded above"));
+                    Self::clean_many(child, conf, depth)?
+                }
+                Pointer::Dirty(node) => {
+                    if !Arc::make_mut(node).remove_many_entries(
+                        group,
+                        keys,
+                        conf,
+                        depth + 1,
+                        store,
+                        removed,
+                    )? {
+                        continue;
+                    }
+                    Self::clean_many(child, conf, depth)?
+                }
+                Pointer::Values(vals) => {
+                    let len = vals.len();
+                    for &mut (_, i) in group {
+                        if let Some(pos) = vals.iter().position(|kv| keys[i].eq(kv.key().borrow()))
+                        {
+                            let kv = vals.remove(pos);
+                            removed[i] = Some((kv.0, kv.1));
+                        }
+                    }
+                    if vals.len() == len {
+                        continue;
+                    }
+                    vals.is_empty()
+                }
+            };
+
+            any_removed = true;
+            if emptied {
+                self.rm_child(cindex, idx);
+            }
+        }
+        Ok(any_removed)
+    }
+
+    /// Removes the entries for which `f` returns false, returning how many were removed.
+    pub(crate) fn retain_entries<F, S: Blockstore>(
+        &mut self,
+        f: &mut F,
+        conf: &Config,
+        depth: u32,
+        store: &S,
+    ) -> Result<usize, Error>
+    where
+        K: Clone,
+        V: Clone,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut total_removed = 0;
+        for idx in 0..(1 << conf.bit_width) {
+            if !self.bitfield.test_bit(idx) {
+                continue;
+            }
+            let cindex = self.index_for_bit_pos(idx);
+            let child = self.get_child_mut(cindex);
+
+            let (removed, emptied) = match child {
+                Pointer::Link { cid, cache } => {
+                    let child_node = Self::load_link_mut(cid, cache, store)?;
+                    let removed = child_node.retain_entries(f, conf, depth + 1, store)?;
+                    if removed == 0 {
+                        continue;
+                    }
+                    *child = Pointer::Dirty(cache.take().expect("loaded above"));
+                    (removed, Self::clean_many(child, conf, depth)?)
+                }
+                Pointer::Dirty(node) => {
+                    let removed = Arc::make_mut(node).retain_entries(f, conf, depth + 1, store)?;
+                    if removed == 0 {
+                        continue;
+                    }
+                    (removed, Self::clean_many(child, conf, depth)?)
+                }
+                Pointer::Values(vals) => {
+                    let len = vals.len();
+                    vals.retain(|kv| f(kv.key(), kv.value()));
+                    if vals.len() == len {
+                        continue;
+                    }
+                    (len - vals.len(), vals.is_empty())
+                }
+            };
+
+            total_removed += removed;
+            if emptied {
+                self.rm_child(cindex, idx);
+            }
+        }
+        Ok(total_removed)
+    }
+
+    /// Looks up `key` and, if it's found, calls `f` with a mutable reference to its value, marking
+    /// the nodes along the path as dirty.
+    ///
+    /// Returns the result of `f`, or `f` itself if the key isn't in the HAMT, so the caller can
+    /// still call it.
+    pub(crate) fn modify_in_place<S, F, R>(
+        &mut self,
+        hashed_key: &mut HashBits,
+        conf: &Config,
+        key: &K,
+        store: &S,
+        f: F,
+    ) -> Result<Result<R, F>, Error>
+    where
+        K: Clone,
+        V: Clone,
+        S: Blockstore,
+        F: FnOnce(Option<&mut V>) -> R,
+    {
+        let idx = hashed_key.next(conf.bit_width)?;
+
+        if !self.bitfield.test_bit(idx) {
+            return Ok(Err(f));
+        }
+
+        let cindex = self.index_for_bit_pos(idx);
+        let child = self.get_child_mut(cindex);
+
+        match child {
+            Pointer::Link { cid, cache } => {
+                let child_node = Self::load_link_mut(cid, cache, store)?;
+
+                let res = child_node.modify_in_place(hashed_key, conf, key, store, f)?;
+                if res.is_ok() {
+                    *child = Pointer::Dirty(cache.take().expect("loaded above"));
+                }
+                Ok(res)
+            }
+            Pointer::Dirty(node) => {
+                Arc::make_mut(node).modify_in_place(hashed_key, conf, key, store, f)
+            }
+            Pointer::Values(vals) => match vals.iter_mut().find(|kv| kv.key() == key) {
+                Some(kv) => Ok(Ok(f(Some(&mut kv.1)))),
+                None => Ok(Err(f)),
+            },
+        }
+    }
+
+    /// Internal method to modify values. New keys are rejected if `at_capacity` is set.
     ///
     /// Returns the a tuple with:
     /// * the old data at this key, if any
@@ -252,21 +566,35 @@ where
         value: V,
         store: &S,
         overwrite: bool,
+        at_capacity: bool,
     ) -> Result<(Option<V>, bool), Error>
     where
-        V: PartialEq,
+        K: Clone,
+        V: PartialEq + Clone,
     {
         let idx = hashed_key.next(conf.bit_width)?;
 
         // No existing values at this point.
         if !self.bitfield.test_bit(idx) {
+            if at_capacity {
+                return Err(Self::max_entries_exceeded(conf));
+            }
             if depth >= conf.min_data_depth {
                 self.insert_child(idx, key, value);
             } else {
                 // Need to insert some empty nodes reserved for links.
                 let mut sub = Node::<K, V, H>::default();
-                sub.modify_value(hashed_key, conf, depth + 1, key, value, store, overwrite)?;
-                self.insert_child_dirty(idx, Box::new(sub));
+                sub.modify_value(
+                    hashed_key,
+                    conf,
+                    depth + 1,
+                    key,
+                    value,
+                    store,
+                    overwrite,
+                    false,
+                )?;
+                self.insert_child_dirty(idx, Arc::new(sub));
             }
             return Ok((None, true));
         }
@@ -276,12 +604,7 @@ where
 
         match child {
             Pointer::Link { cid, cache } => {
-                cache.get_or_try_init(|| {
-                    store
-                        .get_cbor(cid)?
-                        .ok_or_else(|| Error::CidNotFound(cid.to_string()))
-                })?;
-                let child_node = cache.get_mut().expect("filled line above");
+                let child_node = Self::load_link_mut(cid, cache, store)?;
 
                 let (old, modified) = child_node.modify_value(
                     hashed_key,
@@ -291,15 +614,23 @@ where
                     value,
                     store,
                     overwrite,
+                    at_capacity,
                 )?;
                 if modified {
-                    *child = Pointer::Dirty(std::mem::take(child_node));
+                    *child = Pointer::Dirty(cache.take().expect("loaded above"));
                 }
                 Ok((old, modified))
             }
-            Pointer::Dirty(node) => {
-                node.modify_value(hashed_key, conf, depth + 1, key, value, store, overwrite)
-            }
+            Pointer::Dirty(node) => Arc::make_mut(node).modify_value(
+                hashed_key,
+                conf,
+                depth + 1,
+                key,
+                value,
+                store,
+                overwrite,
+                at_capacity,
+            ),
             Pointer::Values(vals) => {
                 // Update, if the key already exists.
                 if let Some(i) = vals.iter().position(|p| p.key() == &key) {
@@ -321,6 +652,10 @@ where
                     }
                 }
 
+                if at_capacity {
+                    return Err(Self::max_entries_exceeded(conf));
+                }
+
                 // If the array is full, create a subshard and insert everything
                 if vals.len() >= conf.max_array_width {
                     let kvs = std::mem::take(vals);
@@ -339,6 +674,7 @@ where
                         value,
                         store,
                         overwrite,
+                        false,
                     )?;
 
                     for (k, v, hash) in hashed_kvs {
@@ -350,10 +686,11 @@ where
                             v,
                             store,
                             overwrite,
+                            false,
                         )?;
                     }
 
-                    *child = Pointer::Dirty(Box::new(sub));
+                    *child = Pointer::Dirty(Arc::new(sub));
 
                     return Ok(modified);
                 }
@@ -370,6 +707,15 @@ where
         }
     }
 
+    /// The error returned when inserting a new key in a HAMT holding [`Config::max_entries`]
+    /// entries.
+    fn max_entries_exceeded(conf: &Config) -> Error {
+        Error::LimitExceeded {
+            limit: "max_entries",
+            max: conf.max_entries.unwrap_or_default(),
+        }
+    }
+
     /// Internal method to delete entries.
     fn rm_value<Q: ?Sized, S: Blockstore>(
         &mut self,
@@ -380,7 +726,8 @@ where
         store: &S,
     ) -> Result<Option<(K, V)>, Error>
     where
-        K: Borrow<Q>,
+        K: Borrow<Q> + Clone,
+        V: Clone,
         Q: Hash + Eq,
     {
         let idx = hashed_key.next(conf.bit_width)?;
@@ -395,17 +742,12 @@ where
 
         match child {
             Pointer::Link { cid, cache } => {
-                cache.get_or_try_init(|| {
-                    store
-                        .get_cbor(cid)?
-                        .ok_or_else(|| Error::CidNotFound(cid.to_string()))
-                })?;
-                let child_node = cache.get_mut().expect("filled line above");
+                let child_node = Self::load_link_mut(cid, cache, store)?;
 
                 let deleted = child_node.rm_value(hashed_key, conf, depth + 1, key, store)?;
 
                 if deleted.is_some() {
-                    *child = Pointer::Dirty(std::mem::take(child_node));
+                    *child = Pointer::Dirty(cache.take().expect("loaded above"));
                     if Self::clean(child, conf, depth)? {
                         self.rm_child(cindex, idx);
                     }
@@ -415,7 +757,8 @@ where
             }
             Pointer::Dirty(node) => {
                 // Delete value and return deleted value
-                let deleted = node.rm_value(hashed_key, conf, depth + 1, key, store)?;
+                let deleted =
+                    Arc::make_mut(node).rm_value(hashed_key, conf, depth + 1, key, store)?;
 
                 if deleted.is_some() && Self::clean(child, conf, depth)? {
                     self.rm_child(cindex, idx);
@@ -445,26 +788,86 @@ where
         }
     }
 
-    pub fn flush<S: Blockstore>(&mut self, store: &S) -> Result<(), Error> {
+    /// Writes the dirty children of this node to the store, turning them into links. Links are
+    /// never modified in place (a modified child is turned back into a dirty pointer), so clean
+    /// subtrees are left untouched.
+    pub fn flush<S: Blockstore>(&mut self, store: &S, hash_code: Code) -> Result<(), Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
         for pointer in &mut self.pointers {
             if let Pointer::Dirty(node) = pointer {
                 // Flush cached sub node to clear it's cache
-                node.flush(store)?;
+                Arc::make_mut(node).flush(store, hash_code)?;
+                Self::link_flushed(pointer, store, hash_code)?;
+            }
+        }
 
-                // Put node in blockstore and retrieve Cid
-                let cid = store.put_cbor(node, Code::Blake2b256)?;
+        Ok(())
+    }
 
-                // Can keep the flushed node in link cache
-                let cache = OnceCell::from(std::mem::take(node));
+    /// Like `flush`, but flushes the dirty children concurrently. They are independent subtrees,
+    /// so they write disjoint sets of blocks.
+    #[cfg(feature = "parallel")]
+    pub fn flush_parallel<S>(&mut self, store: &S, hash_code: Code) -> Result<(), Error>
+    where
+        K: Send + Sync + Clone,
+        V: Send + Sync + Clone,
+        S: Blockstore + Sync,
+        H: Send + Sync,
+    {
+        use rayon::prelude::*;
 
-                // Replace cached node with Cid link
-                *pointer = Pointer::Link { cid, cache };
+        self.pointers.par_iter_mut().try_for_each(|pointer| {
+            if let Pointer::Dirty(node) = pointer {
+                Arc::make_mut(node).flush_parallel(store, hash_code)?;
+                Self::link_flushed(pointer, store, hash_code)?;
             }
-        }
+            Ok(())
+        })
+    }
 
+    /// Replaces a dirty pointer, whose node has been flushed, by a link to the node.
+    fn link_flushed<S: Blockstore>(
+        pointer: &mut Pointer<K, V, H>,
+        store: &S,
+        hash_code: Code,
+    ) -> Result<(), Error> {
+        if let Pointer::Dirty(node) = pointer {
+            // Put node in blockstore and retrieve Cid
+            let cid = store.put_cbor(&**node, hash_code)?;
+
+            // Can keep the flushed node in link cache
+            let cache = OnceCell::from(Arc::clone(node));
+
+            // Replace cached node with Cid link
+            *pointer = Pointer::Link { cid, cache };
+        }
         Ok(())
     }
 
+    /// Returns the node a link points to, to be modified, loading it from the store if it isn't
+    /// cached yet. A cached node which is shared with a clone of the HAMT is copied first, so the
+    /// clone keeps seeing the unmodified node, but the children of the copy are still shared.
+    fn load_link_mut<'a, S: Blockstore>(
+        cid: &Cid,
+        cache: &'a mut OnceCell<Arc<Node<K, V, H>>>,
+        store: &S,
+    ) -> Result<&'a mut Node<K, V, H>, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if cache.get().is_none() {
+            let node: Node<K, V, H> = store
+                .get_cbor(cid)?
+                .ok_or_else(|| Error::CidNotFound(cid.to_string()))?;
+            *cache = OnceCell::from(Arc::new(node));
+        }
+        Ok(Arc::make_mut(cache.get_mut().expect("filled line above")))
+    }
+
     fn rm_child(&mut self, i: usize, idx: u32) -> Pointer<K, V, H> {
         self.bitfield.clear_bit(idx);
         self.pointers.remove(i)
@@ -476,7 +879,7 @@ where
         self.pointers.insert(i, Pointer::from_key_value(key, value))
     }
 
-    fn insert_child_dirty(&mut self, idx: u32, node: Box<Node<K, V, H>>) {
+    fn insert_child_dirty(&mut self, idx: u32, node: Arc<Node<K, V, H>>) {
         let i = self.index_for_bit_pos(idx);
         self.bitfield.set_bit(idx);
         self.pointers.insert(i, Pointer::Dirty(node))
@@ -496,11 +899,46 @@ where
         &self.pointers[i]
     }
 
+    /// Fails with [`Error::BitWidthMismatch`] if this node has buckets which can't be indexed with
+    /// `bit_width` bits.
+    pub(crate) fn check_bit_width(&self, bit_width: u32) -> Result<(), Error> {
+        if !self.bitfield.fits_bit_width(bit_width) {
+            return Err(Error::BitWidthMismatch(bit_width));
+        }
+        Ok(())
+    }
+
+    /// Returns the pointer stored at bucket index `idx`, if any.
+    pub(crate) fn pointer_at(&self, idx: u32) -> Option<&Pointer<K, V, H>> {
+        if !self.bitfield.test_bit(idx) {
+            return None;
+        }
+        Some(self.get_child(self.index_for_bit_pos(idx)))
+    }
+
+    /// Like `clean`, but after a batch of deletions, which may empty a child node entirely at any
+    /// depth. Single deletions can't, as a node with a single value would have been collapsed.
+    fn clean_many(child: &mut Pointer<K, V, H>, conf: &Config, depth: u32) -> Result<bool, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        match child.clean(conf, depth) {
+            Ok(()) => Ok(false),
+            Err(Error::ZeroPointers) => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
     /// Clean after delete to retrieve canonical form.
     ///
     /// Returns true if the child pointer is completely empty and can be removed,
     /// which can happen if we artificially inserted nodes during insertion.
-    fn clean(child: &mut Pointer<K, V, H>, conf: &Config, depth: u32) -> Result<bool, Error> {
+    fn clean(child: &mut Pointer<K, V, H>, conf: &Config, depth: u32) -> Result<bool, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
         match child.clean(conf, depth) {
             Ok(()) => Ok(false),
             Err(Error::ZeroPointers) if depth < conf.min_data_depth => Ok(true),
@@ -4,10 +4,11 @@
 
 use std::cmp::Ordering;
 use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
 
 use cid::Cid;
 use libipld_core::ipld::Ipld;
-use once_cell::unsync::OnceCell;
+use once_cell::sync::OnceCell;
 use serde::de::{self, DeserializeOwned};
 use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
 
@@ -21,9 +22,25 @@ pub(crate) enum Pointer<K, V, H> {
     Values(Vec<KeyValuePair<K, V>>),
     Link {
         cid: Cid,
-        cache: OnceCell<Box<Node<K, V, H>>>,
+        cache: OnceCell<Arc<Node<K, V, H>>>,
     },
-    Dirty(Box<Node<K, V, H>>),
+    Dirty(Arc<Node<K, V, H>>),
+}
+
+/// Clones links along with their cached node, and dirty pointers along with their node, which are
+/// shared rather than copied: a shared node is copied before being modified (see
+/// `Node::load_link_mut`).
+impl<K: Clone, V: Clone, H> Clone for Pointer<K, V, H> {
+    fn clone(&self) -> Self {
+        match self {
+            Pointer::Values(kvs) => Pointer::Values(kvs.clone()),
+            Pointer::Link { cid, cache } => Pointer::Link {
+                cid: *cid,
+                cache: cache.clone(),
+            },
+            Pointer::Dirty(node) => Pointer::Dirty(node.clone()),
+        }
+    }
 }
 
 impl<K: PartialEq, V: PartialEq, H> PartialEq for Pointer<K, V, H> {
@@ -112,7 +129,11 @@ where
 
     /// Internal method to cleanup children, to ensure consistent tree representation
     /// after deletes.
-    pub(crate) fn clean(&mut self, conf: &Config, depth: u32) -> Result<(), Error> {
+    pub(crate) fn clean(&mut self, conf: &Config, depth: u32) -> Result<(), Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
         match self {
             Pointer::Dirty(n) => match n.pointers.len() {
                 0 => Err(Error::ZeroPointers),
@@ -124,7 +145,7 @@ where
                 }
                 1 => {
                     // Node has only one pointer, swap with parent node
-                    if let Pointer::Values(vals) = &mut n.pointers[0] {
+                    if let Pointer::Values(vals) = &mut Arc::make_mut(n).pointers[0] {
                         // Take child values, to ensure canonical ordering
                         let values = std::mem::take(vals);
 
@@ -148,7 +169,7 @@ where
                     }
 
                     // Collect values from child nodes to collapse.
-                    let mut child_vals: Vec<KeyValuePair<K, V>> = n
+                    let mut child_vals: Vec<KeyValuePair<K, V>> = Arc::make_mut(n)
                         .pointers
                         .iter_mut()
                         .filter_map(|p| {
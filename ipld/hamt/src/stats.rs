@@ -0,0 +1,86 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::sync::Arc;
+
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::CborStore;
+use serde::de::DeserializeOwned;
+
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::Error;
+
+/// Structural statistics of a HAMT, useful to evaluate how well a [`Config`](crate::Config) suits
+/// a given key distribution.
+///
+/// Created by [`Hamt::stats`](crate::Hamt::stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HamtStats {
+    /// Number of nodes, including the root node.
+    pub node_count: usize,
+    /// Depth of the deepest node, where the root node is at depth 0.
+    pub max_depth: usize,
+    /// Number of key-value pairs.
+    pub entry_count: usize,
+    /// Number of buckets of key-value pairs.
+    pub bucket_count: usize,
+    /// Number of key-value pairs stored at each depth, starting from the root node.
+    pub entries_per_depth: Vec<usize>,
+}
+
+impl HamtStats {
+    /// Walks the HAMT from `root`, loading the nodes which haven't been loaded yet from `store`.
+    pub(crate) fn collect<K, V, H, S>(root: &Node<K, V, H>, store: &S) -> Result<Self, Error>
+    where
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+        S: Blockstore,
+    {
+        let mut stats = Self::default();
+        let mut stack = vec![(root, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            for pointer in &node.pointers {
+                match pointer {
+                    Pointer::Values(kvs) => {
+                        if stats.entries_per_depth.len() <= depth {
+                            stats.entries_per_depth.resize(depth + 1, 0);
+                        }
+                        stats.entries_per_depth[depth] += kvs.len();
+                        stats.entry_count += kvs.len();
+                        stats.bucket_count += 1;
+                    }
+                    Pointer::Dirty(node) => stack.push((node, depth + 1)),
+                    Pointer::Link { cid, cache } => {
+                        let node = if let Some(cached_node) = cache.get() {
+                            cached_node
+                        } else {
+                            let node = match store.get_cbor(cid)? {
+                                Some(node) => node,
+                                #[cfg(not(feature = "ignore-dead-links"))]
+                                None => return Err(Error::CidNotFound(cid.to_string())),
+                                #[cfg(feature = "ignore-dead-links")]
+                                None => continue,
+                            };
+                            // Ignore error intentionally, the cache value will always be the same
+                            cache.get_or_init(|| Arc::new(node))
+                        };
+                        stack.push((node, depth + 1));
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Average number of key-value pairs per bucket, or 0 if the HAMT is empty.
+    pub fn avg_bucket_size(&self) -> f64 {
+        if self.bucket_count == 0 {
+            return 0.0;
+        }
+        self.entry_count as f64 / self.bucket_count as f64
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::CborStore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::budget::FetchBudget;
+use crate::hash_bits::HashBits;
+use crate::node::Node;
+use crate::pointer::Pointer;
+use crate::{Config, Error, Hash, HashAlgorithm, KeyValuePair};
+
+/// Read-only view over the entries of a HAMT whose key hashes start with a given prefix.
+///
+/// The prefix is a sequence of bucket indices, one per level of the HAMT, each made of
+/// `bit_width` bits of the key hash. Views over different prefixes of the same length are
+/// disjoint.
+///
+/// Created by [`Hamt::subtree`](crate::Hamt::subtree).
+pub struct SubtreeView<'a, BS, V, K, H> {
+    store: &'a BS,
+    fetch_budget: Option<usize>,
+    conf: &'a Config,
+    prefix: Vec<u8>,
+    root: SubtreeRoot<'a, K, V, H>,
+}
+
+/// Where the entries of a subtree live.
+enum SubtreeRoot<'a, K, V, H> {
+    /// The node reached by following the prefix.
+    Node(&'a Node<K, V, H>),
+    /// A bucket found before the end of the prefix, which may also hold entries outside the
+    /// subtree.
+    Values(&'a [KeyValuePair<K, V>]),
+}
+
+impl<'a, BS, V, K, H> SubtreeView<'a, BS, V, K, H>
+where
+    K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    BS: Blockstore,
+    H: HashAlgorithm,
+{
+    /// Follows `prefix` from `root`, returning `None` if no entry of the HAMT is under it.
+    pub(crate) fn new(
+        store: &'a BS,
+        fetch_budget: Option<usize>,
+        conf: &'a Config,
+        root: &'a Node<K, V, H>,
+        prefix: &[u8],
+    ) -> Result<Option<Self>, Error> {
+        let budgeted = FetchBudget::new(store, fetch_budget);
+        let mut node = root;
+        let mut subtree_root = None;
+        for &idx in prefix {
+            if u32::from(idx).checked_shr(conf.bit_width).unwrap_or(0) != 0 {
+                return Err("subtree prefix index exceeds the bit width".into());
+            }
+            node = match node.pointer_at(idx.into()) {
+                None => return Ok(None),
+                Some(Pointer::Values(kvs)) => {
+                    subtree_root = Some(SubtreeRoot::Values(kvs.as_slice()));
+                    break;
+                }
+                Some(Pointer::Dirty(node)) => node,
+                Some(Pointer::Link { cid, cache }) => {
+                    if let Some(cached_node) = cache.get() {
+                        cached_node
+                    } else {
+                        let node = match budgeted.get_cbor(cid)? {
+                            Some(node) => node,
+                            #[cfg(not(feature = "ignore-dead-links"))]
+                            None => return Err(Error::CidNotFound(cid.to_string())),
+                            #[cfg(feature = "ignore-dead-links")]
+                            None => return Ok(None),
+                        };
+                        // Ignore error intentionally, the cache value will always be the same
+                        cache.get_or_init(|| Arc::new(node))
+                    }
+                }
+            };
+        }
+
+        let view = Self {
+            store,
+            fetch_budget,
+            conf,
+            prefix: prefix.to_vec(),
+            root: subtree_root.unwrap_or(SubtreeRoot::Node(node)),
+        };
+        if let SubtreeRoot::Values(kvs) = view.root {
+            if !kvs.iter().any(|kv| view.has_prefix(kv.key())) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(view))
+    }
+
+    /// Returns a reference to the value corresponding to the key, if it's in the subtree.
+    pub fn get<Q: ?Sized>(&self, k: &Q) -> Result<Option<&'a V>, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = H::hash(k);
+        let mut hashed_key = HashBits::new(&hash);
+        for &idx in &self.prefix {
+            if hashed_key.next(self.conf.bit_width)? != u32::from(idx) {
+                return Ok(None);
+            }
+        }
+
+        match self.root {
+            SubtreeRoot::Node(node) => {
+                let store = FetchBudget::new(self.store, self.fetch_budget);
+                Ok(node
+                    .get_value(&mut hashed_key, self.conf, k, &store)?
+                    .map(|kv| kv.value()))
+            }
+            SubtreeRoot::Values(kvs) => Ok(kvs
+                .iter()
+                .find(|kv| k.eq(kv.key().borrow()))
+                .map(|kv| kv.value())),
+        }
+    }
+
+    /// Returns `true` if a value exists for the given key in the subtree.
+    pub fn contains_key<Q: ?Sized>(&self, k: &Q) -> Result<bool, Error>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        Ok(self.get(k)?.is_some())
+    }
+
+    /// Iterates over each entry of the subtree and runs a function on it.
+    pub fn for_each<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&K, &V) -> anyhow::Result<()>,
+    {
+        match self.root {
+            SubtreeRoot::Node(node) => {
+                let store = FetchBudget::new(self.store, self.fetch_budget);
+                node.for_each(&store, &mut f)
+            }
+            SubtreeRoot::Values(kvs) => {
+                for kv in kvs.iter().filter(|kv| self.has_prefix(kv.key())) {
+                    f(kv.key(), kv.value())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns true if the hash of `key` starts with the prefix of this subtree.
+    fn has_prefix(&self, key: &K) -> bool {
+        let hash = H::hash(key);
+        let mut hashed_key = HashBits::new(&hash);
+        self.prefix.iter().all(
+            |&idx| matches!(hashed_key.next(self.conf.bit_width), Ok(i) if i == u32::from(idx)),
+        )
+    }
+}
@@ -8,12 +8,14 @@ use std::fmt::Display;
 use cid::Cid;
 use fvm_ipld_blockstore::tracking::{BSStats, TrackingBlockstore};
 use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
+#[cfg(feature = "car")]
+use fvm_ipld_car::{CarHeader, CarReader};
 use fvm_ipld_encoding::de::DeserializeOwned;
 use fvm_ipld_encoding::strict_bytes::ByteBuf;
 use fvm_ipld_encoding::CborStore;
 #[cfg(feature = "identity")]
 use fvm_ipld_hamt::Identity;
-use fvm_ipld_hamt::{BytesKey, Config, Error, Hamt, Hash};
+use fvm_ipld_hamt::{BytesKey, Change, Config, Cursor, Error, Hamt, Hash, HashAlgorithm, Sha256};
 use multihash::Code;
 use quickcheck::Arbitrary;
 use rand::seq::SliceRandom;
@@ -46,10 +48,7 @@ impl HamtFactory {
         K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
         V: Serialize + DeserializeOwned,
     {
-        let conf = Config {
-            bit_width,
-            ..self.conf
-        };
+        let conf = self.conf.clone().with_bit_width(bit_width);
         Hamt::new_with_config(store, conf)
     }
 
@@ -73,10 +72,7 @@ impl HamtFactory {
         K: Hash + Eq + PartialOrd + Serialize + DeserializeOwned,
         V: Serialize + DeserializeOwned,
     {
-        let conf = Config {
-            bit_width,
-            ..self.conf
-        };
+        let conf = self.conf.clone().with_bit_width(bit_width);
         Hamt::load_with_config(cid, store, conf)
     }
 }
@@ -233,6 +229,654 @@ fn set_with_no_effect_does_not_put(
     }
 }
 
+fn clear(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+    let empty = factory
+        .new::<_, BytesKey, BytesKey>(&store)
+        .flush()
+        .unwrap();
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 2);
+    for i in 0..50 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    // Nothing was flushed yet.
+    assert_eq!(hamt.clear(), None);
+    assert!(hamt.is_empty());
+
+    for i in 0..50 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+    assert_eq!(hamt.clear(), Some(c));
+    assert!(hamt.is_empty());
+    assert_eq!(hamt.get(&tstring(1)).unwrap(), None);
+    assert_eq!(hamt.flush().unwrap(), empty);
+
+    // The previous state is still in the store.
+    let old: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    assert_eq!(old.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+}
+
+fn put_if_changed(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 1);
+    for i in 0..2 * BUCKET_SIZE * 5 {
+        assert!(hamt.put_if_changed(tstring(i), tstring("filler")).unwrap());
+    }
+    let c = hamt.flush().unwrap();
+
+    let mut hamt: Hamt<_, _> = factory.load_with_bit_width(&c, &store, 1).unwrap();
+    let stats = *store.stats.borrow();
+
+    // Putting identical values neither modifies the HAMT nor writes anything on flush.
+    for i in 0..2 * BUCKET_SIZE * 5 {
+        assert!(!hamt.put_if_changed(tstring(i), tstring("filler")).unwrap());
+    }
+    assert_eq!(hamt.flush().unwrap(), c);
+    assert_eq!(store.stats.borrow().w, stats.w);
+
+    // A different value does.
+    assert!(hamt.put_if_changed(tstring(1), tstring("other")).unwrap());
+    assert_ne!(hamt.flush().unwrap(), c);
+    assert_eq!(hamt.get(&tstring(1)).unwrap(), Some(&tstring("other")));
+}
+
+fn modify(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+    let incr = |v: Option<&mut u64>| match v {
+        Some(v) => {
+            *v += 1;
+            None
+        }
+        None => Some(1),
+    };
+
+    let mut hamt: Hamt<_, u64> = factory.new_with_bit_width(&store, 2);
+    for i in 0..50 {
+        assert!(!hamt.modify(tstring(i), incr).unwrap());
+    }
+    let c = hamt.flush().unwrap();
+
+    // Modify values of nodes loaded from the store, which must be marked as dirty.
+    let mut hamt: Hamt<_, u64> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    for i in 0..10 {
+        assert!(hamt.modify(tstring(i), incr).unwrap());
+    }
+    assert_eq!(hamt.get(&tstring(0)).unwrap(), Some(&2));
+    assert_eq!(hamt.get(&tstring(10)).unwrap(), Some(&1));
+
+    // Nothing is inserted if the function doesn't return a value.
+    let c = hamt.flush().unwrap();
+    assert!(!hamt.modify(tstring("absent"), |_| None).unwrap());
+    assert_eq!(hamt.get(&tstring("absent")).unwrap(), None);
+    assert_eq!(hamt.flush().unwrap(), c);
+
+    // Same result as setting the values.
+    let mut expected: Hamt<_, u64> = factory.new_with_bit_width(&store, 2);
+    for i in 0..50 {
+        expected
+            .set(tstring(i), if i < 10 { 2 } else { 1 })
+            .unwrap();
+    }
+    assert_eq!(expected.flush().unwrap(), c);
+}
+
+fn contains_all_any(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 1);
+    for i in 0..2 * BUCKET_SIZE * 5 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+    let hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 1).unwrap();
+
+    let (a, b, absent) = (tstring(1), tstring(7), tstring("absent"));
+    assert!(hamt.contains_all(&[&a, &b]).unwrap());
+    assert!(!hamt.contains_all(&[&a, &absent, &b]).unwrap());
+    assert!(hamt.contains_all::<BytesKey>(&[]).unwrap());
+
+    assert!(hamt.contains_any(&[&absent, &b]).unwrap());
+    assert!(!hamt.contains_any(&[&absent]).unwrap());
+    assert!(!hamt.contains_any::<BytesKey>(&[]).unwrap());
+
+    // Keys spread over the whole HAMT, and keys sharing its nodes.
+    let keys: Vec<BytesKey> = (0..2 * BUCKET_SIZE * 5).map(tstring).collect();
+    let mut key_refs: Vec<&BytesKey> = keys.iter().collect();
+    assert!(hamt.contains_all(&key_refs).unwrap());
+    key_refs.push(&absent);
+    assert!(!hamt.contains_all(&key_refs).unwrap());
+    assert!(hamt.contains_any(&key_refs).unwrap());
+}
+
+fn get_many(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 2);
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let keys: Vec<BytesKey> = (0..220).rev().chain([3, 3]).map(tstring).collect();
+    let key_refs: Vec<&BytesKey> = keys.iter().collect();
+    let expected: Vec<_> = keys.iter().map(|k| hamt.get(k).unwrap().cloned()).collect();
+    let found: Vec<_> = hamt.get_many(&key_refs).unwrap();
+    assert_eq!(
+        found.into_iter().map(|v| v.cloned()).collect::<Vec<_>>(),
+        expected
+    );
+
+    // Same results when nodes have to be loaded from the store.
+    let c = hamt.flush().unwrap();
+    let loaded: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    let found: Vec<_> = loaded.get_many(&key_refs).unwrap();
+    assert_eq!(
+        found.into_iter().map(|v| v.cloned()).collect::<Vec<_>>(),
+        expected
+    );
+    assert_eq!(loaded.get_many::<BytesKey>(&[]).unwrap(), vec![]);
+}
+
+fn delete_many(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 2);
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+
+    // Whole subtrees get emptied, along with a few keys here and there.
+    let keys: Vec<BytesKey> = (0..150)
+        .chain((150..220).step_by(7))
+        .chain([3, 3])
+        .rev()
+        .map(tstring)
+        .collect();
+    let key_refs: Vec<&BytesKey> = keys.iter().collect();
+
+    let mut expected: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    let expected_removed: Vec<_> = keys.iter().map(|k| expected.delete(k).unwrap()).collect();
+
+    let mut hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    let removed = hamt.delete_many(&key_refs).unwrap();
+    assert_eq!(removed, expected_removed);
+    assert_eq!(removed[0], Some((tstring(3), tstring(3))));
+    assert_eq!(removed[1], None);
+    assert_eq!(hamt.flush().unwrap(), expected.flush().unwrap());
+
+    // Deleting everything leaves an empty HAMT.
+    let keys: Vec<BytesKey> = (0..200).map(tstring).collect();
+    let key_refs: Vec<&BytesKey> = keys.iter().collect();
+    let removed = hamt.delete_many(&key_refs).unwrap();
+    assert_eq!(
+        removed.iter().filter(|r| r.is_some()).count(),
+        200 - 150 - 8
+    );
+    assert!(hamt.is_empty());
+
+    // Nothing deleted, nothing to flush.
+    let c = hamt.flush().unwrap();
+    assert_eq!(hamt.delete_many(&key_refs).unwrap().len(), 200);
+    assert_eq!(hamt.flush().unwrap(), c);
+}
+
+fn diff(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 5);
+    for i in 0..1000 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c1 = hamt.flush().unwrap();
+    let node_count = hamt.stats().unwrap().node_count;
+
+    hamt.set(tstring(1), tstring("changed")).unwrap();
+    hamt.delete(&tstring(2)).unwrap();
+    hamt.set(tstring(1000), tstring(1000)).unwrap();
+    let c2 = hamt.flush().unwrap();
+
+    // Only the nodes on the paths to the changed keys are loaded.
+    let old: Hamt<_, BytesKey> = factory.load_with_bit_width(&c1, &store, 5).unwrap();
+    let reads = store.stats.borrow().r;
+    let changes = old.diff(&c2).unwrap();
+    assert!(store.stats.borrow().r - reads < node_count);
+    assert_eq!(changes.len(), 3, "{:?}", changes);
+    assert!(changes.contains(&Change::Modified {
+        key: tstring(1),
+        from: tstring(1),
+        to: tstring("changed"),
+    }));
+    assert!(changes.contains(&Change::Removed(tstring(2), tstring(2))));
+    assert!(changes.contains(&Change::Added(tstring(1000), tstring(1000))));
+
+    // Identical roots only need the other root to be loaded.
+    let reads = store.stats.borrow().r;
+    assert_eq!(old.diff(&c1).unwrap(), vec![]);
+    assert_eq!(store.stats.borrow().r - reads, 1);
+
+    // Pending changes are taken into account.
+    hamt.set(tstring(3), tstring("pending")).unwrap();
+    assert_eq!(
+        hamt.diff(&c2).unwrap(),
+        vec![Change::Modified {
+            key: tstring(3),
+            from: tstring("pending"),
+            to: tstring(3),
+        }]
+    );
+
+    // Diffing against an empty HAMT yields every entry.
+    let empty = factory
+        .new::<_, BytesKey, BytesKey>(&store)
+        .flush()
+        .unwrap();
+    let changes = old.diff(&empty).unwrap();
+    assert_eq!(changes.len(), 1000);
+    assert!(changes.iter().all(|c| matches!(c, Change::Removed(..))));
+}
+
+fn flush_writes_dirty_nodes_only(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 2);
+    for i in 0..500 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+
+    let mut hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    let depth = hamt
+        .iter_with_location()
+        .map(Result::unwrap)
+        .find(|(k, _, _)| **k == tstring(42))
+        .unwrap()
+        .2
+        .depth as usize;
+
+    // Only the nodes on the path to the modified key are written: the node holding it, and each
+    // of its ancestors.
+    let writes = store.stats.borrow().w;
+    hamt.set(tstring(42), tstring("changed")).unwrap();
+    hamt.flush().unwrap();
+    assert_eq!(store.stats.borrow().w - writes, depth + 1);
+}
+
+fn limits(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+    let conf = factory.conf.with_max_entries(3).with_max_value_bytes(8);
+
+    let mut hamt: Hamt<_, BytesKey> = Hamt::new_with_config(&store, conf.clone());
+    for i in 0..3 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    assert_eq!(hamt.len(), Some(3));
+
+    let too_many = |res| {
+        matches!(
+            res,
+            Err(Error::LimitExceeded {
+                limit: "max_entries",
+                max: 3
+            })
+        )
+    };
+    assert!(too_many(hamt.set(tstring(3), tstring(3)).map(|_| ())));
+    assert!(too_many(
+        hamt.set_if_absent(tstring(3), tstring(3)).map(|_| ())
+    ));
+    assert!(too_many(
+        hamt.put_if_changed(tstring(3), tstring(3)).map(|_| ())
+    ));
+    assert!(too_many(
+        hamt.modify(tstring(3), |_| Some(tstring(3))).map(|_| ())
+    ));
+    // Existing keys can still be updated.
+    hamt.set(tstring(0), tstring("zero")).unwrap();
+    assert!(matches!(
+        hamt.set(tstring(0), tstring("a long value")),
+        Err(Error::LimitExceeded {
+            limit: "max_value_bytes",
+            max: 8
+        })
+    ));
+    assert_eq!(hamt.get(&tstring(0)).unwrap(), Some(&tstring("zero")));
+
+    hamt.delete(&tstring(0)).unwrap();
+    assert_eq!(hamt.len(), Some(2));
+    hamt.set(tstring(3), tstring(3)).unwrap();
+    assert_eq!(hamt.len(), Some(3));
+
+    // The count of a loaded HAMT is unknown until an entry is inserted.
+    let c = hamt.flush().unwrap();
+    let mut hamt: Hamt<_, BytesKey> = Hamt::load_with_config(&c, &store, conf).unwrap();
+    assert_eq!(hamt.len(), None);
+    hamt.set(tstring(1), tstring("one")).unwrap();
+    assert_eq!(hamt.len(), Some(3));
+    assert!(too_many(hamt.set(tstring(4), tstring(4)).map(|_| ())));
+    assert_eq!(hamt.len(), Some(3));
+    hamt.delete_many(&[&tstring(1), &tstring(2), &tstring(5)])
+        .unwrap();
+    assert_eq!(hamt.len(), Some(1));
+    hamt.set(tstring(4), tstring(4)).unwrap();
+    assert_eq!(hamt.len(), Some(2));
+
+    hamt.clear();
+    assert_eq!(hamt.len(), Some(0));
+}
+
+fn count(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    assert_eq!(hamt.count().unwrap(), 0);
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    assert_eq!(hamt.count().unwrap(), 200);
+    let c = hamt.flush().unwrap();
+
+    // The count of a loaded HAMT is computed once, then tracked.
+    let mut hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 5).unwrap();
+    assert_eq!(hamt.len(), None);
+    assert_eq!(hamt.count().unwrap(), 200);
+    let reads = store.stats.borrow().r;
+    assert_eq!(hamt.count().unwrap(), 200);
+    assert_eq!(store.stats.borrow().r, reads);
+
+    hamt.set(tstring(200), tstring(200)).unwrap();
+    hamt.set(tstring(0), tstring("zero")).unwrap();
+    hamt.set_if_absent(tstring(1), tstring("one")).unwrap();
+    hamt.modify(tstring(201), |_| Some(tstring(201))).unwrap();
+    assert_eq!(hamt.len(), Some(202));
+    hamt.delete(&tstring(2)).unwrap();
+    hamt.delete(&tstring(2)).unwrap();
+    assert_eq!(hamt.len(), Some(201));
+
+    // Setting a new root invalidates the count.
+    hamt.set_root(&c).unwrap();
+    assert_eq!(hamt.len(), None);
+    assert_eq!(hamt.count().unwrap(), 200);
+}
+
+fn clone_diverges(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    for i in 0..100 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    hamt.flush().unwrap();
+
+    // Loaded nodes are shared with the clone, which doesn't read them again.
+    assert_eq!(hamt.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+    let reads = store.stats.borrow().r;
+    let mut other = hamt.clone();
+    assert_eq!(other.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+    assert_eq!(store.stats.borrow().r, reads);
+
+    other.set(tstring(1), tstring("one")).unwrap();
+    hamt.delete(&tstring(2)).unwrap();
+    assert_eq!(hamt.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+    assert_eq!(other.get(&tstring(2)).unwrap(), Some(&tstring(2)));
+
+    // Pending changes are cloned.
+    let mut third = other.clone();
+    let c = hamt.flush().unwrap();
+    let other_c = other.flush().unwrap();
+    assert_ne!(c, other_c);
+    assert_eq!(third.flush().unwrap(), other_c);
+
+    let loaded: Hamt<_, BytesKey> = factory.load_with_bit_width(&other_c, &store, 5).unwrap();
+    assert_eq!(loaded.get(&tstring(1)).unwrap(), Some(&tstring("one")));
+    assert_eq!(loaded.get(&tstring(2)).unwrap(), Some(&tstring(2)));
+}
+
+fn retain(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    let mut expected: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    for i in 0..300 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+        if i % 3 == 0 {
+            expected.set(tstring(i), tstring(i)).unwrap();
+        }
+    }
+    let c = hamt.flush().unwrap();
+
+    // Retaining everything doesn't modify the HAMT.
+    let mut hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 5).unwrap();
+    assert_eq!(hamt.retain(|_, _| true).unwrap(), 0);
+    let writes = store.stats.borrow().w;
+    assert_eq!(hamt.flush().unwrap(), c);
+    assert_eq!(store.stats.borrow().w, writes);
+
+    // The result is the same as if the entries had been inserted on their own.
+    let removed = hamt
+        .retain(|k, _| expected.contains_key(k).unwrap())
+        .unwrap();
+    assert_eq!(removed, 200);
+    assert_eq!(hamt.flush().unwrap(), expected.flush().unwrap());
+
+    assert_eq!(hamt.retain(|_, _| false).unwrap(), 100);
+    assert!(hamt.is_empty());
+    let mut empty: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    assert_eq!(hamt.flush().unwrap(), empty.flush().unwrap());
+}
+
+fn root_cid(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new(&store);
+    assert_eq!(hamt.root_cid(), None);
+    for i in 0..100 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    assert_eq!(hamt.root_cid(), None);
+    let c = hamt.flush().unwrap();
+    assert_eq!(hamt.root_cid(), Some(c));
+
+    // Reading a loaded HAMT neither clears its root nor writes anything.
+    let writes = store.stats.borrow().w;
+    let mut hamt: Hamt<_, BytesKey> = factory.load(&c, &store).unwrap();
+    assert_eq!(hamt.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+    assert_eq!(hamt.root_cid(), Some(c));
+    assert!(!hamt.put_if_changed(tstring(1), tstring(1)).unwrap());
+    assert_eq!(hamt.root_cid(), Some(c));
+    assert_eq!(store.stats.borrow().w, writes);
+
+    hamt.delete(&tstring(1)).unwrap();
+    assert_eq!(hamt.root_cid(), None);
+    let c = hamt.flush().unwrap();
+    assert_eq!(hamt.root_cid(), Some(c));
+}
+
+#[cfg(feature = "car")]
+fn write_car(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    for i in 0..1000 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+
+    let mut car = Vec::new();
+    let c = hamt.write_car(&mut car).unwrap();
+    assert_eq!(hamt.root_cid(), Some(c));
+
+    let mut reader =
+        futures::executor::block_on(CarReader::new(futures::io::Cursor::new(&car))).unwrap();
+    assert_eq!(reader.header, CarHeader::from(vec![c]));
+
+    // Every node is written once, starting with the root, and the CAR holds the whole HAMT.
+    let imported = MemoryBlockstore::default();
+    let mut cids = Vec::new();
+    while let Some(block) = futures::executor::block_on(reader.next_block()).unwrap() {
+        imported.put_keyed(&block.cid, &block.data).unwrap();
+        cids.push(block.cid);
+    }
+    assert_eq!(cids[0], c);
+    assert_eq!(cids.len(), hamt.stats().unwrap().node_count);
+    assert_eq!(cids.iter().collect::<HashSet<_>>().len(), cids.len());
+
+    let hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &imported, 5).unwrap();
+    for i in 0..1000 {
+        assert_eq!(hamt.get(&tstring(i)).unwrap(), Some(&tstring(i)));
+    }
+}
+
+fn clone_shares_nodes(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    for i in 0..1000 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    hamt.flush().unwrap();
+    // Loads every node.
+    let stats = hamt.stats().unwrap();
+
+    // The nodes on the path to the modified key are copied from memory, the others are shared.
+    let mut other = hamt.clone();
+    let reads = store.stats.borrow().r;
+    other.set(tstring(1), tstring("one")).unwrap();
+    assert_eq!(other.stats().unwrap(), stats);
+    assert_eq!(store.stats.borrow().r, reads);
+
+    assert_eq!(hamt.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+    assert_eq!(other.get(&tstring(1)).unwrap(), Some(&tstring("one")));
+
+    // Modified nodes are shared too, until either HAMT modifies them again.
+    let mut third = other.clone();
+    third.set(tstring(1), tstring("uno")).unwrap();
+    third.delete(&tstring(2)).unwrap();
+    assert_eq!(store.stats.borrow().r, reads);
+
+    assert_eq!(other.get(&tstring(1)).unwrap(), Some(&tstring("one")));
+    assert_eq!(other.get(&tstring(2)).unwrap(), Some(&tstring(2)));
+    assert_eq!(third.get(&tstring(1)).unwrap(), Some(&tstring("uno")));
+    assert_eq!(third.get(&tstring(2)).unwrap(), None);
+
+    let root = hamt.flush().unwrap();
+    let other_root = other.flush().unwrap();
+    assert_ne!(root, other_root);
+    assert_ne!(other_root, third.flush().unwrap());
+    assert_eq!(other.flush().unwrap(), other_root);
+}
+
+fn canonical_bytes(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut a: Hamt<_, _> = factory.new_with_bit_width(&store, 1);
+    for i in 0..2 * BUCKET_SIZE * 5 {
+        a.set(tstring(i), tstring(i)).unwrap();
+    }
+
+    // Same entries, inserted in reverse order, with extra keys added and removed along the way.
+    let mut b: Hamt<_, _> = factory.new_with_bit_width(&store, 1);
+    for i in (0..2 * BUCKET_SIZE * 5).rev() {
+        b.set(tstring(i), tstring(i)).unwrap();
+        b.set(tstring(format!("extra-{i}")), tstring(i)).unwrap();
+    }
+    let c = b.flush().unwrap();
+    let mut b: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 1).unwrap();
+    for i in 0..2 * BUCKET_SIZE * 5 {
+        b.delete(&tstring(format!("extra-{i}"))).unwrap();
+    }
+
+    let bytes = a.canonical_bytes().unwrap();
+    assert_eq!(bytes, b.canonical_bytes().unwrap());
+    assert_eq!(store.get(&a.flush().unwrap()).unwrap(), Some(bytes));
+}
+
+fn flush_repeatedly(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 1);
+    for i in 0..2 * BUCKET_SIZE * 5 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let checkpoint = hamt.flush().unwrap();
+
+    // Flushing again only writes the nodes modified since the last flush.
+    let writes = store.stats.borrow().w;
+    hamt.set(tstring(0), tstring("changed")).unwrap();
+    hamt.flush().unwrap();
+    let depth = hamt
+        .iter_with_location()
+        .map(|e| e.unwrap().2.depth as usize)
+        .max()
+        .unwrap();
+    assert!(store.stats.borrow().w - writes <= depth + 1);
+
+    // Resuming from the first checkpoint gets to the same root.
+    let mut resumed: Hamt<_, BytesKey> =
+        factory.load_with_bit_width(&checkpoint, &store, 1).unwrap();
+    resumed.set(tstring(0), tstring("changed")).unwrap();
+    assert_eq!(resumed.flush().unwrap(), hamt.flush().unwrap());
+}
+
+fn subtree(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 2);
+    for i in 0..200 {
+        hamt.set(tstring(i), i).unwrap();
+    }
+    let cid = hamt.flush().unwrap();
+    let hamt: Hamt<_, usize> = factory.load_with_bit_width(&cid, &store, 2).unwrap();
+
+    // Views over all the prefixes of a given length partition the entries.
+    for depth in 0..3 {
+        let mut seen = HashSet::new();
+        let mut prefix = vec![0u8; depth];
+        loop {
+            if let Some(view) = hamt.subtree(&prefix).unwrap() {
+                view.for_each(|k, v| {
+                    assert!(seen.insert(*v));
+                    assert_eq!(view.get(k).unwrap(), Some(v));
+                    Ok(())
+                })
+                .unwrap();
+            }
+
+            // Next prefix, in base 4.
+            match prefix.iter().rposition(|&idx| idx < 3) {
+                Some(pos) => {
+                    prefix[pos] += 1;
+                    prefix[pos + 1..].fill(0);
+                }
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 200);
+    }
+
+    // Each key is only found through the view of its own prefix.
+    let views: Vec<_> = (0..4)
+        .filter_map(|idx| hamt.subtree(&[idx]).unwrap())
+        .collect();
+    for i in 0..200 {
+        let found = views
+            .iter()
+            .filter(|view| view.contains_key(&tstring(i)).unwrap())
+            .count();
+        assert_eq!(found, 1);
+    }
+
+    assert!(hamt.subtree(&[4]).is_err());
+}
+
 fn delete(factory: HamtFactory, stats: Option<BSStats>, mut cids: CidChecker) {
     let mem = MemoryBlockstore::default();
     let store = TrackingBlockstore::new(&mem);
@@ -386,6 +1030,144 @@ fn for_each(factory: HamtFactory, stats: Option<BSStats>, mut cids: CidChecker)
     }
 }
 
+fn stats(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, _> = factory.new_with_bit_width(&store, 2);
+    assert_eq!(hamt.stats().unwrap().avg_bucket_size(), 0.0);
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let stats = hamt.stats().unwrap();
+    assert_eq!(stats.entry_count, 200);
+    assert!(stats.node_count > 1);
+    assert!(stats.avg_bucket_size() >= 1.0);
+
+    // Entries per depth agree with their locations.
+    let mut entries_per_depth = vec![0; stats.max_depth + 1];
+    for entry in hamt.iter_with_location() {
+        entries_per_depth[entry.unwrap().2.depth as usize] += 1;
+    }
+    entries_per_depth.truncate(stats.entries_per_depth.len());
+    assert_eq!(stats.entries_per_depth, entries_per_depth);
+
+    // Nodes are loaded from the store when needed.
+    let c = hamt.flush().unwrap();
+    let loaded: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+    assert_eq!(loaded.stats().unwrap(), stats);
+}
+
+fn iter(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+    let hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 5).unwrap();
+
+    // Same order as for_each.
+    let mut expected = Vec::new();
+    hamt.for_each(|k, v| {
+        expected.push((k.clone(), v.clone()));
+        Ok(())
+    })
+    .unwrap();
+    let entries: Vec<_> = hamt
+        .iter()
+        .map(|entry| entry.map(|(k, v)| (k.clone(), v.clone())))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(entries, expected);
+    assert_eq!(entries.len(), 200);
+
+    // Iteration can stop early.
+    assert_eq!(hamt.iter().take(10).count(), 10);
+}
+
+fn iter_from(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, usize> = factory.new_with_bit_width(&store, 2);
+    for i in 0..200 {
+        hamt.set(tstring(i), i).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+    let hamt: Hamt<_, usize> = factory.load_with_bit_width(&c, &store, 2).unwrap();
+
+    // Paginate through all entries, round-tripping the cursor through bytes.
+    let mut entries = Vec::new();
+    let mut cursor: Option<Cursor> = None;
+    loop {
+        let page = hamt
+            .iter_after(cursor.as_ref())
+            .take(7)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let last = match page.last() {
+            Some(&(_, _, last)) => last,
+            None => break,
+        };
+        cursor = Some(Cursor::from_bytes(last.as_bytes()).unwrap());
+        entries.extend(
+            page.into_iter()
+                .map(|(k, v, cursor)| (k.clone(), *v, cursor)),
+        );
+    }
+    assert_eq!(entries.len(), 200);
+    assert!(entries.windows(2).all(|w| w[0].2 < w[1].2));
+
+    // Starting from a key includes it.
+    let (key, value, _) = &entries[50];
+    let from: Vec<_> = hamt.iter_from(key).map(|entry| *entry.unwrap().1).collect();
+    let expected: Vec<_> = entries[50..].iter().map(|(_, v, _)| *v).collect();
+    assert_eq!(from[0], *value);
+    assert_eq!(from, expected);
+
+    assert!(Cursor::from_bytes(&[0; 3]).is_err());
+}
+
+fn iter_with_location(factory: HamtFactory) {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, BytesKey> = factory.new_with_bit_width(&store, 5);
+
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+
+    // Nothing has been flushed yet, so no node has a CID.
+    let mut count = 0;
+    for entry in hamt.iter_with_location() {
+        let (k, v, location) = entry.unwrap();
+        assert_eq!(k, v);
+        assert_eq!(location.node_cid, None);
+        count += 1;
+    }
+    assert_eq!(count, 200);
+
+    let c = hamt.flush().unwrap();
+    let hamt: Hamt<_, BytesKey> = factory.load_with_bit_width(&c, &store, 5).unwrap();
+
+    // Every entry should live in the node its location points at.
+    let mut count = 0;
+    let mut max_depth = 0;
+    for entry in hamt.iter_with_location() {
+        let (k, _, location) = entry.unwrap();
+        let node_cid = location.node_cid.expect("all nodes are flushed");
+        if location.depth == 0 {
+            assert_eq!(node_cid, c);
+        }
+        let block = store.get(&node_cid).unwrap().unwrap();
+        assert!(block.windows(k.len()).any(|w| w == k.as_slice()));
+        max_depth = max_depth.max(location.depth);
+        count += 1;
+    }
+    assert_eq!(count, 200);
+    assert!(max_depth > 0);
+}
+
 #[cfg(feature = "identity")]
 fn add_and_remove_keys(
     bit_width: u32,
@@ -436,6 +1218,165 @@ fn add_and_remove_keys(
     assert_eq!(*store.stats.borrow(), stats);
 }
 
+#[test]
+fn borrowed_bytes_key_lookup() {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, u64> = Hamt::new_with_bit_width(&store, 1);
+    for i in 0..2 * BUCKET_SIZE as u64 * 5 {
+        hamt.set(tstring(i), i).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+
+    // Byte slices hash like the `BytesKey` they borrow from.
+    let key = tstring(3);
+    assert_eq!(Sha256::hash(&key), Sha256::hash::<[u8]>(&key));
+
+    let mut hamt: Hamt<_, u64> = Hamt::load_with_bit_width(&c, &store, 1).unwrap();
+    assert_eq!(hamt.get::<[u8]>(b"3").unwrap(), Some(&3));
+    assert!(hamt.contains_key::<[u8]>(b"7").unwrap());
+    assert!(!hamt.contains_key::<[u8]>(b"absent").unwrap());
+    assert_eq!(hamt.delete::<[u8]>(b"3").unwrap(), Some((tstring(3), 3)));
+    assert_eq!(hamt.get::<[u8]>(b"3").unwrap(), None);
+}
+
+#[test]
+fn fetch_budget() {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, BytesKey> = Hamt::new_with_bit_width(&store, 5);
+    for i in 0..200 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+
+    // Iterating requires fetching every node, which exceeds the budget.
+    let hamt: Hamt<_, BytesKey> = Hamt::load_with_bit_width(&c, &store, 5)
+        .unwrap()
+        .with_fetch_budget(5);
+    let err = hamt.for_each(|_, _| Ok(())).unwrap_err();
+    assert!(matches!(err, Error::FetchBudgetExceeded), "{:?}", err);
+
+    // The root is loaded up-front, but any other node needs to be fetched.
+    let hamt: Hamt<_, BytesKey> = Hamt::load_with_bit_width(&c, &store, 5)
+        .unwrap()
+        .with_fetch_budget(0);
+    let err = hamt.get(&tstring(1)).unwrap_err();
+    assert!(matches!(err, Error::FetchBudgetExceeded), "{:?}", err);
+
+    // The budget applies per operation, and cached nodes don't count against it.
+    let mut hamt: Hamt<_, BytesKey> = Hamt::load_with_bit_width(&c, &store, 5)
+        .unwrap()
+        .with_fetch_budget(3);
+    for i in 0..200 {
+        assert_eq!(hamt.get(&tstring(i)).unwrap(), Some(&tstring(i)));
+    }
+    hamt.set(tstring(1), tstring("foo")).unwrap();
+    let mut count = 0;
+    hamt.for_each(|_, _| {
+        count += 1;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(count, 200);
+}
+
+#[test]
+fn hash_code() {
+    let store = MemoryBlockstore::default();
+
+    let conf = Config::default()
+        .with_bit_width(2)
+        .with_hash_code(Code::Blake2b512);
+    let mut hamt: Hamt<_, BytesKey> = Hamt::new_with_config(&store, conf.clone());
+    for i in 0..50 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+    assert_eq!(c.hash().code(), u64::from(Code::Blake2b512));
+
+    // Child nodes use the configured hash as well.
+    let hamt: Hamt<_, BytesKey> = Hamt::load_with_config(&c, &store, conf).unwrap();
+    let mut links = 0;
+    for entry in hamt.iter_with_location() {
+        let node_cid = entry.unwrap().2.node_cid.unwrap();
+        assert_eq!(node_cid.hash().code(), u64::from(Code::Blake2b512));
+        links += 1;
+    }
+    assert_eq!(links, 50);
+}
+
+#[test]
+fn bit_width_mismatch() {
+    let store = MemoryBlockstore::default();
+
+    let mut hamt: Hamt<_, BytesKey> = Hamt::new_with_bit_width(&store, 8);
+    for i in 0..100 {
+        hamt.set(tstring(i), tstring(i)).unwrap();
+    }
+    let c = hamt.flush().unwrap();
+
+    let err = Hamt::<_, BytesKey>::load_with_bit_width(&c, &store, 5).unwrap_err();
+    assert!(matches!(err, Error::BitWidthMismatch(5)), "{:?}", err);
+
+    let mut other: Hamt<_, BytesKey> = Hamt::new_with_bit_width(&store, 5);
+    let err = other.set_root(&c).unwrap_err();
+    assert!(matches!(err, Error::BitWidthMismatch(5)), "{:?}", err);
+    assert!(other.is_empty());
+
+    let loaded: Hamt<_, BytesKey> = Hamt::load_with_bit_width(&c, &store, 8).unwrap();
+    assert_eq!(loaded.get(&tstring(1)).unwrap(), Some(&tstring(1)));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn flush_parallel() {
+    use std::sync::Mutex;
+
+    /// A thread-safe blockstore, as flushing in parallel requires.
+    #[derive(Default)]
+    struct SyncBlockstore(Mutex<HashMap<Cid, Vec<u8>>>);
+
+    impl Blockstore for SyncBlockstore {
+        fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().unwrap().get(k).cloned())
+        }
+
+        fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+            self.0.lock().unwrap().insert(*k, block.into());
+            Ok(())
+        }
+    }
+
+    let mem = MemoryBlockstore::default();
+    let store = SyncBlockstore::default();
+    let mut serial: Hamt<_, _> = Hamt::new_with_bit_width(&mem, 5);
+    let mut parallel: Hamt<_, _> = Hamt::new_with_bit_width(&store, 5);
+    for i in 0..1000 {
+        serial.set(tstring(i), i).unwrap();
+        parallel.set(tstring(i), i).unwrap();
+    }
+
+    let c = parallel.flush_parallel().unwrap();
+    assert_eq!(c, serial.flush().unwrap());
+
+    // Only the modified subtrees are written again.
+    parallel.set(tstring(0), 1).unwrap();
+    serial.set(tstring(0), 1).unwrap();
+    assert_eq!(parallel.flush_parallel().unwrap(), serial.flush().unwrap());
+
+    // All the nodes made it to the store.
+    let reloaded: Hamt<_, usize> = Hamt::load_with_bit_width(&c, &store, 5).unwrap();
+    let mut count = 0;
+    reloaded
+        .for_each(|_, _| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(count, 1000);
+}
+
 #[test]
 #[cfg(feature = "identity")]
 fn canonical_structure() {
@@ -574,10 +1515,7 @@ fn test_reduced_root_size(factory1: HamtFactory, factory2: HamtFactory) {
 #[test]
 fn min_data_depth_reduces_root_size() {
     let mk_factory = |min_data_depth| HamtFactory {
-        conf: Config {
-            min_data_depth,
-            ..Default::default()
-        },
+        conf: Config::default().with_min_data_depth(min_data_depth),
     };
 
     let factory1 = mk_factory(0);
@@ -589,10 +1527,7 @@ fn min_data_depth_reduces_root_size() {
 #[test]
 fn max_array_width_reduces_root_size() {
     let mk_factory = |max_array_width| HamtFactory {
-        conf: Config {
-            max_array_width,
-            ..Default::default()
-        },
+        conf: Config::default().with_max_array_width(max_array_width),
     };
 
     let factory1 = mk_factory(3);
@@ -768,6 +1703,94 @@ mod test_default {
         super::set_with_no_effect_does_not_put(HamtFactory::default(), Some(stats), cids);
     }
 
+    #[test]
+    fn clear() {
+        super::clear(HamtFactory::default())
+    }
+
+    #[test]
+    fn put_if_changed() {
+        super::put_if_changed(HamtFactory::default())
+    }
+
+    #[test]
+    fn modify() {
+        super::modify(HamtFactory::default())
+    }
+
+    #[test]
+    fn contains_all_any() {
+        super::contains_all_any(HamtFactory::default())
+    }
+
+    #[test]
+    fn get_many() {
+        super::get_many(HamtFactory::default())
+    }
+
+    #[test]
+    fn delete_many() {
+        super::delete_many(HamtFactory::default())
+    }
+
+    #[test]
+    fn limits() {
+        super::limits(HamtFactory::default())
+    }
+
+    #[test]
+    fn count() {
+        super::count(HamtFactory::default())
+    }
+
+    #[test]
+    fn clone_diverges() {
+        super::clone_diverges(HamtFactory::default())
+    }
+
+    #[test]
+    fn retain() {
+        super::retain(HamtFactory::default())
+    }
+    #[test]
+    fn root_cid() {
+        super::root_cid(HamtFactory::default())
+    }
+    #[cfg(feature = "car")]
+    #[test]
+    fn write_car() {
+        super::write_car(HamtFactory::default())
+    }
+    #[test]
+    fn clone_shares_nodes() {
+        super::clone_shares_nodes(HamtFactory::default())
+    }
+
+    #[test]
+    fn diff() {
+        super::diff(HamtFactory::default())
+    }
+
+    #[test]
+    fn flush_writes_dirty_nodes_only() {
+        super::flush_writes_dirty_nodes_only(HamtFactory::default())
+    }
+
+    #[test]
+    fn canonical_bytes() {
+        super::canonical_bytes(HamtFactory::default())
+    }
+
+    #[test]
+    fn flush_repeatedly() {
+        super::flush_repeatedly(HamtFactory::default())
+    }
+
+    #[test]
+    fn subtree() {
+        super::subtree(HamtFactory::default())
+    }
+
     #[test]
     fn delete() {
         #[rustfmt::skip]
@@ -823,6 +1846,26 @@ mod test_default {
         super::for_each(HamtFactory::default(), Some(stats), cids);
     }
 
+    #[test]
+    fn stats() {
+        super::stats(HamtFactory::default())
+    }
+
+    #[test]
+    fn iter() {
+        super::iter(HamtFactory::default())
+    }
+
+    #[test]
+    fn iter_from() {
+        super::iter_from(HamtFactory::default())
+    }
+
+    #[test]
+    fn iter_with_location() {
+        super::iter_with_location(HamtFactory::default())
+    }
+
     #[test]
     fn clean_child_ordering() {
         #[rustfmt::skip]
@@ -851,12 +1894,9 @@ mod test_default {
 /// ```text
 /// test_hamt_mod!(test_extension, || {
 ///   HamtFactory {
-///       conf: Config {
-///           use_extensions: true,
-///           bit_width: 2,
-///           min_data_depth: 1,
-///           data_in_leaves_only: false,
-///       },
+///       conf: Config::default()
+///           .with_bit_width(2)
+///           .with_min_data_depth(1),
 ///   }
 /// });
 /// ```
@@ -888,6 +1928,94 @@ macro_rules! test_hamt_mod {
                 super::set_with_no_effect_does_not_put($factory, None, CidChecker::empty())
             }
 
+            #[test]
+            fn clear() {
+                super::clear($factory)
+            }
+
+            #[test]
+            fn put_if_changed() {
+                super::put_if_changed($factory)
+            }
+
+            #[test]
+            fn modify() {
+                super::modify($factory)
+            }
+
+            #[test]
+            fn contains_all_any() {
+                super::contains_all_any($factory)
+            }
+
+            #[test]
+            fn get_many() {
+                super::get_many($factory)
+            }
+
+            #[test]
+            fn delete_many() {
+                super::delete_many($factory)
+            }
+
+            #[test]
+            fn limits() {
+                super::limits($factory)
+            }
+
+            #[test]
+            fn count() {
+                super::count($factory)
+            }
+
+            #[test]
+            fn clone_diverges() {
+                super::clone_diverges($factory)
+            }
+
+            #[test]
+            fn retain() {
+                super::retain($factory)
+            }
+            #[test]
+            fn root_cid() {
+                super::root_cid($factory)
+            }
+            #[cfg(feature = "car")]
+            #[test]
+            fn write_car() {
+                super::write_car($factory)
+            }
+            #[test]
+            fn clone_shares_nodes() {
+                super::clone_shares_nodes($factory)
+            }
+
+            #[test]
+            fn diff() {
+                super::diff($factory)
+            }
+
+            #[test]
+            fn flush_writes_dirty_nodes_only() {
+                super::flush_writes_dirty_nodes_only($factory)
+            }
+
+            #[test]
+            fn canonical_bytes() {
+                super::canonical_bytes($factory)
+            }
+
+            #[test]
+            fn flush_repeatedly() {
+                super::flush_repeatedly($factory)
+            }
+
+            #[test]
+            fn subtree() {
+                super::subtree($factory)
+            }
+
             #[test]
             fn delete() {
                 super::delete($factory, None, CidChecker::empty())
@@ -913,6 +2041,26 @@ macro_rules! test_hamt_mod {
                 super::for_each($factory, None, CidChecker::empty())
             }
 
+            #[test]
+            fn stats() {
+                super::stats($factory)
+            }
+
+            #[test]
+            fn iter() {
+                super::iter($factory)
+            }
+
+            #[test]
+            fn iter_from() {
+                super::iter_from($factory)
+            }
+
+            #[test]
+            fn iter_with_location() {
+                super::iter_with_location($factory)
+            }
+
             #[test]
             fn clean_child_ordering() {
                 super::clean_child_ordering($factory, None, CidChecker::empty())
@@ -937,21 +2085,19 @@ macro_rules! test_hamt_mod {
 test_hamt_mod!(
     test_binary_tree,
     HamtFactory {
-        conf: Config {
-            bit_width: 1,
-            min_data_depth: 0,
-            max_array_width: 3
-        },
+        conf: Config::default()
+            .with_bit_width(1)
+            .with_min_data_depth(0)
+            .with_max_array_width(3),
     }
 );
 
 test_hamt_mod!(
     test_min_data_depth,
     HamtFactory {
-        conf: Config {
-            bit_width: 4,
-            min_data_depth: 2,
-            max_array_width: 1
-        },
+        conf: Config::default()
+            .with_bit_width(4)
+            .with_min_data_depth(2)
+            .with_max_array_width(1),
     }
 );
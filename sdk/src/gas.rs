@@ -9,6 +9,7 @@ pub fn charge(name: &str, compute: u64) {
         .expect("failed to charge gas")
 }
 
+/// Returns the gas remaining for the current message, in whole gas units.
 pub fn available() -> u64 {
     unsafe { sys::gas::available() }.expect("failed to check available gas")
 }
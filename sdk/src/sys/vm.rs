@@ -20,7 +20,8 @@ super::fvm_syscalls! {
     ///   code][fvm_shared::error::ExitCode::FIRST_USER_EXIT_CODE], it will be replaced with
     ///   [`SYS_ILLEGAL_EXIT_CODE`][fvm_shared::error::ExitCode::SYS_ILLEGAL_EXIT_CODE].
     /// - `blk_id` is the optional data block id; it should be 0 if there are no data attached to
-    ///   this exit.
+    ///   this exit. If it isn't a valid block id, the exit code is replaced with
+    ///   [`SYS_ILLEGAL_EXIT_CODE`][fvm_shared::error::ExitCode::SYS_ILLEGAL_EXIT_CODE].
     /// - `message_off` and `message_len` specify the offset and length (in wasm memory) of an
     ///   optional debug message associated with this abort. These parameters may be null/0 and will
     ///   be ignored if invalid.
@@ -40,6 +40,6 @@ pub struct StateRoot {
 }
 
 /// Empty state tree information. This is serialized as an array for future proofing.
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct StateInfo0([(); 0]);
@@ -365,6 +365,10 @@ where
     fn append_event(&mut self, evt: StampedEvent) {
         self.0.append_event(evt)
     }
+
+    fn record_block_write(&mut self, actor: ActorID, size: u64) {
+        self.0.record_block_write(actor, size)
+    }
 }
 
 /// A kernel for intercepting syscalls.
@@ -492,6 +496,10 @@ where
     fn block_stat(&self, id: BlockId) -> Result<BlockStat> {
         self.0.block_stat(id)
     }
+
+    fn block_exists(&self, id: BlockId) -> bool {
+        self.0.block_exists(id)
+    }
 }
 
 impl<M, C, K> CircSupplyOps for TestKernel<K>
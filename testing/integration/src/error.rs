@@ -1,10 +1,12 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use cid::Cid;
+use fvm_shared::address::Address;
+use fvm_shared::error::ExitCode;
 
 #[derive(thiserror::Error, Debug)]
 /// Util errors for the intergration test framework.
-pub(crate) enum Error {
+pub enum Error {
     #[error("could not find manifest information for cid: {0}")]
     NoManifestInformation(Cid),
     #[error("could not load builtin manifest")]
@@ -15,4 +17,23 @@ pub(crate) enum Error {
     FailedToSetActor(String),
     #[error("failed to flush tree")]
     FailedToFlushTree,
+    #[error("state root changed from {0} to {1}")]
+    StateRootChanged(Cid, Cid),
+    #[error("expected exit code {0}, got {1}")]
+    UnexpectedExitCode(ExitCode, ExitCode),
+    #[error("failure message does not contain {0:?}: {1}")]
+    UnexpectedAbortMessage(String, String),
+    #[error("message {0} diverged: expected state root {1}, got {2}")]
+    ReplayDiverged(usize, Cid, Cid),
+    #[error("expected state root {0}, got {1}")]
+    UnexpectedStateRoot(Cid, Cid),
+    /// A message sent by the tester failed. `receiver` is the actor which aborted first, i.e.,
+    /// the deepest actor in the backtrace, or the receiver of the message if it failed before
+    /// reaching any actor.
+    #[error("actor {receiver} aborted with exit code {code}: {message}")]
+    ActorAborted {
+        receiver: Address,
+        code: ExitCode,
+        message: String,
+    },
 }
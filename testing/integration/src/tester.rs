@@ -1,29 +1,38 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use anyhow::{anyhow, Context, Result};
+use bls_signatures::Serialize as _;
 use cid::Cid;
 use fvm::call_manager::DefaultCallManager;
 use fvm::engine::EnginePool;
-use fvm::executor::DefaultExecutor;
+use fvm::executor::{ApplyFailure, ApplyKind, DefaultExecutor, Executor};
 use fvm::externs::Externs;
 use fvm::machine::{DefaultMachine, Machine, MachineContext, NetworkConfig};
 use fvm::state_tree::{ActorState, StateTree};
+use fvm::trace::GasTraceEntry;
 use fvm::{init_actor, system_actor, DefaultKernel};
 use fvm_ipld_blockstore::{Block, Blockstore};
-use fvm_ipld_encoding::{ser, CborStore};
+use fvm_ipld_encoding::{de, ser, to_vec, CborStore, RawBytes};
 use fvm_shared::address::{Address, Protocol};
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
 use fvm_shared::state::StateTreeVersion;
 use fvm_shared::version::NetworkVersion;
-use fvm_shared::{ActorID, IPLD_RAW};
+use fvm_shared::{ActorID, MethodNum, IPLD_RAW};
 use lazy_static::lazy_static;
 use libsecp256k1::{PublicKey, SecretKey};
 use multihash::Code;
 
 use crate::builtin::{fetch_builtin_code_cid, set_eam_actor, set_init_actor, set_sys_actor};
-use crate::error::Error::{FailedToFlushTree, NoManifestInformation};
+use crate::error::Error::{
+    ActorAborted, FailedToFlushTree, NoManifestInformation, ReplayDiverged, StateRootChanged,
+    UnexpectedAbortMessage, UnexpectedExitCode, UnexpectedStateRoot,
+};
 
 const DEFAULT_BASE_FEE: u64 = 100;
+const DEFAULT_GAS_LIMIT: i64 = 1_000_000_000;
 
 lazy_static! {
     pub static ref INITIAL_ACCOUNT_BALANCE: TokenAmount = TokenAmount::from_atto(10000);
@@ -36,6 +45,42 @@ pub type IntegrationExecutor<B, E> =
 
 pub type Account = (ActorID, Address);
 
+/// Outcome of a scenario run against a single network version by [`Tester::run_matrix`].
+#[derive(Debug)]
+pub struct ScenarioResult<T> {
+    pub nv: NetworkVersion,
+    pub outcome: Result<T>,
+}
+
+/// A message to be applied by [`Tester::replay`], along with how it was originally applied.
+#[derive(Debug, Clone)]
+pub struct PreparedMessage {
+    pub message: Message,
+    pub apply_kind: ApplyKind,
+    /// Length of the message as it was included on chain, used for gas accounting.
+    pub raw_length: usize,
+    /// State root expected right after applying this message, if known.
+    pub expected_root: Option<Cid>,
+}
+
+impl PreparedMessage {
+    /// Prepares an explicit message, computing its raw length from its serialized form.
+    pub fn explicit(message: Message) -> Result<Self> {
+        Ok(PreparedMessage {
+            raw_length: to_vec(&message)?.len(),
+            message,
+            apply_kind: ApplyKind::Explicit,
+            expected_root: None,
+        })
+    }
+
+    /// Sets the state root expected right after applying this message.
+    pub fn with_expected_root(mut self, root: Cid) -> Self {
+        self.expected_root = Some(root);
+        self
+    }
+}
+
 pub struct Tester<B: Blockstore + 'static, E: Externs + 'static> {
     // Network version used in the test
     nv: NetworkVersion,
@@ -47,10 +92,18 @@ pub struct Tester<B: Blockstore + 'static, E: Externs + 'static> {
     placeholder_code_cid: Cid,
     // Custom code cid deployed by developer
     code_cids: Vec<Cid>,
+    // Base fee set in the MachineContext
+    base_fee: TokenAmount,
+    // Circulating supply set in the MachineContext
+    circ_supply: TokenAmount,
+    // Epoch at which the Machine is instantiated
+    epoch: ChainEpoch,
     // Executor used to interact with deployed actors.
     pub executor: Option<IntegrationExecutor<B, E>>,
     // State tree constructed before instantiating the Machine
     pub state_tree: Option<StateTree<B>>,
+    // Gas trace of the last message applied by `call` or `assert_abort`
+    last_trace: Vec<GasTraceEntry>,
 }
 
 impl<B, E> Tester<B, E>
@@ -89,12 +142,44 @@ where
             builtin_actors,
             executor: None,
             code_cids: vec![],
+            base_fee: TokenAmount::from_atto(DEFAULT_BASE_FEE),
+            circ_supply: fvm_shared::TOTAL_FILECOIN.clone(),
+            epoch: 0,
             state_tree: Some(state_tree),
             accounts_code_cid,
             placeholder_code_cid,
+            last_trace: vec![],
         })
     }
 
+    /// Runs `scenario` once per network version, each time on a fresh tester built from the
+    /// corresponding builtin-actors bundle, and collects the outcomes for comparison.
+    ///
+    /// Every tester gets its own clone of `blockstore`, which must already contain all the
+    /// bundles. Only failures to build a tester are returned as errors; the scenario's own errors
+    /// are recorded in the corresponding [`ScenarioResult`].
+    pub fn run_matrix<F, T>(
+        stv: StateTreeVersion,
+        bundle_per_nv: &[(NetworkVersion, Cid)],
+        blockstore: &B,
+        scenario: F,
+    ) -> Result<Vec<ScenarioResult<T>>>
+    where
+        B: Clone,
+        F: Fn(&mut Self) -> Result<T>,
+    {
+        bundle_per_nv
+            .iter()
+            .map(|&(nv, builtin_actors)| {
+                let mut tester = Self::new(nv, stv, builtin_actors, blockstore.clone())?;
+                Ok(ScenarioResult {
+                    nv,
+                    outcome: scenario(&mut tester),
+                })
+            })
+            .collect()
+    }
+
     /// Creates new accounts in the testing context
     /// Inserts the specified number of accounts in the state tree, all with 1000 FIL，returning their IDs and Addresses.
     pub fn create_accounts<const N: usize>(&mut self) -> Result<[Account; N]> {
@@ -110,6 +195,21 @@ where
         Ok(ret)
     }
 
+    /// Creates new BLS accounts in the testing context
+    /// Inserts the specified number of accounts in the state tree, all with 1000 FIL，returning their IDs and Addresses.
+    pub fn create_bls_accounts<const N: usize>(&mut self) -> Result<[Account; N]> {
+        use rand::SeedableRng;
+
+        let rng = &mut rand_chacha::ChaCha8Rng::seed_from_u64(8);
+
+        let mut ret: [Account; N] = [(0, Address::default()); N];
+        for account in ret.iter_mut().take(N) {
+            let priv_key = bls_signatures::PrivateKey::generate(rng);
+            *account = self.make_bls_account(priv_key, INITIAL_ACCOUNT_BALANCE.clone())?;
+        }
+        Ok(ret)
+    }
+
     pub fn set_account_sequence(&mut self, id: ActorID, new_sequence: u64) -> anyhow::Result<()> {
         let state_tree = self
             .state_tree
@@ -168,6 +268,18 @@ where
         Ok(state_cid)
     }
 
+    /// Get the state of the actor at a given address, or `None` if there is no such actor
+    ///
+    /// The state is read from the machine if it has been instantiated, or from the state tree
+    /// otherwise.
+    pub fn get_actor_state<S: de::DeserializeOwned>(&self, addr: Address) -> Result<Option<S>> {
+        match (self.executor.as_ref(), self.state_tree.as_ref()) {
+            (Some(executor), _) => read_actor_state(executor.state_tree(), &addr),
+            (None, Some(state_tree)) => read_actor_state(state_tree, &addr),
+            (None, None) => Err(anyhow!("no state tree to read from")),
+        }
+    }
+
     /// Set a new at a given address, provided with a given token balance
     /// and returns the CodeCID of the installed actor
     pub fn set_actor_from_bin(
@@ -177,6 +289,30 @@ where
         actor_address: Address,
         balance: TokenAmount,
     ) -> Result<Cid> {
+        let (_, code_cid) = self.install_actor(wasm_bin, state_cid, actor_address, balance)?;
+        Ok(code_cid)
+    }
+
+    /// Set a new actor with the given state at a given address, provided with a given token
+    /// balance, and returns the ID and the CodeCID of the installed actor
+    pub fn install_actor_with_state<S: ser::Serialize>(
+        &mut self,
+        wasm_bin: &[u8],
+        state: &S,
+        actor_address: Address,
+        balance: TokenAmount,
+    ) -> Result<(ActorID, Cid)> {
+        let state_cid = self.set_state(state)?;
+        self.install_actor(wasm_bin, state_cid, actor_address, balance)
+    }
+
+    fn install_actor(
+        &mut self,
+        wasm_bin: &[u8],
+        state_cid: Cid,
+        actor_address: Address,
+        balance: TokenAmount,
+    ) -> Result<(ActorID, Cid)> {
         // Register actor address (unless it's an ID address)
         let actor_id = match actor_address.id() {
             Ok(id) => id,
@@ -213,7 +349,92 @@ where
             .set_actor(actor_id, actor_state)
             .map_err(anyhow::Error::from)?;
 
-        Ok(code_cid)
+        Ok((actor_id, code_cid))
+    }
+
+    /// Sets the base fee of the machine, 100 atto by default.
+    ///
+    /// Only takes effect when the machine is instantiated.
+    pub fn set_base_fee(&mut self, fee: TokenAmount) {
+        self.base_fee = fee;
+    }
+
+    /// Sets the circulating supply of the machine, the total FIL supply by default.
+    ///
+    /// Only takes effect when the machine is instantiated.
+    pub fn set_circulating_supply(&mut self, supply: TokenAmount) {
+        self.circ_supply = supply;
+    }
+
+    /// Moves the machine to `epoch`, keeping the current state.
+    ///
+    /// If the machine has already been instantiated, its state tree is flushed and the machine is
+    /// re-instantiated at the new epoch, with the same configuration. Otherwise, the epoch is used
+    /// when the machine gets instantiated.
+    pub fn set_epoch(&mut self, epoch: ChainEpoch) -> Result<()>
+    where
+        E: Clone,
+    {
+        self.epoch = epoch;
+
+        let state_root = match self.executor.as_mut() {
+            Some(executor) => executor.flush().context(FailedToFlushTree)?,
+            None => return Ok(()),
+        };
+        self.reinstantiate_machine(state_root)
+    }
+
+    /// Flushes the state tree (of the machine, if it has been instantiated) and returns the
+    /// current state root, to be restored later with [`Tester::restore`].
+    pub fn checkpoint(&mut self) -> Result<Cid> {
+        self.state_root()
+    }
+
+    /// Resets the state tree (of the machine, if it has been instantiated) to `root`, usually
+    /// obtained from [`Tester::checkpoint`]. Changes made since then are discarded.
+    ///
+    /// The blockstore must still contain all the blocks reachable from `root`, which is the case
+    /// for checkpoints of this tester.
+    pub fn restore(&mut self, root: Cid) -> Result<()>
+    where
+        E: Clone,
+    {
+        if self.executor.is_some() {
+            return self.reinstantiate_machine(root);
+        }
+
+        let state_tree = self
+            .state_tree
+            .take()
+            .ok_or_else(|| anyhow!("no state tree to restore"))?;
+        let state_tree = StateTree::new_from_root(state_tree.into_store(), &root)
+            .map_err(anyhow::Error::from)?;
+        self.state_tree = Some(state_tree);
+        Ok(())
+    }
+
+    /// Re-instantiates the machine on top of `state_root`, at the current epoch, keeping the rest
+    /// of its configuration.
+    fn reinstantiate_machine(&mut self, state_root: Cid) -> Result<()>
+    where
+        E: Clone,
+    {
+        let executor = self
+            .executor
+            .take()
+            .ok_or_else(|| anyhow!("machine not instantiated"))?;
+
+        let mut mc = executor.context().clone();
+        mc.epoch = self.epoch;
+        mc.initial_state_root = state_root;
+
+        let machine = executor
+            .into_machine()
+            .ok_or_else(|| anyhow!("machine poisoned"))?;
+        let externs = machine.externs().clone();
+        let blockstore = machine.into_store().into_inner();
+
+        self.build_executor(&mc, blockstore, externs)
     }
 
     /// Sets the Machine and the Executor in our Tester structure.
@@ -255,17 +476,23 @@ where
         // Custom configuration.
         configure_nc(&mut nc);
 
-        let mut mc = nc.for_epoch(0, 0, state_root);
-        mc.set_base_fee(TokenAmount::from_atto(DEFAULT_BASE_FEE))
+        let mut mc = nc.for_epoch(self.epoch, 0, state_root);
+        mc.set_base_fee(self.base_fee.clone())
+            .set_circulating_supply(self.circ_supply.clone())
             .enable_tracing();
 
         // Custom configuration.
         configure_mc(&mut mc);
 
+        self.build_executor(&mc, blockstore, externs)
+    }
+
+    /// Builds the Machine and the Executor from `mc`, on top of `blockstore`.
+    fn build_executor(&mut self, mc: &MachineContext, blockstore: B, externs: E) -> Result<()> {
         let engine = EnginePool::new_default((&mc.network.clone()).into())?;
         engine.acquire().preload(&blockstore, &self.code_cids)?;
 
-        let machine = DefaultMachine::new(&mc, blockstore, externs)?;
+        let machine = DefaultMachine::new(mc, blockstore, externs)?;
 
         let executor =
             DefaultExecutor::<DefaultKernel<DefaultCallManager<DefaultMachine<B, E>>>>::new(
@@ -277,6 +504,195 @@ where
         Ok(())
     }
 
+    /// Flushes the state tree (of the machine, if it has been instantiated) and returns the
+    /// current state root.
+    pub fn state_root(&mut self) -> Result<Cid> {
+        match (self.executor.as_mut(), self.state_tree.as_mut()) {
+            (Some(executor), _) => executor.flush(),
+            (None, Some(state_tree)) => state_tree.flush().map_err(anyhow::Error::from),
+            (None, None) => Err(anyhow!("no state tree to flush")),
+        }
+        .context(FailedToFlushTree)
+    }
+
+    /// Runs `f` and checks that it left the state root unchanged, returning an error if it
+    /// didn't.
+    ///
+    /// This is useful to make sure that paths which should be free of side effects (e.g.,
+    /// messages failing validation) don't accidentally mutate the state.
+    pub fn assert_root_unchanged_by<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let before = self.state_root()?;
+        f(self)?;
+        let after = self.state_root()?;
+        if before != after {
+            return Err(StateRootChanged(before, after).into());
+        }
+        Ok(())
+    }
+
+    /// Applies `message` as an explicit message, and checks that it failed with `expected_code`
+    /// and a failure message containing `msg_fragment`.
+    ///
+    /// The fragment is looked up in the abort messages of the backtrace, which hold the text the
+    /// VM extracted when the actors exited (decoded as lossy UTF-8), or in the description of the
+    /// pre-validation failure.
+    pub fn assert_abort(
+        &mut self,
+        msg_fragment: &str,
+        expected_code: ExitCode,
+        message: Message,
+    ) -> Result<()> {
+        let raw_length = to_vec(&message)?.len();
+        let ret = self
+            .executor
+            .as_mut()
+            .ok_or_else(|| anyhow!("machine not instantiated"))?
+            .execute_message(message, ApplyKind::Explicit, raw_length)?;
+        self.last_trace = GasTraceEntry::from_exec_trace(&ret.exec_trace);
+
+        let actual = ret.msg_receipt.exit_code;
+        if actual != expected_code {
+            return Err(UnexpectedExitCode(expected_code, actual).into());
+        }
+
+        let matches = match &ret.failure_info {
+            Some(ApplyFailure::MessageBacktrace(bt)) => bt
+                .frames
+                .iter()
+                .any(|frame| frame.message.contains(msg_fragment)),
+            Some(ApplyFailure::PreValidation(msg)) => msg.contains(msg_fragment),
+            None => false,
+        };
+        if !matches {
+            let failure = ret
+                .failure_info
+                .map(|f| f.to_string())
+                .unwrap_or_else(|| "no failure".to_owned());
+            return Err(UnexpectedAbortMessage(msg_fragment.to_owned(), failure).into());
+        }
+        Ok(())
+    }
+
+    /// Sends an explicit message calling `method` on `to` with `params`, and decodes its return
+    /// value.
+    ///
+    /// The message uses the current sequence of `from`, so calls can be chained without tracking
+    /// it. If the message doesn't exit successfully, an
+    /// [`ActorAborted`](crate::error::Error::ActorAborted) error is returned. An empty return
+    /// value is decoded as CBOR `null`, so methods returning nothing can be called with `R = ()`.
+    pub fn call<P, R>(
+        &mut self,
+        from: Account,
+        to: Address,
+        method: MethodNum,
+        params: &P,
+        value: TokenAmount,
+    ) -> Result<R>
+    where
+        P: ser::Serialize,
+        R: de::DeserializeOwned,
+    {
+        let executor = self
+            .executor
+            .as_mut()
+            .ok_or_else(|| anyhow!("machine not instantiated"))?;
+
+        let sequence = executor
+            .state_tree()
+            .get_actor(from.0)?
+            .ok_or_else(|| anyhow!("sender {} not found", from.1))?
+            .sequence;
+
+        let message = Message {
+            from: from.1,
+            to,
+            sequence,
+            value,
+            method_num: method,
+            params: RawBytes::serialize(params)?,
+            gas_limit: DEFAULT_GAS_LIMIT,
+            ..Message::default()
+        };
+        let raw_length = to_vec(&message)?.len();
+        let ret = executor.execute_message(message, ApplyKind::Explicit, raw_length)?;
+        self.last_trace = GasTraceEntry::from_exec_trace(&ret.exec_trace);
+
+        let exit_code = ret.msg_receipt.exit_code;
+        if !exit_code.is_success() {
+            let err = match ret.failure_info {
+                Some(ApplyFailure::MessageBacktrace(bt)) if !bt.frames.is_empty() => {
+                    let frame = &bt.frames[0];
+                    ActorAborted {
+                        receiver: Address::new_id(frame.source),
+                        code: frame.code,
+                        message: frame.message.clone(),
+                    }
+                }
+                failure => ActorAborted {
+                    receiver: to,
+                    code: exit_code,
+                    message: failure
+                        .map(|f| f.to_string())
+                        .unwrap_or_else(|| "no failure".to_owned()),
+                },
+            };
+            return Err(err.into());
+        }
+
+        let return_data = ret.msg_receipt.return_data;
+        if return_data.is_empty() {
+            Ok(RawBytes::serialize(())?.deserialize()?)
+        } else {
+            Ok(return_data.deserialize()?)
+        }
+    }
+
+    /// Returns the gas trace of the last message applied by [`Tester::call`] or
+    /// [`Tester::assert_abort`]: the gas charged for each operation, along with the boundaries of
+    /// the calls it made.
+    ///
+    /// Unlike the raw execution trace, this doesn't depend on timings, so it can be compared across
+    /// runs, e.g., to check that the gas profile of an actor stays the same. The trace is empty if
+    /// tracing was disabled in the [`MachineContext`].
+    pub fn last_trace(&self) -> &[GasTraceEntry] {
+        &self.last_trace
+    }
+
+    /// Applies `messages` in order and checks that the final state root is `expected_root`.
+    ///
+    /// Messages carrying an expected root are checked right after being applied, so that the
+    /// first diverging message is reported rather than just the final mismatch. Note that this
+    /// flushes the state tree after each of those messages.
+    pub fn replay(&mut self, messages: &[PreparedMessage], expected_root: Cid) -> Result<()> {
+        for (i, prepared) in messages.iter().enumerate() {
+            self.executor
+                .as_mut()
+                .ok_or_else(|| anyhow!("machine not instantiated"))?
+                .execute_message(
+                    prepared.message.clone(),
+                    prepared.apply_kind,
+                    prepared.raw_length,
+                )
+                .with_context(|| format!("failed to apply message {}", i))?;
+
+            if let Some(expected) = prepared.expected_root {
+                let actual = self.state_root()?;
+                if actual != expected {
+                    return Err(ReplayDiverged(i, expected, actual).into());
+                }
+            }
+        }
+
+        let actual = self.state_root()?;
+        if actual != expected_root {
+            return Err(UnexpectedStateRoot(expected_root, actual).into());
+        }
+        Ok(())
+    }
+
     /// Get blockstore
     pub fn blockstore(&self) -> &dyn Blockstore {
         if self.executor.is_some() {
@@ -294,7 +710,29 @@ where
     ) -> Result<Account> {
         let pub_key = PublicKey::from_secret_key(&priv_key);
         let pub_key_addr = Address::new_secp256k1(&pub_key.serialize())?;
+        self.make_account(pub_key_addr, init_balance)
+    }
 
+    /// Put BLS account with specified private key and balance
+    ///
+    /// The private key is a BLS12-381 key, as used by Filecoin BLS signatures. The account's
+    /// address is derived from the (48 bytes, compressed) public key.
+    pub fn make_bls_account(
+        &mut self,
+        priv_key: bls_signatures::PrivateKey,
+        init_balance: TokenAmount,
+    ) -> Result<Account> {
+        let pub_key = priv_key.public_key();
+        let pub_key_addr = Address::new_bls(&pub_key.as_bytes())?;
+        self.make_account(pub_key_addr, init_balance)
+    }
+
+    /// Put account actor with the specified public key address and balance
+    fn make_account(
+        &mut self,
+        pub_key_addr: Address,
+        init_balance: TokenAmount,
+    ) -> Result<Account> {
         let state_tree = self
             .state_tree
             .as_mut()
@@ -320,6 +758,26 @@ where
         Ok((assigned_addr, pub_key_addr))
     }
 }
+/// Reads the state of the actor at `addr` from `state_tree`.
+fn read_actor_state<S: de::DeserializeOwned>(
+    state_tree: &StateTree<impl Blockstore>,
+    addr: &Address,
+) -> Result<Option<S>> {
+    let id = match state_tree.lookup_id(addr)? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let actor = match state_tree.get_actor(id)? {
+        Some(actor) => actor,
+        None => return Ok(None),
+    };
+    let state = state_tree
+        .store()
+        .get_cbor(&actor.state)?
+        .ok_or_else(|| anyhow!("state {} of actor {} not found", actor.state, addr))?;
+    Ok(Some(state))
+}
+
 /// Inserts the WASM code for the actor into the blockstore.
 fn put_wasm_code(blockstore: &impl Blockstore, wasm_binary: &[u8]) -> Result<Cid> {
     let cid = blockstore.put(
@@ -5,8 +5,10 @@ use bundles::*;
 use fil_address_actor::WASM_BINARY as ADDRESS_BINARY;
 use fvm::executor::{ApplyKind, Executor};
 use fvm_integration_tests::dummy::DummyExterns;
+use fvm_integration_tests::tester::Account;
 use fvm_ipld_blockstore::MemoryBlockstore;
-use fvm_shared::address::Address;
+use fvm_ipld_encoding::CborStore;
+use fvm_shared::address::{Address, Protocol};
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::message::Message;
 use fvm_shared::state::StateTreeVersion;
@@ -64,3 +66,27 @@ fn basic_address_tests() {
         );
     }
 }
+
+#[test]
+fn bls_accounts() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let accounts: [Account; 2] = tester.create_bls_accounts().unwrap();
+    assert_ne!(accounts[0], accounts[1]);
+
+    let state_tree = tester.state_tree.as_ref().unwrap();
+    for (id, address) in accounts {
+        assert_eq!(address.protocol(), Protocol::BLS);
+        assert_eq!(state_tree.lookup_id(&address).unwrap(), Some(id));
+
+        let actor = state_tree.get_actor(id).unwrap().unwrap();
+        let state: fvm::account_actor::State =
+            state_tree.store().get_cbor(&actor.state).unwrap().unwrap();
+        assert_eq!(state.address, address);
+    }
+}
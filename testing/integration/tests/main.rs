@@ -12,8 +12,11 @@ use fil_ipld_actor::WASM_BINARY as IPLD_BINARY;
 use fil_stack_overflow_actor::WASM_BINARY as OVERFLOW_BINARY;
 use fil_syscall_actor::WASM_BINARY as SYSCALL_BINARY;
 use fvm::executor::{ApplyKind, Executor, ThreadedExecutor};
+use fvm::machine::Machine;
+use fvm_integration_tests::bundle;
 use fvm_integration_tests::dummy::DummyExterns;
-use fvm_integration_tests::tester::{Account, IntegrationExecutor};
+use fvm_integration_tests::error::Error;
+use fvm_integration_tests::tester::{Account, IntegrationExecutor, PreparedMessage, Tester};
 use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
 use fvm_ipld_encoding::tuple::*;
 use fvm_ipld_encoding::RawBytes;
@@ -81,6 +84,311 @@ fn hello_world() {
     assert_eq!(res.msg_receipt.exit_code.value(), 16)
 }
 
+#[test]
+fn rejected_message_leaves_root_unchanged() {
+    // Instantiate tester
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+    let wasm_bin = HELLO_BINARY.unwrap();
+
+    // Set actor state
+    let actor_state = State::default();
+    let state_cid = tester.set_state(&actor_state).unwrap();
+
+    // Set actor
+    let actor_address = Address::new_id(10000);
+
+    tester
+        .set_actor_from_bin(wasm_bin, state_cid, actor_address, TokenAmount::zero())
+        .unwrap();
+
+    // Instantiate machine
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    // A message with the wrong sequence is rejected before anything is charged.
+    let message = Message {
+        from: sender[0].1,
+        to: actor_address,
+        gas_limit: 1000000000,
+        method_num: 1,
+        sequence: 10,
+        ..Message::default()
+    };
+
+    tester
+        .assert_root_unchanged_by(|tester| {
+            let res = tester.executor.as_mut().unwrap().execute_message(
+                message,
+                ApplyKind::Explicit,
+                100,
+            )?;
+            assert_eq!(
+                res.msg_receipt.exit_code,
+                ExitCode::SYS_SENDER_STATE_INVALID
+            );
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn hello_world_abort() {
+    // Instantiate tester
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+    let wasm_bin = HELLO_BINARY.unwrap();
+
+    // Set actor state
+    let actor_state = State::default();
+    let state_cid = tester.set_state(&actor_state).unwrap();
+
+    // Set actor
+    let actor_address = Address::new_id(10000);
+
+    tester
+        .set_actor_from_bin(wasm_bin, state_cid, actor_address, TokenAmount::zero())
+        .unwrap();
+
+    // Instantiate machine
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    let message = Message {
+        from: sender[0].1,
+        to: actor_address,
+        gas_limit: 1000000000,
+        method_num: 1,
+        ..Message::default()
+    };
+
+    // Wrong message fragment.
+    assert!(tester
+        .assert_abort("other abort", ExitCode::new(16), message.clone())
+        .is_err());
+
+    // Wrong exit code.
+    let message = Message {
+        sequence: 1,
+        ..message
+    };
+    assert!(tester
+        .assert_abort("sample abort", ExitCode::USR_ILLEGAL_STATE, message.clone())
+        .is_err());
+
+    let message = Message {
+        sequence: 2,
+        ..message
+    };
+    tester
+        .assert_abort("sample abort", ExitCode::new(16), message)
+        .unwrap();
+}
+
+#[test]
+fn hello_world_call_aborted() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let [sender] = tester.create_accounts().unwrap();
+
+    let state_cid = tester.set_state(&State::default()).unwrap();
+    let actor_address = Address::new_id(10000);
+    tester
+        .set_actor_from_bin(
+            HELLO_BINARY.unwrap(),
+            state_cid,
+            actor_address,
+            TokenAmount::zero(),
+        )
+        .unwrap();
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    let err = tester
+        .call::<_, ()>(sender, actor_address, 1, &(), TokenAmount::zero())
+        .unwrap_err();
+    match err.downcast_ref::<Error>() {
+        Some(Error::ActorAborted {
+            receiver,
+            code,
+            message,
+        }) => {
+            assert_eq!(*receiver, actor_address);
+            assert_eq!(*code, ExitCode::new(16));
+            assert!(message.contains("sample abort"), "{}", message);
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn hello_world_replay() {
+    let setup = || {
+        let mut tester = new_tester(
+            NetworkVersion::V18,
+            StateTreeVersion::V5,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+        let state_cid = tester.set_state(&State::default()).unwrap();
+        tester
+            .set_actor_from_bin(
+                HELLO_BINARY.unwrap(),
+                state_cid,
+                Address::new_id(10000),
+                TokenAmount::zero(),
+            )
+            .unwrap();
+        tester.instantiate_machine(DummyExterns).unwrap();
+        (tester, sender[0].1)
+    };
+
+    // Record the messages and the roots they lead to.
+    let (mut recorder, sender) = setup();
+    let mut messages = Vec::new();
+    for sequence in 0..3 {
+        let message = Message {
+            from: sender,
+            to: Address::new_id(10000),
+            gas_limit: 1000000000,
+            method_num: 2,
+            sequence,
+            ..Message::default()
+        };
+        let prepared = PreparedMessage::explicit(message).unwrap();
+        recorder
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(
+                prepared.message.clone(),
+                prepared.apply_kind,
+                prepared.raw_length,
+            )
+            .unwrap();
+        messages.push(prepared.with_expected_root(recorder.state_root().unwrap()));
+    }
+    let final_root = recorder.state_root().unwrap();
+
+    let (mut tester, _) = setup();
+    tester.replay(&messages, final_root).unwrap();
+
+    // A wrong intermediate root is reported at the diverging message.
+    let (mut tester, _) = setup();
+    messages[1].expected_root = Some(final_root);
+    let err = tester.replay(&messages, final_root).unwrap_err();
+    assert!(err.to_string().starts_with("message 1 diverged"));
+}
+
+#[test]
+fn hello_world_matrix() {
+    let store = MemoryBlockstore::default();
+    let root = bundle::import_bundle(&store, actors_v10::BUNDLE_CAR).unwrap();
+
+    let results = Tester::<_, DummyExterns>::run_matrix(
+        StateTreeVersion::V5,
+        &[(NetworkVersion::V18, root)],
+        &store,
+        |tester| {
+            let sender: [Account; 1] = tester.create_accounts()?;
+
+            let state_cid = tester.set_state(&State::default())?;
+            let actor_address = Address::new_id(10000);
+            tester.set_actor_from_bin(
+                HELLO_BINARY.unwrap(),
+                state_cid,
+                actor_address,
+                TokenAmount::zero(),
+            )?;
+
+            tester.instantiate_machine(DummyExterns)?;
+
+            let message = Message {
+                from: sender[0].1,
+                to: actor_address,
+                gas_limit: 1000000000,
+                method_num: 1,
+                ..Message::default()
+            };
+
+            let res = tester.executor.as_mut().unwrap().execute_message(
+                message,
+                ApplyKind::Explicit,
+                100,
+            )?;
+            Ok(res.msg_receipt.exit_code)
+        },
+    )
+    .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].nv, NetworkVersion::V18);
+    assert_eq!(results[0].outcome.as_ref().unwrap().value(), 16);
+}
+
+#[test]
+fn base_fee_and_circulating_supply() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    tester.set_base_fee(TokenAmount::from_atto(42));
+    tester.set_circulating_supply(TokenAmount::from_whole(1000));
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    let context = tester.executor.as_ref().unwrap().context();
+    assert_eq!(context.base_fee, TokenAmount::from_atto(42));
+    assert_eq!(context.circ_supply, TokenAmount::from_whole(1000));
+}
+
+#[test]
+fn set_epoch() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let [(sender_id, _)] = tester.create_accounts().unwrap();
+    tester.set_base_fee(TokenAmount::from_atto(42));
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let root = tester.state_root().unwrap();
+
+    tester.set_epoch(10).unwrap();
+
+    let executor = tester.executor.as_ref().unwrap();
+    assert_eq!(executor.context().epoch, 10);
+    assert_eq!(executor.context().base_fee, TokenAmount::from_atto(42));
+    assert!(executor
+        .state_tree()
+        .get_actor(sender_id)
+        .unwrap()
+        .is_some());
+    assert_eq!(tester.state_root().unwrap(), root);
+}
+
 #[test]
 fn ipld() {
     // Instantiate tester
@@ -492,6 +800,48 @@ fn no_memory() {
     );
 }
 
+#[test]
+fn exit_invalid_return_block() {
+    test_exitcode(
+        r#"(module
+             (type (;0;) (func (param i32 i32 i32 i32) (result i32)))
+             (import "vm" "exit" (func $fvm_sdk::sys::vm::exit::syscall (type 0)))
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (i32.const 16)
+               (i32.const 42)
+               (i32.const 0)
+               (i32.const 0)
+               (call $fvm_sdk::sys::vm::exit::syscall)
+               unreachable))"#,
+        ExitCode::SYS_ILLEGAL_EXIT_CODE,
+    );
+}
+
+#[test]
+fn gas_available_decreases() {
+    test_exitcode(
+        r#"(module
+             (type (;0;) (func (param i32) (result i32)))
+             (import "gas" "available" (func $fvm_sdk::sys::gas::available::syscall (type 0)))
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (local $i i32)
+               (drop (call $fvm_sdk::sys::gas::available::syscall (i32.const 0)))
+               ;; Do some work.
+               (local.set $i (i32.const 1000))
+               (loop $work
+                 (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+                 (br_if $work (local.get $i)))
+               (drop (call $fvm_sdk::sys::gas::available::syscall (i32.const 8)))
+               ;; Trap unless less gas is available than the first time.
+               (if (i64.ge_u (i64.load (i32.const 8)) (i64.load (i32.const 0)))
+                 (then unreachable))
+               (i32.const 0)))"#,
+        ExitCode::OK,
+    );
+}
+
 #[test]
 fn backtraces() {
     // Note: this test **does not actually assert anything**, but it's useful to
@@ -0,0 +1,187 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use fil_integer_overflow_actor::WASM_BINARY as OVERFLOW_BINARY;
+use fvm::trace::GasTraceEntry;
+use fvm_integration_tests::dummy::DummyExterns;
+use fvm_integration_tests::tester::{Account, Tester};
+use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_ipld_encoding::tuple::*;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use num_traits::Zero;
+
+mod bundles;
+use bundles::*;
+
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, Default)]
+pub struct State {
+    pub value: i64,
+}
+
+// Instantiates a tester with the integer overflow actor, whose state is a single integer
+fn instantiate_tester() -> (Account, Tester<MemoryBlockstore, DummyExterns>, Address) {
+    // Instantiate tester
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+    // Set actor state
+    let actor_state = State::default();
+    let state_cid = tester.set_state(&actor_state).unwrap();
+
+    // Set actor
+    let actor_address = Address::new_id(10000);
+
+    // Get wasm bin
+    let wasm_bin = OVERFLOW_BINARY.unwrap();
+
+    tester
+        .set_actor_from_bin(wasm_bin, state_cid, actor_address, TokenAmount::zero())
+        .unwrap();
+
+    (sender[0], tester, actor_address)
+}
+
+#[test]
+fn typed_call() {
+    let (sender, mut tester, actor_address) = instantiate_tester();
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    let x: i64 = 10000000000;
+    tester
+        .call::<_, ()>(sender, actor_address, 1, &x, TokenAmount::zero())
+        .unwrap();
+    let value: i64 = tester
+        .call(sender, actor_address, 3, &(), TokenAmount::zero())
+        .unwrap();
+    assert_eq!(value, x);
+}
+
+#[test]
+fn read_actor_state() {
+    let (sender, mut tester, actor_address) = instantiate_tester();
+
+    let state: State = tester.get_actor_state(actor_address).unwrap().unwrap();
+    assert_eq!(state.value, 0);
+    assert!(tester
+        .get_actor_state::<State>(Address::new_id(10001))
+        .unwrap()
+        .is_none());
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    let x: i64 = 10000000000;
+    tester
+        .call::<_, ()>(sender, actor_address, 1, &x, TokenAmount::zero())
+        .unwrap();
+    let state: State = tester.get_actor_state(actor_address).unwrap().unwrap();
+    assert_eq!(state.value, x);
+}
+
+#[test]
+fn install_actor_with_state() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let actor_address = Address::new_id(10000);
+    let (id, code_cid) = tester
+        .install_actor_with_state(
+            OVERFLOW_BINARY.unwrap(),
+            &State { value: 42 },
+            actor_address,
+            TokenAmount::zero(),
+        )
+        .unwrap();
+    assert_eq!(id, 10000);
+
+    let actor = tester
+        .state_tree
+        .as_ref()
+        .unwrap()
+        .get_actor(id)
+        .unwrap()
+        .unwrap();
+    assert_eq!(actor.code, code_cid);
+
+    let state: State = tester.get_actor_state(actor_address).unwrap().unwrap();
+    assert_eq!(state.value, 42);
+}
+
+#[test]
+fn checkpoint_and_restore() {
+    let (sender, mut tester, actor_address) = instantiate_tester();
+
+    // Before instantiating the machine.
+    let genesis = tester.checkpoint().unwrap();
+    tester
+        .install_actor_with_state(
+            OVERFLOW_BINARY.unwrap(),
+            &State { value: 1 },
+            actor_address,
+            TokenAmount::zero(),
+        )
+        .unwrap();
+    tester.restore(genesis).unwrap();
+    let state: State = tester.get_actor_state(actor_address).unwrap().unwrap();
+    assert_eq!(state.value, 0);
+
+    // After instantiating the machine.
+    tester.instantiate_machine(DummyExterns).unwrap();
+    tester
+        .call::<_, ()>(sender, actor_address, 1, &42i64, TokenAmount::zero())
+        .unwrap();
+    tester.restore(genesis).unwrap();
+    let state: State = tester.get_actor_state(actor_address).unwrap().unwrap();
+    assert_eq!(state.value, 0);
+
+    // The sender's sequence was restored too, so the call can be replayed.
+    tester
+        .call::<_, ()>(sender, actor_address, 1, &42i64, TokenAmount::zero())
+        .unwrap();
+    let state: State = tester.get_actor_state(actor_address).unwrap().unwrap();
+    assert_eq!(state.value, 42);
+}
+
+#[test]
+fn gas_trace_is_deterministic() {
+    let (sender, mut tester, actor_address) = instantiate_tester();
+    tester.instantiate_machine(DummyExterns).unwrap();
+    assert!(tester.last_trace().is_empty());
+
+    let genesis = tester.checkpoint().unwrap();
+    tester
+        .call::<_, ()>(sender, actor_address, 1, &42i64, TokenAmount::zero())
+        .unwrap();
+    let trace = tester.last_trace().to_vec();
+    assert!(trace.contains(&GasTraceEntry::Call {
+        from: sender.0,
+        to: actor_address,
+        method: 1,
+    }));
+    assert!(trace.contains(&GasTraceEntry::Return(ExitCode::OK)));
+    assert!(trace.iter().any(
+        |entry| matches!(entry, GasTraceEntry::Charge { name, .. } if name == "OnChainMessage")
+    ));
+    assert!(trace.iter().any(
+        |entry| matches!(entry, GasTraceEntry::Write { actor, size } if *actor == actor_address && *size > 0)
+    ));
+
+    // Replaying the same message from the same state charges exactly the same gas.
+    tester.restore(genesis).unwrap();
+    tester
+        .call::<_, ()>(sender, actor_address, 1, &42i64, TokenAmount::zero())
+        .unwrap();
+    assert_eq!(tester.last_trace(), trace);
+}